@@ -560,6 +560,7 @@ pub struct Request {
     pub authorization: Option<String>,
     pub if_modified_since: Option<String>,
     pub if_none_match: Option<String>,
+    pub cache_control: Option<String>,
 }
 
 impl fmt::Debug for Request {
@@ -571,6 +572,7 @@ impl fmt::Debug for Request {
             .field("authorization", &self.authorization)
             .field("if_modified_since", &self.if_modified_since)
             .field("if_none_match", &self.if_none_match)
+            .field("cache_control", &self.cache_control)
             .finish()
     }
 }
@@ -658,6 +660,7 @@ impl HttpServer {
             // Grab headers we care about.
             let mut if_modified_since = None;
             let mut if_none_match = None;
+            let mut cache_control = None;
             let mut authorization = None;
             let mut content_len = None;
             loop {
@@ -676,6 +679,7 @@ impl HttpServer {
                 match name.as_str() {
                     "if-modified-since" => if_modified_since = Some(value),
                     "if-none-match" => if_none_match = Some(value),
+                    "cache-control" => cache_control = Some(value),
                     "authorization" => authorization = Some(value),
                     "content-length" => content_len = Some(value),
                     _ => {}
@@ -694,6 +698,7 @@ impl HttpServer {
                 authorization,
                 if_modified_since,
                 if_none_match,
+                cache_control,
                 method,
                 url,
                 body,
@@ -1,13 +1,14 @@
 use anyhow::{anyhow, Context as _};
 use cargo::core::shell::Shell;
 use cargo::core::{features, CliUnstable};
-use cargo::{self, drop_print, drop_println, CliResult, Config};
+use cargo::{self, drop_print, drop_println, CargoResult, CliResult, Config};
 use clap::{Arg, ArgMatches};
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::fmt::Write;
+use std::time::Duration;
 
 use super::commands;
 use super::list_commands;
@@ -348,6 +349,10 @@ fn config_configure(
     if let Some(values) = args.get_many::<String>("config") {
         config_args.extend(values.cloned());
     }
+    let network_deadline = args
+        .get_one::<String>("network-deadline")
+        .or(global_args.network_deadline.as_ref());
+    let strict_network = args.flag("strict-network") || global_args.strict_network;
     config.configure(
         verbose,
         quiet,
@@ -359,9 +364,34 @@ fn config_configure(
         &unstable_flags,
         &config_args,
     )?;
+    if let Some(network_deadline) = network_deadline {
+        let dur = parse_network_deadline(network_deadline)?;
+        let deadline = config.creation_time() + dur;
+        config.set_network_deadline(deadline);
+    }
+    if strict_network {
+        config.set_strict_network();
+    }
     Ok(())
 }
 
+/// Parses the value of `--network-deadline`: a whole number of seconds, or of
+/// minutes with an `m` suffix.
+fn parse_network_deadline(s: &str) -> CargoResult<Duration> {
+    let (number, unit) = match s.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (s.strip_suffix('s').unwrap_or(s), 1),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => Ok(Duration::from_secs(n.saturating_mul(unit))),
+        Err(_) => anyhow::bail!(
+            "invalid value `{}` for `--network-deadline`, \
+             expected a number of seconds such as `90s`, or minutes such as `2m`",
+            s
+        ),
+    }
+}
+
 fn execute_subcommand(config: &mut Config, cmd: &str, subcommand_args: &ArgMatches) -> CliResult {
     if let Some(exec) = commands::builtin_exec(cmd) {
         return exec(config, subcommand_args);
@@ -385,6 +415,8 @@ struct GlobalArgs {
     frozen: bool,
     locked: bool,
     offline: bool,
+    network_deadline: Option<String>,
+    strict_network: bool,
     unstable_flags: Vec<String>,
     config_args: Vec<String>,
 }
@@ -398,6 +430,8 @@ impl GlobalArgs {
             frozen: args.flag("frozen"),
             locked: args.flag("locked"),
             offline: args.flag("offline"),
+            network_deadline: args.get_one::<String>("network-deadline").cloned(),
+            strict_network: args.flag("strict-network"),
             unstable_flags: args
                 .get_many::<String>("unstable-features")
                 .unwrap_or_default()
@@ -485,6 +519,21 @@ See 'cargo help <command>' for more information on a specific command.\n",
         .arg(flag("frozen", "Require Cargo.lock and cache are up to date").global(true))
         .arg(flag("locked", "Require Cargo.lock is up to date").global(true))
         .arg(flag("offline", "Run without accessing the network").global(true))
+        .arg(
+            opt(
+                "network-deadline",
+                "Stop retrying network operations DURATION after starting",
+            )
+            .value_name("DURATION")
+            .global(true),
+        )
+        .arg(
+            flag(
+                "strict-network",
+                "Fail once done if any network operation needed a retry",
+            )
+            .global(true),
+        )
         .arg(multi_opt("config", "KEY=VALUE", "Override a configuration value").global(true))
         .arg(
             Arg::new("unstable-features")
@@ -9,10 +9,14 @@ pub fn cli() -> Command {
         .arg_quiet()
         .arg_manifest_path()
         .arg_target_triple("Fetch dependencies for the target triple")
+        .arg_retry()
         .after_help("Run `cargo help fetch` for more detailed information.\n")
 }
 
 pub fn exec(config: &mut Config, args: &ArgMatches) -> CliResult {
+    if let Some(&retry) = args.get_one::<u32>("retry") {
+        config.set_retry_override(retry);
+    }
     let ws = args.workspace(config)?;
 
     let opts = FetchOptions {
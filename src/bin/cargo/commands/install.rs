@@ -86,6 +86,7 @@ pub fn cli() -> Command {
 }
 
 pub fn exec(config: &mut Config, args: &ArgMatches) -> CliResult {
+    config.set_retry_profile("install");
     let path = args.value_of_path("path", config);
     if let Some(path) = &path {
         config.reload_rooted_at(path)?;
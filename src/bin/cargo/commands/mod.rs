@@ -32,6 +32,7 @@ pub fn builtin() -> Vec<Command> {
         rustc::cli(),
         rustdoc::cli(),
         search::cli(),
+        simulate_retry::cli(),
         test::cli(),
         tree::cli(),
         uninstall::cli(),
@@ -75,6 +76,7 @@ pub fn builtin_exec(cmd: &str) -> Option<fn(&mut Config, &ArgMatches) -> CliResu
         "rustc" => rustc::exec,
         "rustdoc" => rustdoc::exec,
         "search" => search::exec,
+        "simulate-retry" => simulate_retry::exec,
         "test" => test::exec,
         "tree" => tree::exec,
         "uninstall" => uninstall::exec,
@@ -118,6 +120,7 @@ pub mod run;
 pub mod rustc;
 pub mod rustdoc;
 pub mod search;
+pub mod simulate_retry;
 pub mod test;
 pub mod tree;
 pub mod uninstall;
@@ -23,11 +23,15 @@ pub fn cli() -> Command {
         .arg_features()
         .arg_jobs()
         .arg_dry_run("Perform all checks without uploading")
+        .arg_retry()
         .arg(opt("registry", "Registry to publish to").value_name("REGISTRY"))
         .after_help("Run `cargo help publish` for more detailed information.\n")
 }
 
 pub fn exec(config: &mut Config, args: &ArgMatches) -> CliResult {
+    if let Some(&retry) = args.get_one::<u32>("retry") {
+        config.set_retry_override(retry);
+    }
     let registry = args.registry(config)?;
     let ws = args.workspace(config)?;
     let index = args.index()?;
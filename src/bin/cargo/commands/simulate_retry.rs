@@ -0,0 +1,43 @@
+use crate::command_prelude::*;
+use cargo::ops::{self, SimulateRetryOptions};
+
+pub fn cli() -> Command {
+    subcommand("simulate-retry")
+        .about("Run network retries against a scripted sequence of errors")
+        .hide(true)
+        .arg_quiet()
+        .arg(
+            opt(
+                "errors",
+                "The outcome of each attempt, separated by commas, such as `503,timeout,ok`",
+            )
+            .value_name("OUTCOMES")
+            .required(true),
+        )
+        .arg(
+            opt("url", "The URL the simulated operation talks to")
+                .value_name("URL")
+                .default_value("https://example.com/"),
+        )
+        .arg(
+            opt(
+                "seed",
+                "Seed for the random part of the delays, to reproduce them",
+            )
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64)),
+        )
+}
+
+pub fn exec(config: &mut Config, args: &ArgMatches) -> CliResult {
+    config
+        .cli_unstable()
+        .fail_if_stable_command(config, "simulate-retry", 177)?;
+    let opts = SimulateRetryOptions {
+        errors: args.get_one::<String>("errors").unwrap(),
+        url: args.get_one::<String>("url").unwrap(),
+        seed: args.get_one::<u64>("seed").copied(),
+    };
+    ops::simulate_retry(config, &opts)?;
+    Ok(())
+}
@@ -14,6 +14,7 @@ pub fn cli() -> Command {
             "Force updating all dependencies of SPEC as well when used with -p",
         ))
         .arg_dry_run("Don't actually write the lockfile")
+        .arg_retry()
         .arg(
             opt(
                 "precise",
@@ -27,6 +28,9 @@ pub fn cli() -> Command {
 }
 
 pub fn exec(config: &mut Config, args: &ArgMatches) -> CliResult {
+    if let Some(&retry) = args.get_one::<u32>("retry") {
+        config.set_retry_override(retry);
+    }
     let ws = args.workspace(config)?;
 
     if args.is_present_with_zero_values("package") {
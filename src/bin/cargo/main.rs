@@ -24,13 +24,23 @@ fn main() {
 
     let mut config = cli::LazyConfig::new();
 
-    let result = if let Some(lock_addr) = cargo::ops::fix_get_proxy_lock_addr() {
+    let mut result = if let Some(lock_addr) = cargo::ops::fix_get_proxy_lock_addr() {
         cargo::ops::fix_exec_rustc(config.get(), &lock_addr).map_err(|e| CliError::from(e))
     } else {
         let _token = cargo::util::job::setup();
         cli::main(&mut config)
     };
 
+    if config.is_init() {
+        // Not being able to print the summary shouldn't change the outcome.
+        let _ = cargo::util::network::retry::print_session_summary(config.get());
+        if result.is_ok() {
+            if let Err(e) = cargo::util::network::retry::check_strict_retries(config.get()) {
+                result = Err(e.into());
+            }
+        }
+    }
+
     match result {
         Err(e) => cargo::exit_with_error(e, &mut config.get_mut().shell()),
         Ok(()) => {}
@@ -25,10 +25,14 @@ use crate::core::source::MaybePackage;
 use crate::core::{Dependency, Manifest, PackageId, SourceId, Target};
 use crate::core::{SourceMap, Summary, Workspace};
 use crate::ops;
-use crate::util::config::PackageCacheLock;
+use crate::util::config::{PackageCacheLock, RetryModeConfig};
 use crate::util::errors::{CargoResult, HttpNotSuccessful};
 use crate::util::interning::InternedString;
-use crate::util::network::Retry;
+use crate::util::network;
+use crate::util::network::retry::{
+    progress_observer, NetRetryPolicy, Retry, RetryBuilder, RetryProgressSink, RetryResult,
+};
+use crate::util::network::sleep::SleepTracker;
 use crate::util::{self, internal, Config, Progress, ProgressStyle};
 
 pub const MANIFEST_PREAMBLE: &str = "\
@@ -319,6 +323,14 @@ pub struct Downloads<'a, 'cfg> {
     /// Set of packages currently being downloaded. This should stay in sync
     /// with `pending`.
     pending_ids: HashSet<PackageId>,
+    /// Downloads that have failed and are waiting to retry again later.
+    sleeping: SleepTracker<(Download<'cfg>, Easy)>,
+    /// With `net.retry-mode = "batched"`, downloads that have failed and are
+    /// waiting for the others to finish, along with the delay they asked for.
+    /// They are moved to `sleeping` together once nothing is pending.
+    deferred: Vec<(Duration, (Download<'cfg>, Easy))>,
+    /// Whether `net.retry-mode` is `"batched"`.
+    batch_retries: bool,
     /// The final result of each download. A pair `(token, result)`. This is a
     /// temporary holding area, needed because curl can report multiple
     /// downloads at once, but the main loop (`wait`) is written to only
@@ -338,6 +350,13 @@ pub struct Downloads<'a, 'cfg> {
     start: Instant,
     /// Indicates *all* downloads were successful.
     success: bool,
+    /// `Warning` headers from the server that have already been shown.
+    warnings_shown: HashSet<String>,
+    /// Downloads that are being retried, shown on the progress bar.
+    retrying: Rc<RetryingDownloads>,
+    /// The retry settings shared by every download, before applying those
+    /// for the download's host.
+    retry_policy: NetRetryPolicy,
 
     /// Timeout management, both of timeout thresholds as well as whether or not
     /// our connection has timed out (and accompanying message if it has).
@@ -376,6 +395,14 @@ struct Download<'cfg> {
     /// Actual downloaded data, updated throughout the lifetime of this download.
     data: RefCell<Vec<u8>>,
 
+    /// HTTP headers of the response, as `name: value` lines.
+    headers: RefCell<Vec<String>>,
+
+    /// Data received by earlier attempts that were cut short, when
+    /// `net.partial-download = "resume"`. The next attempt asks the server
+    /// for the rest of the file with a range request.
+    resumed: Vec<u8>,
+
     /// The URL that we're downloading from, cached here for error messages and
     /// reenqueuing.
     url: String,
@@ -442,6 +469,9 @@ impl<'cfg> PackageSet<'cfg> {
             next: 0,
             pending: HashMap::new(),
             pending_ids: HashSet::new(),
+            sleeping: SleepTracker::new(),
+            deferred: Vec::new(),
+            batch_retries: self.config.net_config()?.retry_mode == Some(RetryModeConfig::Batched),
             results: Vec::new(),
             progress: RefCell::new(Some(Progress::with_style(
                 "Downloading",
@@ -452,6 +482,9 @@ impl<'cfg> PackageSet<'cfg> {
             downloaded_bytes: 0,
             largest: (0, String::new()),
             success: false,
+            warnings_shown: HashSet::new(),
+            retrying: Rc::new(RetryingDownloads::default()),
+            retry_policy: NetRetryPolicy::new(self.config)?,
             updated_at: Cell::new(Instant::now()),
             timeout,
             next_speed_check: Cell::new(Instant::now()),
@@ -706,7 +739,8 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
         debug!("downloading {} as {}", id, token);
         assert!(self.pending_ids.insert(id));
 
-        let (mut handle, _timeout) = ops::http_handle_and_timeout(self.set.config)?;
+        let (mut handle, timeout) = ops::http_handle_and_timeout(self.set.config)?;
+        timeout.configure_deadline(&mut handle)?;
         handle.get(true)?;
         handle.url(&url)?;
         handle.follow_location(true)?; // follow redirects
@@ -730,10 +764,14 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
         // HTTP/2 but newer ones will. All that to basically say we ignore
         // errors here on OSX, but consider this a fatal error to not activate
         // HTTP/2 on all other platforms.
-        if self.set.multiplexing {
-            crate::try_old_curl!(handle.http_version(HttpVersion::V2), "HTTP2");
-        } else {
-            handle.http_version(HttpVersion::V11)?;
+        //
+        // A version pinned with `http.version` was set by `http_handle`.
+        if ops::pinned_http_version(self.set.config)?.is_none() {
+            if self.set.multiplexing {
+                crate::try_old_curl!(handle.http_version(HttpVersion::V2), "HTTP2");
+            } else {
+                handle.http_version(HttpVersion::V11)?;
+            }
         }
 
         // This is an option to `libcurl` which indicates that if there's a
@@ -758,6 +796,24 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
             });
             Ok(buf.len())
         })?;
+        handle.header_function(move |data| {
+            tls::with(|downloads| {
+                if let Some(downloads) = downloads {
+                    // Headers contain trailing \r\n, trim them to make it easier
+                    // to work with.
+                    let header = String::from_utf8_lossy(data).trim().to_string();
+                    let mut headers = downloads.pending[&token].0.headers.borrow_mut();
+                    // Only keep the headers of the response a redirect led to.
+                    if header.starts_with("HTTP/") {
+                        headers.clear();
+                    }
+                    if !header.is_empty() {
+                        headers.push(header);
+                    }
+                }
+            });
+            true
+        })?;
 
         handle.progress(true)?;
         handle.progress_function(move |dl_total, dl_cur, _, _| {
@@ -780,9 +836,19 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
                 .status("Downloading", "crates ...")?;
         }
 
+        let policy = self.retry_policy.clone().for_url(self.set.config, &url)?;
+        let mut retry = RetryBuilder::new(self.set.config)
+            .policy(policy)
+            .url(url.as_str());
+        if self.progress.borrow().as_ref().unwrap().is_enabled() {
+            let sink = Rc::clone(&self.retrying) as Rc<dyn RetryProgressSink<PackageId>>;
+            retry = retry.observer(progress_observer(sink, id));
+        }
         let dl = Download {
             token,
             data: RefCell::new(Vec::new()),
+            headers: RefCell::new(Vec::new()),
+            resumed: Vec::new(),
             id,
             url,
             descriptor,
@@ -790,7 +856,7 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
             current: Cell::new(0),
             start: Instant::now(),
             timed_out: Cell::new(None),
-            retry: Retry::new(self.set.config)?,
+            retry: retry.build()?,
         };
         self.enqueue(dl, handle)?;
         self.tick(WhyTick::DownloadStarted)?;
@@ -800,7 +866,7 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
 
     /// Returns the number of crates that are still downloading.
     pub fn remaining(&self) -> usize {
-        self.pending.len()
+        self.pending.len() + self.sleeping.len() + self.deferred.len()
     }
 
     /// Blocks the current thread waiting for a package to finish downloading.
@@ -822,60 +888,107 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
                 .remove(&token)
                 .expect("got a token for a non-in-progress transfer");
             let data = mem::take(&mut *dl.data.borrow_mut());
+            let headers = mem::take(&mut *dl.headers.borrow_mut());
             let mut handle = self.set.multi.remove(handle)?;
             self.pending_ids.remove(&dl.id);
+            network::warn_from_headers(
+                self.set.config,
+                &dl.url,
+                &headers,
+                &mut self.warnings_shown,
+            )?;
+
+            // Hold on to what we got from a transfer that was cut short, so
+            // the retry only has to fetch the rest of it.
+            let partial = matches!(&result, Err(e) if e.is_partial_file());
+            if partial && dl.retry.resumes_partial_downloads() {
+                dl.resumed.extend_from_slice(&data);
+            }
 
             // Check if this was a spurious error. If it was a spurious error
             // then we want to re-enqueue our request for another attempt and
             // then we wait for another request to finish.
+            dl.retry.set_bytes_received(data.len() as u64);
+            // Older versions of curl may not report this, in which case the
+            // warning just goes without it.
+            let dns_lookup_time = handle.namelookup_time();
+            if let Ok(time) = dns_lookup_time {
+                dl.retry.set_dns_lookup_time(time);
+            }
+            crate::try_old_curl!(dns_lookup_time, "DNS lookup timing");
             let ret = {
                 let timed_out = &dl.timed_out;
                 let url = &dl.url;
-                dl.retry
-                    .r#try(|| {
-                        if let Err(e) = result {
-                            // If this error is "aborted by callback" then that's
-                            // probably because our progress callback aborted due to
-                            // a timeout. We'll find out by looking at the
-                            // `timed_out` field, looking for a descriptive message.
-                            // If one is found we switch the error code (to ensure
-                            // it's flagged as spurious) and then attach our extra
-                            // information to the error.
-                            if !e.is_aborted_by_callback() {
-                                return Err(e.into());
-                            }
+                let resumed = &mut dl.resumed;
+                dl.retry.r#try(|| {
+                    if let Err(e) = result {
+                        // If this error is "aborted by callback" then that's
+                        // probably because our progress callback aborted due to
+                        // a timeout. We'll find out by looking at the
+                        // `timed_out` field, looking for a descriptive message.
+                        // If one is found we switch the error code (to ensure
+                        // it's flagged as spurious) and then attach our extra
+                        // information to the error.
+                        if !e.is_aborted_by_callback() {
+                            return Err(e.into());
+                        }
 
-                            return Err(match timed_out.replace(None) {
-                                Some(msg) => {
-                                    let code = curl_sys::CURLE_OPERATION_TIMEDOUT;
-                                    let mut err = curl::Error::new(code);
-                                    err.set_extra(msg);
-                                    err
-                                }
-                                None => e,
+                        return Err(match timed_out.replace(None) {
+                            Some(msg) => {
+                                let code = curl_sys::CURLE_OPERATION_TIMEDOUT;
+                                let mut err = curl::Error::new(code);
+                                err.set_extra(msg);
+                                err
                             }
-                            .into());
+                            None => e,
                         }
+                        .into());
+                    }
 
-                        let code = handle.response_code()?;
-                        if code != 200 && code != 0 {
-                            let url = handle.effective_url()?.unwrap_or(url);
-                            return Err(HttpNotSuccessful {
-                                code,
-                                url: url.to_string(),
-                                body: data,
-                            }
-                            .into());
+                    let code = handle.response_code()?;
+                    if code == 206 && !resumed.is_empty() {
+                        return network::append_range_response(resumed, &data, &headers, url);
+                    }
+                    if code != 200 && code != 0 {
+                        let effective_url = handle.effective_url()?.unwrap_or(url);
+                        return Err(HttpNotSuccessful {
+                            code,
+                            method: "GET".to_string(),
+                            url: effective_url.to_string(),
+                            body: data,
+                            headers,
+                            redirected_from: (effective_url != url).then(|| url.to_string()),
                         }
-                        Ok(data)
-                    })
-                    .with_context(|| format!("failed to download from `{}`", dl.url))?
+                        .into());
+                    }
+                    Ok(data)
+                })
             };
             match ret {
-                Some(data) => break (dl, data),
-                None => {
-                    self.pending_ids.insert(dl.id);
-                    self.enqueue(dl, handle)?
+                RetryResult::Success(data) => {
+                    self.retrying.0.borrow_mut().remove(&dl.id);
+                    break (dl, data);
+                }
+                RetryResult::Err(e) => {
+                    return Err(e.context(format!("failed to download from `{}`", dl.url)))
+                }
+                RetryResult::Retry(sleep) => {
+                    debug!("download retry {} for {:?}", dl.url, sleep);
+                    if let Some(url) = dl.retry.rewritten_url() {
+                        debug!("retrying {} at {}", dl.url, url);
+                        handle.url(&url)?;
+                        dl.url = url;
+                    }
+                    if let Some(timeouts) = dl.retry.attempt_timeouts(sleep, self.timeout.dur) {
+                        // Don't let the retry run past the `--network-deadline`.
+                        timeouts.configure(&mut handle)?;
+                    }
+                    dl.retry.configure_handle(&mut handle, dl.resumed.len())?;
+                    if self.batch_retries {
+                        self.deferred.push((sleep, (dl, handle)));
+                    } else {
+                        self.sleeping.push(sleep, (dl, handle));
+                    }
                 }
             }
         };
@@ -963,6 +1076,7 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
         // actually block waiting for I/O to happen, which we achieve with the
         // `wait` method on `multi`.
         loop {
+            self.add_sleepers()?;
             let n = tls::set(self, || {
                 self.set
                     .multi
@@ -985,17 +1099,40 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
             if let Some(pair) = results.pop() {
                 break Ok(pair);
             }
-            assert!(!self.pending.is_empty());
-            let min_timeout = Duration::new(1, 0);
-            let timeout = self.set.multi.get_timeout()?.unwrap_or(min_timeout);
-            let timeout = timeout.min(min_timeout);
-            self.set
-                .multi
-                .wait(&mut [], timeout)
-                .with_context(|| "failed to wait on curl `Multi`")?;
+            assert_ne!(self.remaining(), 0);
+            if self.pending.is_empty() {
+                let delay = self.sleeping.time_to_next().unwrap();
+                debug!("sleeping main thread for {:?}", delay);
+                std::thread::sleep(delay);
+            } else {
+                let min_timeout = Duration::new(1, 0);
+                let timeout = self.set.multi.get_timeout()?.unwrap_or(min_timeout);
+                let timeout = timeout.min(min_timeout);
+                self.set
+                    .multi
+                    .wait(&mut [], timeout)
+                    .with_context(|| "failed to wait on curl `Multi`")?;
+            }
         }
     }
 
+    /// Re-enqueues the downloads whose retry delay has passed.
+    fn add_sleepers(&mut self) -> CargoResult<()> {
+        if self.pending.is_empty() && !self.deferred.is_empty() {
+            // Everything else has finished, so retry the failed downloads
+            // together after the longest of their delays.
+            let delay = self.deferred.iter().map(|(d, _)| *d).max().unwrap();
+            for (_, data) in self.deferred.drain(..) {
+                self.sleeping.push(delay, data);
+            }
+        }
+        for (dl, handle) in self.sleeping.to_retry() {
+            self.pending_ids.insert(dl.id);
+            self.enqueue(dl, handle)?;
+        }
+        Ok(())
+    }
+
     fn progress(&self, token: usize, total: u64, cur: u64) -> bool {
         let dl = &self.pending[&token].0;
         dl.total.set(total);
@@ -1086,12 +1223,35 @@ impl<'a, 'cfg> Downloads<'a, 'cfg> {
                 if remaining > 0 && dur > Duration::from_millis(500) {
                     msg.push_str(&format!(", remaining bytes: {}", ByteSize(remaining)));
                 }
+                for (id, (attempt, max_attempts)) in self.retrying.0.borrow().iter() {
+                    msg.push_str(&format!(
+                        ", {} v{} retry {}/{}",
+                        id.name(),
+                        id.version(),
+                        attempt,
+                        max_attempts
+                    ));
+                }
             }
         }
         progress.print_now(&msg)
     }
 }
 
+/// Downloads that are being retried, by package, with the attempt that is
+/// about to be made and the maximum number of attempts.
+///
+/// Keyed by [`PackageId`] rather than name, so that two versions of a crate
+/// retrying at once are told apart.
+#[derive(Default)]
+struct RetryingDownloads(RefCell<BTreeMap<PackageId, (u32, u32)>>);
+
+impl RetryProgressSink<PackageId> for RetryingDownloads {
+    fn retrying(&self, item: &PackageId, attempt: u32, max_attempts: u32) {
+        self.0.borrow_mut().insert(*item, (attempt, max_attempts));
+    }
+}
+
 #[derive(Copy, Clone)]
 enum WhyTick<'a> {
     DownloadStarted,
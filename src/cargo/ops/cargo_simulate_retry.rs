@@ -0,0 +1,163 @@
+//! Implementation of the unstable `cargo simulate-retry` subcommand, which
+//! runs the network retry logic against a scripted sequence of errors.
+//!
+//! Each attempt of the simulated operation takes the next outcome from the
+//! script and fails with the same kind of error a real transfer would, so
+//! classification, backoff and the retry warnings all go through the code
+//! real network operations use. The delays between attempts are printed
+//! instead of waited out.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::{bail, format_err};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::drop_println;
+use crate::util::errors::{CargoResult, HttpNotSuccessful};
+use crate::util::network::retry::{RetryBuilder, RetryEvent, RetryResult};
+use crate::util::network::sleep::Sleeper;
+use crate::util::Config;
+
+pub struct SimulateRetryOptions<'a> {
+    /// The outcome of each attempt, separated by commas, such as
+    /// `503,timeout,ok`.
+    pub errors: &'a str,
+    /// The URL the simulated operation talks to, which selects its
+    /// `net.retry-hosts` table, if any.
+    pub url: &'a str,
+    /// Seeds the random part of the delays, so that they can be reproduced.
+    pub seed: Option<u64>,
+}
+
+/// What one attempt of the simulated operation does.
+#[derive(Clone, Copy)]
+enum Outcome {
+    Ok,
+    Status(u32),
+    Curl(&'static str, curl_sys::CURLcode),
+}
+
+/// The names of the curl errors a script can use, by the
+/// [`SpuriousReason`](crate::util::network::retry::SpuriousReason) they are
+/// usually classified as.
+const CURL_ERRORS: &[(&str, curl_sys::CURLcode)] = &[
+    ("timeout", curl_sys::CURLE_OPERATION_TIMEDOUT),
+    ("connect", curl_sys::CURLE_COULDNT_CONNECT),
+    ("dns", curl_sys::CURLE_COULDNT_RESOLVE_HOST),
+    ("send", curl_sys::CURLE_SEND_ERROR),
+    ("recv", curl_sys::CURLE_RECV_ERROR),
+    ("http2", curl_sys::CURLE_HTTP2),
+    ("ssl", curl_sys::CURLE_SSL_CONNECT_ERROR),
+    ("partial", curl_sys::CURLE_PARTIAL_FILE),
+];
+
+impl Outcome {
+    fn parse(s: &str) -> CargoResult<Outcome> {
+        let s = s.trim();
+        if s == "ok" {
+            return Ok(Outcome::Ok);
+        }
+        if let Ok(code @ 100..=599) = s.parse() {
+            return Ok(Outcome::Status(code));
+        }
+        match CURL_ERRORS.iter().find(|(name, _)| *name == s) {
+            Some(&(name, code)) => Ok(Outcome::Curl(name, code)),
+            None => {
+                let names: Vec<_> = CURL_ERRORS.iter().map(|(name, _)| *name).collect();
+                bail!(
+                    "unknown outcome `{}`, expected `ok`, an HTTP status code, or one of {}",
+                    s,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    fn run(self, url: &str) -> CargoResult<()> {
+        match self {
+            Outcome::Ok => Ok(()),
+            Outcome::Status(code) => Err(HttpNotSuccessful {
+                code,
+                method: "GET".to_string(),
+                url: url.to_string(),
+                body: Vec::new(),
+                headers: Vec::new(),
+                redirected_from: None,
+            }
+            .into()),
+            Outcome::Curl(_, code) => Err(curl::Error::new(code).into()),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Outcome::Ok => "ok".to_string(),
+            Outcome::Status(code) => code.to_string(),
+            Outcome::Curl(name, _) => name.to_string(),
+        }
+    }
+}
+
+/// A [`Sleeper`] that returns right away, since the delays are only printed.
+struct NoSleep;
+
+impl Sleeper for NoSleep {
+    fn sleep(&self, _dur: Duration) {}
+}
+
+pub fn simulate_retry(config: &Config, opts: &SimulateRetryOptions<'_>) -> CargoResult<()> {
+    let script = opts
+        .errors
+        .split(',')
+        .map(Outcome::parse)
+        .collect::<CargoResult<Vec<_>>>()?;
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let observed = events.clone();
+    let mut builder = RetryBuilder::new(config)
+        .url(opts.url)
+        .operation("simulated operation")
+        .sleeper(Rc::new(NoSleep))
+        .observer(move |event: &RetryEvent| observed.borrow_mut().push(*event));
+    if let Some(seed) = opts.seed {
+        builder = builder.jitter_rng(StdRng::seed_from_u64(seed));
+    }
+    let mut retry = builder.build()?;
+    for (attempt, outcome) in (1..).zip(script) {
+        let name = outcome.name();
+        match retry.r#try(|| outcome.run(opts.url)) {
+            RetryResult::Success(()) => {
+                drop_println!(config, "attempt {}: ok", attempt);
+                drop_println!(config, "succeeded after {} attempt(s)", attempt);
+                return Ok(());
+            }
+            RetryResult::Retry(delay) => {
+                let event = events.borrow_mut().pop().unwrap();
+                drop_println!(
+                    config,
+                    "attempt {}: {}, retrying in {}ms ({})",
+                    attempt,
+                    name,
+                    delay.as_millis(),
+                    event.reason
+                );
+                retry.sleep(delay);
+            }
+            RetryResult::Err(e) => {
+                let decision = if retry.ran_out_of_retries(&e) {
+                    "giving up"
+                } else {
+                    "not retried"
+                };
+                drop_println!(config, "attempt {}: {}, {}", attempt, name, decision);
+                return Err(e.context(format!("failed after {} attempt(s)", attempt)));
+            }
+        }
+    }
+    Err(format_err!(
+        "the scripted outcomes ran out before the operation finished, \
+         end them with `ok` or an error that isn't retried"
+    ))
+}
@@ -17,12 +17,16 @@ pub use self::cargo_package::{check_yanked, package, package_one, PackageOpts};
 pub use self::cargo_pkgid::pkgid;
 pub use self::cargo_read_manifest::{read_package, read_packages};
 pub use self::cargo_run::run;
+pub use self::cargo_simulate_retry::{simulate_retry, SimulateRetryOptions};
 pub use self::cargo_test::{run_benches, run_tests, TestOptions};
 pub use self::cargo_uninstall::uninstall;
 pub use self::fix::{fix, fix_exec_rustc, fix_get_proxy_lock_addr, FixOptions};
 pub use self::lockfile::{load_pkg_lockfile, resolve_to_string, write_pkg_lockfile};
 pub use self::registry::HttpTimeout;
-pub use self::registry::{configure_http_handle, http_handle, http_handle_and_timeout};
+pub use self::registry::{
+    configure_http_handle, configure_pinned_http_version, http_handle, http_handle_and_timeout,
+};
+pub use self::registry::pinned_http_version;
 pub use self::registry::{modify_owners, yank, OwnersOptions, PublishOpts};
 pub use self::registry::{needs_custom_http_transport, registry_login, registry_logout, search};
 pub use self::registry::{publish, RegistryCredentialConfig};
@@ -47,6 +51,7 @@ mod cargo_pkgid;
 mod cargo_read_manifest;
 pub mod cargo_remove;
 mod cargo_run;
+mod cargo_simulate_retry;
 mod cargo_test;
 mod cargo_uninstall;
 mod common_for_install_and_uninstall;
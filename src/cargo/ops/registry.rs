@@ -6,13 +6,13 @@ use std::iter::repeat;
 use std::path::PathBuf;
 use std::str;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, format_err, Context as _};
 use cargo_util::paths;
 use crates_io::{self, NewCrate, NewCrateDependency, Registry};
-use curl::easy::{Easy, InfoType, SslOpt, SslVersion};
-use log::{log, Level};
+use curl::easy::{Easy, HttpVersion, InfoType, SslOpt, SslVersion};
+use log::{log, warn, Level};
 use pasetors::keys::{AsymmetricKeyPair, Generate};
 use pasetors::paserk::FormatAsPaserk;
 use termcolor::Color::Green;
@@ -32,9 +32,11 @@ use crate::sources::{RegistrySource, SourceConfigMap, CRATES_IO_DOMAIN, CRATES_I
 use crate::util::auth::{
     paserk_public_from_paserk_secret, Secret, {self, AuthorizationError},
 };
-use crate::util::config::{Config, SslVersionConfig, SslVersionConfigRange};
-use crate::util::errors::CargoResult;
+use crate::util::config::{Config, HttpVersionConfig, SslVersionConfig, SslVersionConfigRange};
+use crate::util::errors::{CargoResult, HttpNotSuccessful};
 use crate::util::important_paths::find_root_manifest_for_wd;
+use crate::util::network::proxy::{http_proxy, http_proxy_exists};
+use crate::util::network::retry;
 use crate::util::{truncate_with_ellipsis, IntoUrl};
 use crate::{drop_print, drop_println, version};
 
@@ -377,6 +379,7 @@ fn transmit(
         None => BTreeMap::new(),
     };
 
+    let url = format!("{}/api/v1/crates/new", registry.host());
     let warnings = registry
         .publish(
             &NewCrate {
@@ -400,6 +403,7 @@ fn transmit(
             },
             tarball,
         )
+        .map_err(|e| with_request_method(e, "PUT", &url))
         .with_context(|| format!("failed to publish to registry at {}", registry.host()))?;
 
     if !warnings.invalid_categories.is_empty() {
@@ -446,7 +450,7 @@ fn wait_for_publish(
     let query = Dependency::parse(pkg.name(), Some(&version_req), registry_src)?;
 
     let now = std::time::Instant::now();
-    let sleep_time = std::time::Duration::from_secs(1);
+    let poll_wait = retry::PollWait::new(config, std::time::Duration::from_secs(1));
     let mut logged = false;
     loop {
         {
@@ -460,15 +464,16 @@ fn wait_for_publish(
                 .updated_sources()
                 .remove(&source.replaced_source_id());
             source.invalidate_cache();
-            let summaries = loop {
+            let summaries = retry::with_retry_poll(config, || {
                 // Exact to avoid returning all for path/git
                 match source.query_vec(&query, QueryKind::Exact) {
-                    std::task::Poll::Ready(res) => {
-                        break res?;
-                    }
-                    std::task::Poll::Pending => source.block_until_ready()?,
+                    Poll::Ready(res) => Poll::Ready(res),
+                    Poll::Pending => match source.block_until_ready() {
+                        Ok(()) => Poll::Pending,
+                        Err(e) => Poll::Ready(Err(e)),
+                    },
                 }
-            };
+            })?;
             if !summaries.is_empty() {
                 break;
             }
@@ -494,7 +499,7 @@ fn wait_for_publish(
             )?;
             logged = true;
         }
-        std::thread::sleep(sleep_time);
+        poll_wait.wait(timeout.saturating_sub(now.elapsed()));
     }
 
     Ok(())
@@ -605,6 +610,7 @@ pub fn configure_http_handle(config: &Config, handle: &mut Easy) -> CargoResult<
     if let Some(proxy) = http_proxy(config)? {
         handle.proxy(&proxy)?;
     }
+    configure_pinned_http_version(config, handle)?;
     if let Some(cainfo) = &http.cainfo {
         let cainfo = cainfo.resolve_path(config);
         handle.cainfo(&cainfo)?;
@@ -716,6 +722,8 @@ pub fn configure_http_handle(config: &Config, handle: &mut Easy) -> CargoResult<
 pub struct HttpTimeout {
     pub dur: Duration,
     pub low_speed_limit: u32,
+    /// From [`Config::network_deadline`].
+    pub deadline: Option<Instant>,
 }
 
 impl HttpTimeout {
@@ -734,6 +742,7 @@ impl HttpTimeout {
         Ok(HttpTimeout {
             dur: Duration::new(seconds, 0),
             low_speed_limit,
+            deadline: config.network_deadline(),
         })
     }
 
@@ -746,45 +755,49 @@ impl HttpTimeout {
         handle.connect_timeout(self.dur)?;
         handle.low_speed_time(self.dur)?;
         handle.low_speed_limit(self.low_speed_limit)?;
-        Ok(())
+        self.configure_deadline(handle)
     }
-}
 
-/// Finds an explicit HTTP proxy if one is available.
-///
-/// Favor cargo's `http.proxy`, then git's `http.proxy`. Proxies specified
-/// via environment variables are picked up by libcurl.
-fn http_proxy(config: &Config) -> CargoResult<Option<String>> {
-    let http = config.http_config()?;
-    if let Some(s) = &http.proxy {
-        return Ok(Some(s.clone()));
-    }
-    if let Ok(cfg) = git2::Config::open_default() {
-        if let Ok(s) = cfg.get_string("http.proxy") {
-            return Ok(Some(s));
+    /// Cuts transfers off at the `--network-deadline`, if there is one.
+    ///
+    /// This is separate from [`HttpTimeout::configure`] for the downloaders
+    /// that check the other timeouts themselves.
+    pub fn configure_deadline(&self, handle: &mut Easy) -> CargoResult<()> {
+        if let Some(deadline) = self.deadline {
+            // A timeout of zero means no timeout to curl.
+            let left = deadline
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1));
+            handle.timeout(left)?;
+            handle.connect_timeout(self.dur.min(left))?;
         }
+        Ok(())
     }
-    Ok(None)
 }
 
-/// Determine if an http proxy exists.
+/// The HTTP version every request uses, if `http.version` pins one.
 ///
-/// Checks the following for existence, in order:
+/// Without it, the downloaders pick HTTP/2 or HTTP/1.1 from
+/// `http.multiplexing`, and the retries may fall back to HTTP/1.1 after
+/// HTTP/2 stream errors, see `net.retry-http1-fallback`.
+pub fn pinned_http_version(config: &Config) -> CargoResult<Option<HttpVersion>> {
+    Ok(config.http_config()?.version.map(|version| match version {
+        HttpVersionConfig::Http11 => HttpVersion::V11,
+        HttpVersionConfig::Http2 => HttpVersion::V2,
+    }))
+}
+
+/// Makes `handle` use the HTTP version `http.version` pins, if any.
 ///
-/// * cargo's `http.proxy`
-/// * git's `http.proxy`
-/// * `http_proxy` env var
-/// * `HTTP_PROXY` env var
-/// * `https_proxy` env var
-/// * `HTTPS_PROXY` env var
-fn http_proxy_exists(config: &Config) -> CargoResult<bool> {
-    if http_proxy(config)?.is_some() {
-        Ok(true)
-    } else {
-        Ok(["http_proxy", "HTTP_PROXY", "https_proxy", "HTTPS_PROXY"]
-            .iter()
-            .any(|v| config.get_env(v).is_ok()))
+/// [`configure_http_handle`] does this for every handle it sets up.
+pub fn configure_pinned_http_version(
+    config: &Config,
+    handle: &mut impl retry::RetryHandle,
+) -> CargoResult<()> {
+    if let Some(version) = pinned_http_version(config)? {
+        crate::try_old_curl!(handle.http_version(version), "http.version");
     }
+    Ok(())
 }
 
 pub fn registry_login(
@@ -974,13 +987,17 @@ pub fn modify_owners(config: &Config, opts: &OwnersOptions) -> CargoResult<()> {
 
     if let Some(ref v) = opts.to_add {
         let v = v.iter().map(|s| &s[..]).collect::<Vec<_>>();
-        let msg = registry.add_owners(&name, &v).with_context(|| {
-            format!(
-                "failed to invite owners to crate `{}` on registry at {}",
-                name,
-                registry.host()
-            )
-        })?;
+        let url = format!("{}/api/v1/crates/{}/owners", registry.host(), name);
+        let msg = registry
+            .add_owners(&name, &v)
+            .map_err(|e| with_request_method(e, "PUT", &url))
+            .with_context(|| {
+                format!(
+                    "failed to invite owners to crate `{}` on registry at {}",
+                    name,
+                    registry.host()
+                )
+            })?;
 
         config.shell().status("Owner", msg)?;
     }
@@ -990,13 +1007,17 @@ pub fn modify_owners(config: &Config, opts: &OwnersOptions) -> CargoResult<()> {
         config
             .shell()
             .status("Owner", format!("removing {:?} from crate {}", v, name))?;
-        registry.remove_owners(&name, &v).with_context(|| {
-            format!(
-                "failed to remove owners from crate `{}` on registry at {}",
-                name,
-                registry.host()
-            )
-        })?;
+        let url = format!("{}/api/v1/crates/{}/owners", registry.host(), name);
+        registry
+            .remove_owners(&name, &v)
+            .map_err(|e| with_request_method(e, "DELETE", &url))
+            .with_context(|| {
+                format!(
+                    "failed to remove owners from crate `{}` on registry at {}",
+                    name,
+                    registry.host()
+                )
+            })?;
     }
 
     if opts.list {
@@ -1063,10 +1084,18 @@ pub fn yank(
         Some(message),
     )?;
 
+    // Yanking is idempotent, so it is safe to retry it.
     let package_spec = format!("{}@{}", name, version);
+    let url = format!("{}/api/v1/crates/{}/{}", registry.host(), name, version);
     if undo {
         config.shell().status("Unyank", package_spec)?;
-        registry.unyank(&name, &version).with_context(|| {
+        let url = format!("{url}/unyank");
+        retry::with_retry_named(config, &format!("unyank of {name} v{version}"), || {
+            registry
+                .unyank(&name, &version)
+                .map_err(|e| with_request_method(e, "PUT", &url))
+        })
+        .with_context(|| {
             format!(
                 "failed to undo a yank from the registry at {}",
                 registry.host()
@@ -1074,14 +1103,41 @@ pub fn yank(
         })?;
     } else {
         config.shell().status("Yank", package_spec)?;
-        registry
-            .yank(&name, &version)
-            .with_context(|| format!("failed to yank from the registry at {}", registry.host()))?;
+        let url = format!("{url}/yank");
+        retry::with_retry_named(config, &format!("yank of {name} v{version}"), || {
+            registry
+                .yank(&name, &version)
+                .map_err(|e| with_request_method(e, "DELETE", &url))
+        })
+        .with_context(|| format!("failed to yank from the registry at {}", registry.host()))?;
     }
 
     Ok(())
 }
 
+/// Turns an unsuccessful response to a `method` request to the registry API
+/// at `url` into an [`HttpNotSuccessful`], so that the error says which
+/// request failed.
+fn with_request_method(err: anyhow::Error, method: &str, url: &str) -> anyhow::Error {
+    match err.downcast::<crates_io::ResponseError>() {
+        Ok(crates_io::ResponseError::Code {
+            code,
+            headers,
+            body,
+        }) => HttpNotSuccessful {
+            code,
+            method: method.to_string(),
+            url: url.to_string(),
+            body: body.into_bytes(),
+            headers,
+            redirected_from: None,
+        }
+        .into(),
+        Ok(err) => err.into(),
+        Err(err) => err,
+    }
+}
+
 /// Gets the SourceId for an index or registry setting.
 ///
 /// The `index` and `reg` values are from the command-line or config settings.
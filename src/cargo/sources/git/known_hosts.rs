@@ -20,6 +20,7 @@
 //! and revoked markers. See "FIXME" comments littered in this file.
 
 use crate::util::config::{Config, Definition, Value};
+use crate::util::network::retry::transient_callback_error;
 use base64::engine::general_purpose::STANDARD;
 use base64::engine::general_purpose::STANDARD_NO_PAD;
 use base64::Engine as _;
@@ -28,6 +29,7 @@ use git2::CertificateCheckStatus;
 use hmac::Mac;
 use std::collections::HashSet;
 use std::fmt::{Display, Write};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
 /// These are host keys that are hard-coded in cargo to provide convenience.
@@ -143,7 +145,21 @@ pub fn certificate_check(
             return Ok(CertificateCheckStatus::CertificateOk);
         }
         Err(KnownHostError::CheckError(e)) => {
-            format!("error: failed to validate host key:\n{:#}", e)
+            let msg = format!("error: failed to validate host key:\n{:#}", e);
+            // A known_hosts file on a network filesystem may be briefly
+            // unreadable, which is worth another try.
+            let transient = e.chain().any(|cause| {
+                cause.downcast_ref::<std::io::Error>().map_or(false, |io| {
+                    matches!(
+                        io.kind(),
+                        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    )
+                })
+            });
+            if transient {
+                return Err(transient_callback_error(&msg));
+            }
+            msg
         }
         Err(KnownHostError::HostKeyNotFound {
             hostname,
@@ -7,4 +7,22 @@ mod utils;
 
 pub mod fetch {
     pub type Error = gix::env::collate::fetch::Error<gix::refspec::parse::Error>;
+
+    /// Whether the fetch failed while negotiating what to send, because the
+    /// server lacks a capability it was asked to use or because no common
+    /// history could be found. Repeating the same fetch would fail the same
+    /// way, but one from an empty repository, with nothing to negotiate,
+    /// may well succeed.
+    pub fn needs_full_fetch(err: &Error) -> bool {
+        use gix::protocol::fetch::response::Error as ResponseError;
+        use gix::remote::fetch::Error as FetchError;
+
+        matches!(
+            err,
+            Error::Fetch(
+                FetchError::Negotiate(_)
+                    | FetchError::FetchResponse(ResponseError::MissingServerCapability { .. })
+            )
+        )
+    }
 }
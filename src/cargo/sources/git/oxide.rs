@@ -2,6 +2,8 @@
 //! `utils` closely for now. One day it can be renamed into `utils` once `git2` isn't required anymore.
 
 use crate::ops::HttpTimeout;
+use crate::util::config::HttpVersionConfig;
+use crate::util::errors::CredentialsExpired;
 use crate::util::{human_readable_bytes, network, MetricsCounter, Progress};
 use crate::{CargoResult, Config};
 use cargo_util::paths;
@@ -10,8 +12,8 @@ use log::debug;
 use std::cell::RefCell;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Weak};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
 
 /// For the time being, `repo_path` makes it easy to instantiate a gitoxide repo just for fetching.
 /// In future this may change to be the gitoxide repository itself.
@@ -23,13 +25,20 @@ pub fn with_retry_and_progress(
         &AtomicBool,
         &mut gix::progress::tree::Item,
         &mut dyn FnMut(&gix::bstr::BStr),
+        &FetchCredentials,
     ) -> Result<(), crate::sources::git::fetch::Error>
           + Send
           + Sync),
 ) -> CargoResult<()> {
+    let credentials = Arc::new(FetchCredentials::default());
     std::thread::scope(|s| {
         let mut progress_bar = Progress::new("Fetch", config);
-        network::with_retry(config, || {
+        let refresh = {
+            let credentials = Arc::clone(&credentials);
+            move || credentials.refresh()
+        };
+        let credentials = &*credentials;
+        network::retry::with_retry_refreshing_credentials(config, refresh, || {
             let progress_root: Arc<gix::progress::tree::Root> =
                 gix::progress::tree::root::Options {
                     initial_capacity: 10,
@@ -47,8 +56,9 @@ pub fn with_retry_and_progress(
                     &mut |url| {
                         *urls.borrow_mut() = Some(url.to_owned());
                     },
+                    credentials,
                 );
-                amend_authentication_hints(res, urls.get_mut().take())
+                amend_authentication_hints(res, urls.get_mut().take(), credentials)
             });
             translate_progress_to_bar(&mut progress_bar, root)?;
             thread.join().expect("no panic in scoped thread")
@@ -145,9 +155,148 @@ fn translate_progress_to_bar(
     Ok(())
 }
 
+/// The credentials of a fetch, handed out to each of its attempts.
+///
+/// The credential helpers are asked for them, keeping the expiry time that
+/// the helper providing them reports, so that credentials the server rejects
+/// can be told apart as expired rather than wrong. Expired ones are
+/// replaced by [`FetchCredentials::refresh`] before the fetch is retried.
+#[derive(Default)]
+pub struct FetchCredentials {
+    state: Mutex<CredentialsState>,
+}
+
+#[derive(Default)]
+struct CredentialsState {
+    /// The credential helpers configured for the URL, with the request to
+    /// get credentials from them and the options for prompting.
+    helpers: Option<(
+        gix::credentials::helper::Cascade,
+        gix::credentials::helper::Action,
+        gix::prompt::Options<'static>,
+    )>,
+    /// When the helper said the credentials it last handed out expire.
+    expires: Option<SystemTime>,
+    /// New credentials from [`FetchCredentials::refresh`], for the next
+    /// attempt to use.
+    refreshed: Option<gix::credentials::protocol::Outcome>,
+}
+
+impl FetchCredentials {
+    /// Uses the credential helpers `repo` configures for `url`.
+    pub fn configure(
+        &self,
+        repo: &gix::Repository,
+        url: gix::Url,
+    ) -> Result<(), gix::config::credential_helpers::Error> {
+        let helpers = repo.config_snapshot().credential_helpers(url)?;
+        self.state.lock().unwrap().helpers = Some(helpers);
+        Ok(())
+    }
+
+    /// Handles `action` for `gix`, see [`gix::remote::Connection::with_credentials`].
+    pub fn authenticate(
+        &self,
+        action: gix::credentials::helper::Action,
+    ) -> gix::credentials::protocol::Result {
+        let mut state = self.state.lock().unwrap();
+        if let gix::credentials::helper::Action::Get(_) = action {
+            if let Some(refreshed) = state.refreshed.take() {
+                return Ok(Some(refreshed));
+            }
+        }
+        state.invoke(action)
+    }
+
+    /// Whether the helper said the credentials it last handed out have
+    /// expired by now.
+    fn expired(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .expires
+            .map_or(false, |expires| expires <= SystemTime::now())
+    }
+
+    /// Asks the credential helpers for new credentials, in place of the ones
+    /// the server rejected. `gix` erased those from the helpers already.
+    fn refresh(&self) -> CargoResult<()> {
+        debug!("asking the credential helpers for new credentials");
+        let mut state = self.state.lock().unwrap();
+        let Some((_, get, _)) = &state.helpers else {
+            anyhow::bail!("no credential helpers to ask for new credentials");
+        };
+        let get = get.clone();
+        state.refreshed = state.invoke(get)?;
+        Ok(())
+    }
+}
+
+impl CredentialsState {
+    /// Passes `action` on to the credential helpers.
+    ///
+    /// Credentials are got by asking each helper in turn, as
+    /// [`Cascade::invoke`](gix::credentials::helper::Cascade::invoke) does,
+    /// as only the output of the helper says when they expire. If no helper
+    /// has both a username and a password, the cascade is asked after all,
+    /// as it also puts together partial answers and prompts for whatever is
+    /// missing.
+    fn invoke(
+        &mut self,
+        action: gix::credentials::helper::Action,
+    ) -> gix::credentials::protocol::Result {
+        use gix::credentials::helper::{self, Action};
+
+        let (cascade, _, prompt) = self
+            .helpers
+            .as_mut()
+            .expect("credential helpers are configured before connecting");
+        if let Action::Get(ctx) = &action {
+            self.expires = None;
+            let mut ctx = ctx.clone();
+            ctx.destructure_url_in_place(cascade.use_http_path)?;
+            ctx.url = None;
+            let get = Action::Get(ctx.clone());
+            for program in &mut cascade.programs {
+                program.stderr = cascade.stderr;
+                let Ok(Some(outcome)) = helper::invoke(program, &get) else {
+                    continue;
+                };
+                if outcome.username.is_none() || outcome.password.is_none() {
+                    continue;
+                }
+                if let Action::Store(output) = outcome.next.store() {
+                    self.expires = password_expiry(&output);
+                }
+                ctx.username = outcome.username.clone();
+                ctx.password = outcome.password.clone();
+                let outcome = helper::Outcome {
+                    next: ctx.clone().into(),
+                    ..outcome
+                };
+                return gix::credentials::protocol::helper_outcome_to_result(
+                    Some(outcome),
+                    Action::Get(ctx),
+                );
+            }
+        }
+        cascade.invoke(action, prompt.clone())
+    }
+}
+
+/// Reads `password_expiry_utc`, which a credential helper can send along with
+/// a short-lived password, from the `output` of the helper.
+fn password_expiry(output: &[u8]) -> Option<SystemTime> {
+    let secs = output
+        .lines()
+        .find_map(|line| line.strip_prefix(b"password_expiry_utc=".as_slice()))?;
+    let secs = secs.to_str().ok()?.trim().parse().ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
 fn amend_authentication_hints(
     res: Result<(), crate::sources::git::fetch::Error>,
     last_url_for_authentication: Option<gix::bstr::BString>,
+    credentials: &FetchCredentials,
 ) -> CargoResult<()> {
     let Err(err) = res else { return Ok(()) };
     let e = match &err {
@@ -158,6 +307,14 @@ fn amend_authentication_hints(
     };
     if let Some(e) = e {
         use anyhow::Context;
+        // Credentials that the helper or the server says have expired, such
+        // as a short-lived token, are replaced with new ones for one retry.
+        let expired = match e {
+            gix::protocol::handshake::Error::InvalidCredentials { source, .. } => {
+                credentials.expired() || source.to_string().contains("expired")
+            }
+            _ => false,
+        };
         let auth_message = match e {
             gix::protocol::handshake::Error::Credentials(_) => {
                 "\n* attempted to find username/password via \
@@ -195,7 +352,13 @@ fn amend_authentication_hints(
             msg.push_str(
                 "https://doc.rust-lang.org/cargo/reference/config.html#netgit-fetch-with-cli",
             );
-            return Err(anyhow::Error::from(err)).context(msg);
+            let err = anyhow::Error::from(err);
+            let err = if expired {
+                CredentialsExpired::new(err).into()
+            } else {
+                err
+            };
+            return Err(err).context(msg);
         }
     }
     Err(err.into())
@@ -311,8 +474,11 @@ pub fn cargo_config_to_gitoxide_overrides(config: &Config) -> CargoResult<Vec<BS
     if let Some(debug) = http.debug {
         values.push(gitoxide::Http::VERBOSE.validated_assignment_fmt(&debug)?);
     }
-    if let Some(multiplexing) = http.multiplexing {
-        let http_version = multiplexing.then(|| "HTTP/2").unwrap_or("HTTP/1.1");
+    let pinned_http2 = http
+        .version
+        .map(|version| version == HttpVersionConfig::Http2);
+    if let Some(http2) = pinned_http2.or(http.multiplexing) {
+        let http_version = http2.then(|| "HTTP/2").unwrap_or("HTTP/1.1");
         // Note that failing to set the HTTP version in `gix-transport` isn't fatal,
         // which is why we don't have to try to figure out if HTTP V2 is supported in the
         // currently linked version (see `try_old_curl!()`)
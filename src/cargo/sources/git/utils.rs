@@ -703,7 +703,9 @@ where
                 );
                 err = err.context(msg);
             }
-            ErrorClass::Callback => {
+            // A transient failure is kept as a git2 error so that
+            // `with_retry` can tell to try again.
+            ErrorClass::Callback if !network::retry::is_transient_callback_error(e) => {
                 // This unwraps the git2 error. We're using the callback error
                 // specifically to convey errors from Rust land through the C
                 // callback interface. We don't need the `; class=Callback
@@ -739,7 +741,7 @@ pub fn with_fetch_options(
     let ssh_config = config.net_config()?.ssh.as_ref();
     let config_known_hosts = ssh_config.and_then(|ssh| ssh.known_hosts.as_ref());
     let diagnostic_home_config = config.diagnostic_home_config();
-    network::with_retry(config, || {
+    network::retry::with_retry(config, || {
         with_authentication(config, url, git_config, |f| {
             let port = Url::parse(url).ok().and_then(|url| url.port());
             let mut last_update = Instant::now();
@@ -899,7 +901,8 @@ pub fn fetch(
             &|repo_path,
               should_interrupt,
               mut progress,
-              url_for_authentication: &mut dyn FnMut(&gix::bstr::BStr)| {
+              url_for_authentication: &mut dyn FnMut(&gix::bstr::BStr),
+              credentials: &oxide::FetchCredentials| {
                 // The `fetch` operation here may fail spuriously due to a corrupt
                 // repository. It could also fail, however, for a whole slew of other
                 // reasons (aka network related reasons). We want Cargo to automatically
@@ -938,7 +941,7 @@ pub fn fetch(
                             .to_owned();
                         let connection =
                             remote.connect(gix::remote::Direction::Fetch, &mut progress)?;
-                        let mut authenticate = connection.configured_credentials(url)?;
+                        credentials.configure(&repo, url)?;
                         let connection = connection.with_credentials(
                             move |action: gix::protocol::credentials::helper::Action| {
                                 if let Some(url) = action
@@ -947,7 +950,7 @@ pub fn fetch(
                                 {
                                     url_for_authentication(url.as_ref());
                                 }
-                                authenticate(action)
+                                credentials.authenticate(action)
                             },
                         );
                         let outcome = connection
@@ -978,6 +981,18 @@ pub fn fetch(
                         }
                     }
 
+                    // Negotiating from what we have failed, so fall back to
+                    // fetching everything into an empty repository instead.
+                    if !repo_reinitialized.load(Ordering::Relaxed)
+                        && crate::sources::git::fetch::needs_full_fetch(&err)
+                    {
+                        repo_reinitialized.store(true, Ordering::Relaxed);
+                        debug!("negotiation failed, falling back to a full fetch");
+                        if oxide::reinitialize(repo_path).is_ok() {
+                            continue;
+                        }
+                    }
+
                     return Err(err.into());
                 }
                 Ok(())
@@ -10,11 +10,13 @@ use crate::sources::registry::{
 };
 use crate::util::auth;
 use crate::util::errors::CargoResult;
+use crate::util::network::retry::{RetryBuilder, RetryResult};
 use crate::util::{Config, Filesystem};
 use std::fmt::Write as FmtWrite;
 use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::SeekFrom;
+use std::io::{self, SeekFrom};
+use std::path::Path;
 use std::str;
 
 pub(super) fn filename(pkg: PackageId) -> String {
@@ -100,18 +102,65 @@ pub(super) fn finish_download(
     cache_path.create_dir()?;
     let path = cache_path.join(&filename);
     let path = config.assert_package_cache_locked(&path);
+    write_to_cache(config, path, || write_crate(path, data))
+}
+
+/// Runs `write` to put a downloaded `.crate` file into the cache, retrying
+/// it after I/O errors that are likely to go away by themselves, such as a
+/// virus scanner holding on to the file.
+///
+/// The data is already downloaded at this point, so failing to store it
+/// would be a waste.
+fn write_to_cache(
+    config: &Config,
+    path: &Path,
+    mut write: impl FnMut() -> CargoResult<File>,
+) -> CargoResult<File> {
+    let mut retry = RetryBuilder::new(config)
+        .classifier(cache_write_is_spurious)
+        .operation(format!("writing `{}`", path.display()))
+        .build()?;
+    loop {
+        match retry.r#try(&mut write) {
+            RetryResult::Success(file) => return Ok(file),
+            RetryResult::Err(e) => return Err(e),
+            RetryResult::Retry(delay) => retry.sleep(delay),
+        }
+    }
+}
+
+fn cache_write_is_spurious(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => true,
+            // Windows reports a file that another process has open, which
+            // is usually a virus scanner, as access denied.
+            io::ErrorKind::PermissionDenied => cfg!(windows),
+            _ => false,
+        })
+}
+
+/// Writes `data` to the cache file at `path`, unless an earlier download
+/// already did.
+fn write_crate(path: &Path, data: &[u8]) -> CargoResult<File> {
     let mut dst = OpenOptions::new()
         .create(true)
         .read(true)
         .write(true)
-        .open(&path)
+        .open(path)
         .with_context(|| format!("failed to open `{}`", path.display()))?;
     let meta = dst.metadata()?;
     if meta.len() > 0 {
         return Ok(dst);
     }
 
-    dst.write_all(data)?;
+    if let Err(e) = dst.write_all(data) {
+        // An empty file is treated as not downloaded yet, so a retry
+        // writes all of the data again.
+        let _ = dst.set_len(0);
+        return Err(e.into());
+    }
     dst.seek(SeekFrom::Start(0))?;
     Ok(dst)
 }
@@ -128,3 +177,38 @@ pub(super) fn is_crate_downloaded(
     }
     false
 }
+
+#[test]
+fn cache_writes_are_retried() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo-1.0.0.crate");
+
+    let mut attempts = 0;
+    let mut file = write_to_cache(&config, &path, || {
+        attempts += 1;
+        if attempts == 1 {
+            return Err(io::Error::from(io::ErrorKind::Interrupted).into());
+        }
+        write_crate(&path, b"crate data")
+    })
+    .unwrap();
+    assert_eq!(attempts, 2);
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "crate data");
+
+    // Other errors, like a missing directory, fail right away.
+    let mut attempts = 0;
+    let missing = dir.path().join("missing/foo-1.0.0.crate");
+    let err = write_to_cache(&config, &missing, || {
+        attempts += 1;
+        write_crate(&missing, b"crate data")
+    })
+    .unwrap_err();
+    assert_eq!(attempts, 1);
+    assert!(err.to_string().starts_with("failed to open"), "{err}");
+}
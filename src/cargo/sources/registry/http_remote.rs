@@ -8,11 +8,13 @@ use crate::sources::registry::download;
 use crate::sources::registry::MaybeLock;
 use crate::sources::registry::{LoadResponse, RegistryConfig, RegistryData};
 use crate::util::errors::{CargoResult, HttpNotSuccessful};
-use crate::util::network::Retry;
+use crate::util::network;
+use crate::util::network::retry::{Retry, RetryBuilder, RetryResult};
+use crate::util::network::sleep::SleepTracker;
 use crate::util::{auth, Config, Filesystem, IntoUrl, Progress, ProgressStyle};
 use anyhow::Context;
 use cargo_util::paths;
-use curl::easy::{HttpVersion, List};
+use curl::easy::{Easy, HttpVersion, List};
 use curl::multi::{EasyHandle, Multi};
 use log::{debug, trace, warn};
 use std::cell::RefCell;
@@ -31,6 +33,7 @@ const LAST_MODIFIED: &'static str = "last-modified";
 const WWW_AUTHENTICATE: &'static str = "www-authenticate";
 const IF_NONE_MATCH: &'static str = "if-none-match";
 const IF_MODIFIED_SINCE: &'static str = "if-modified-since";
+const CACHE_CONTROL: &'static str = "cache-control";
 
 const UNKNOWN: &'static str = "Unknown";
 
@@ -98,8 +101,10 @@ pub struct Downloads<'cfg> {
     /// finished.
     pending: HashMap<usize, (Download<'cfg>, EasyHandle)>,
     /// Set of paths currently being downloaded.
-    /// This should stay in sync with `pending`.
+    /// This should stay in sync with the `pending` field and `sleeping`.
     pending_paths: HashSet<PathBuf>,
+    /// Downloads that have failed and are waiting to retry again later.
+    sleeping: SleepTracker<(Download<'cfg>, Easy)>,
     /// The final result of each download.
     results: HashMap<PathBuf, CargoResult<CompletedDownload>>,
     /// The next ID to use for creating a token (see `Download::token`).
@@ -111,6 +116,8 @@ pub struct Downloads<'cfg> {
     /// Number of times the caller has requested blocking. This is used for
     /// an estimate of progress.
     blocking_calls: usize,
+    /// `Warning` headers from the registry that have already been shown.
+    warnings_shown: HashSet<String>,
 }
 
 struct Download<'cfg> {
@@ -127,6 +134,13 @@ struct Download<'cfg> {
     /// HTTP headers.
     header_map: RefCell<Headers>,
 
+    /// The headers sent with the request, kept so a retry can add to them.
+    request_headers: Vec<String>,
+
+    /// Whether we have a cached copy of the file, so that the server can
+    /// answer with `304 Not Modified`.
+    cached: bool,
+
     /// Logic used to track retrying this download if it's a spurious failure.
     retry: Retry<'cfg>,
 }
@@ -136,6 +150,8 @@ struct Headers {
     last_modified: Option<String>,
     etag: Option<String>,
     www_authenticate: Vec<String>,
+    /// All headers, as `name: value` lines.
+    all: Vec<String>,
 }
 
 enum StatusCode {
@@ -181,6 +197,7 @@ impl<'cfg> HttpRegistry<'cfg> {
                 next: 0,
                 pending: HashMap::new(),
                 pending_paths: HashSet::new(),
+                sleeping: SleepTracker::new(),
                 results: HashMap::new(),
                 progress: RefCell::new(Some(Progress::with_style(
                     "Fetch",
@@ -189,6 +206,7 @@ impl<'cfg> HttpRegistry<'cfg> {
                 ))),
                 downloads_finished: 0,
                 blocking_calls: 0,
+                warnings_shown: HashSet::new(),
             },
             fresh: HashSet::new(),
             requested_update: false,
@@ -240,7 +258,7 @@ impl<'cfg> HttpRegistry<'cfg> {
 
     fn handle_completed_downloads(&mut self) -> CargoResult<()> {
         assert_eq!(
-            self.downloads.pending.len(),
+            self.downloads.pending.len() + self.downloads.sleeping.len(),
             self.downloads.pending_paths.len()
         );
 
@@ -262,39 +280,104 @@ impl<'cfg> HttpRegistry<'cfg> {
             let mut handle = self.multi.remove(handle)?;
             let data = download.data.take();
             let url = self.full_url(&download.path);
+            network::warn_from_headers(
+                self.config,
+                &url,
+                &download.header_map.borrow().all,
+                &mut self.downloads.warnings_shown,
+            )?;
+            download.retry.set_bytes_received(data.len() as u64);
+            let dns_lookup_time = handle.namelookup_time();
+            if let Ok(time) = dns_lookup_time {
+                download.retry.set_dns_lookup_time(time);
+            }
+            crate::try_old_curl!(dns_lookup_time, "DNS lookup timing");
+            let mut bad_not_modified = false;
             let result = match download.retry.r#try(|| {
                 result.with_context(|| format!("failed to download from `{}`", url))?;
                 let code = handle.response_code()?;
                 // Keep this list of expected status codes in sync with the codes handled in `load`
                 let code = match code {
                     200 => StatusCode::Success,
-                    304 => StatusCode::NotModified,
+                    304 if download.cached => StatusCode::NotModified,
+                    304 => {
+                        // We didn't ask for a conditional response, so a cache
+                        // between us and the server answered for a file it
+                        // doesn't have. This is retried as spurious.
+                        bad_not_modified = true;
+                        let effective_url = handle.effective_url()?.unwrap_or(&url);
+                        return Err(anyhow::Error::from(HttpNotSuccessful {
+                            code,
+                            method: "GET".to_string(),
+                            url: effective_url.to_owned(),
+                            body: data,
+                            headers: download.header_map.borrow().all.clone(),
+                            redirected_from: (effective_url != url).then(|| url.clone()),
+                        })
+                        .context(
+                            "server said not modified (HTTP 304) when no local cache exists",
+                        ));
+                    }
                     401 => StatusCode::Unauthorized,
                     404 | 410 | 451 => StatusCode::NotFound,
                     code => {
-                        let url = handle.effective_url()?.unwrap_or(&url);
+                        let effective_url = handle.effective_url()?.unwrap_or(&url);
                         return Err(HttpNotSuccessful {
                             code,
-                            url: url.to_owned(),
+                            method: "GET".to_string(),
+                            url: effective_url.to_owned(),
                             body: data,
+                            headers: download.header_map.borrow().all.clone(),
+                            redirected_from: (effective_url != url).then(|| url.clone()),
                         }
                         .into());
                     }
                 };
                 Ok((data, code))
             }) {
-                Ok(Some((data, code))) => Ok(CompletedDownload {
-                    response_code: code,
-                    data,
-                    header_map: download.header_map.take(),
-                }),
-                Ok(None) => {
-                    // retry the operation
-                    let handle = self.multi.add(handle)?;
-                    self.downloads.pending.insert(token, (download, handle));
+                RetryResult::Success((data, code)) => {
+                    let header_map = download.header_map.take();
+                    if let Some(delay) = network::retry_after(&header_map.all) {
+                        // Pacing for anyone polling the index, see `PollWait`.
+                        self.config.retry_session().record_poll_after(delay);
+                    }
+                    Ok(CompletedDownload {
+                        response_code: code,
+                        data,
+                        header_map,
+                    })
+                }
+                RetryResult::Err(e) => Err(e),
+                RetryResult::Retry(sleep) => {
+                    debug!("download retry {:?} for {:?}", download.path, sleep);
+                    if let Some(url) = download.retry.rewritten_url() {
+                        debug!("retrying {:?} at {}", download.path, url);
+                        handle.url(&url)?;
+                    }
+                    download.retry.configure_handle(&mut handle, 0)?;
+                    let connect_timeout = ops::HttpTimeout::new(self.config)?.dur;
+                    if let Some(timeouts) = download.retry.attempt_timeouts(sleep, connect_timeout)
+                    {
+                        // Don't let the retry run past the `--network-deadline`.
+                        timeouts.configure(&mut handle)?;
+                    }
+                    if bad_not_modified
+                        && !download
+                            .request_headers
+                            .iter()
+                            .any(|h| h.starts_with(CACHE_CONTROL))
+                    {
+                        // Ask the caches along the way to go to the server,
+                        // rather than answering from what they have.
+                        download
+                            .request_headers
+                            .push(format!("{}: no-cache", CACHE_CONTROL));
+                        handle.http_headers(header_list(&download.request_headers)?)?;
+                    }
+                    download.header_map.take();
+                    self.downloads.sleeping.push(sleep, (download, handle));
                     continue;
                 }
-                Err(e) => Err(e),
             };
 
             assert!(self.downloads.pending_paths.remove(&download.path));
@@ -307,6 +390,17 @@ impl<'cfg> HttpRegistry<'cfg> {
         Ok(())
     }
 
+    /// Re-adds the downloads whose retry delay has passed to the `Multi`
+    /// handle.
+    fn add_sleepers(&mut self) -> CargoResult<()> {
+        for (dl, handle) in self.downloads.sleeping.to_retry() {
+            let mut handle = self.multi.add(handle)?;
+            handle.set_token(dl.token)?;
+            self.downloads.pending.insert(dl.token, (dl, handle));
+        }
+        Ok(())
+    }
+
     fn full_url(&self, path: &Path) -> String {
         // self.url always ends with a slash.
         format!("{}{}", self.url, path.display())
@@ -474,11 +568,8 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
                 }
                 StatusCode::NotModified => {
                     // Not Modified: the data in the cache is still the latest.
-                    if index_version.is_none() {
-                        return Poll::Ready(Err(anyhow::anyhow!(
-                            "server said not modified (HTTP 304) when no local cache exists"
-                        )));
-                    }
+                    // A 304 without a local cache is turned into an error by
+                    // `handle_completed_downloads`.
                     return Poll::Ready(Ok(LoadResponse::CacheValid));
                 }
                 StatusCode::NotFound => {
@@ -518,7 +609,10 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
                     let err = Err(HttpNotSuccessful {
                         code: 401,
                         body: result.data,
+                        method: "GET".to_string(),
                         url: self.full_url(path),
+                        headers: result.header_map.all,
+                        redirected_from: None,
                     }
                     .into());
                     if self.auth_required {
@@ -558,11 +652,14 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
         handle.url(&full_url)?;
         handle.follow_location(true)?;
 
-        // Enable HTTP/2 if possible.
-        if self.multiplexing {
-            crate::try_old_curl!(handle.http_version(HttpVersion::V2), "HTTP2");
-        } else {
-            handle.http_version(HttpVersion::V11)?;
+        // Enable HTTP/2 if possible, unless `http.version` already picked
+        // one for `http_handle`.
+        if ops::pinned_http_version(self.config)?.is_none() {
+            if self.multiplexing {
+                crate::try_old_curl!(handle.http_version(HttpVersion::V2), "HTTP2");
+            } else {
+                handle.http_version(HttpVersion::V11)?;
+            }
         }
 
         // This is an option to `libcurl` which indicates that if there's a
@@ -574,19 +671,19 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
         // reduces the number of connections done to a more manageable state.
         crate::try_old_curl!(handle.pipewait(true), "pipewait");
 
-        let mut headers = List::new();
+        let mut headers = Vec::new();
         // Include a header to identify the protocol. This allows the server to
         // know that Cargo is attempting to use the sparse protocol.
-        headers.append("cargo-protocol: version=1")?;
-        headers.append("accept: text/plain")?;
+        headers.push("cargo-protocol: version=1".to_string());
+        headers.push("accept: text/plain".to_string());
 
         // If we have a cached copy of the file, include IF_NONE_MATCH or IF_MODIFIED_SINCE header.
         if let Some(index_version) = index_version {
             if let Some((key, value)) = index_version.split_once(':') {
                 match key {
-                    ETAG => headers.append(&format!("{}: {}", IF_NONE_MATCH, value.trim()))?,
+                    ETAG => headers.push(format!("{}: {}", IF_NONE_MATCH, value.trim())),
                     LAST_MODIFIED => {
-                        headers.append(&format!("{}: {}", IF_MODIFIED_SINCE, value.trim()))?
+                        headers.push(format!("{}: {}", IF_MODIFIED_SINCE, value.trim()))
                     }
                     _ => debug!("unexpected index version: {}", index_version),
                 }
@@ -595,10 +692,10 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
         if self.auth_required {
             let authorization =
                 auth::auth_token(self.config, &self.source_id, self.login_url.as_ref(), None)?;
-            headers.append(&format!("Authorization: {}", authorization))?;
+            headers.push(format!("Authorization: {}", authorization));
             trace!("including authorization for {}", full_url);
         }
-        handle.http_headers(headers)?;
+        handle.http_headers(header_list(&headers)?)?;
 
         // We're going to have a bunch of downloads all happening "at the same time".
         // So, we need some way to track what headers/data/responses are for which request.
@@ -631,10 +728,21 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
 
         // And ditto for the header function.
         handle.header_function(move |buf| {
+            // A status line starts a new response, such as the one a redirect
+            // leads to, so only keep the headers of the final response.
+            if buf.starts_with(b"HTTP/") {
+                tls::with(|downloads| {
+                    if let Some(downloads) = downloads {
+                        downloads.pending[&token].0.header_map.take();
+                    }
+                });
+                return true;
+            }
             if let Some((tag, value)) = Self::handle_http_header(buf) {
                 tls::with(|downloads| {
                     if let Some(downloads) = downloads {
                         let mut header_map = downloads.pending[&token].0.header_map.borrow_mut();
+                        header_map.all.push(format!("{tag}: {value}"));
                         match tag.to_ascii_lowercase().as_str() {
                             LAST_MODIFIED => header_map.last_modified = Some(value.to_string()),
                             ETAG => header_map.etag = Some(value.to_string()),
@@ -653,7 +761,12 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
             path: path.to_path_buf(),
             data: RefCell::new(Vec::new()),
             header_map: Default::default(),
-            retry: Retry::new(self.config)?,
+            request_headers: headers,
+            cached: index_version.is_some(),
+            retry: RetryBuilder::new(self.config)
+                .url(full_url)
+                .spurious_not_modified()
+                .build()?,
         };
 
         // Finally add the request we've lined up to the pool of requests that cURL manages.
@@ -719,6 +832,7 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
 
         loop {
             self.handle_completed_downloads()?;
+            self.add_sleepers()?;
 
             let remaining_in_multi = tls::set(&self.downloads, || {
                 self.multi
@@ -727,19 +841,25 @@ impl<'cfg> RegistryData for HttpRegistry<'cfg> {
             })?;
             trace!("{} transfers remaining", remaining_in_multi);
 
-            if remaining_in_multi == 0 {
+            if remaining_in_multi + self.downloads.sleeping.len() as u32 == 0 {
                 return Ok(());
             }
 
-            // We have no more replies to provide the caller with,
-            // so we need to wait until cURL has something new for us.
-            let timeout = self
-                .multi
-                .get_timeout()?
-                .unwrap_or_else(|| Duration::new(1, 0));
-            self.multi
-                .wait(&mut [], timeout)
-                .with_context(|| "failed to wait on curl `Multi`")?;
+            if self.downloads.pending.is_empty() {
+                let delay = self.downloads.sleeping.time_to_next().unwrap();
+                debug!("sleeping main thread for {:?}", delay);
+                std::thread::sleep(delay);
+            } else {
+                // We have no more replies to provide the caller with,
+                // so we need to wait until cURL has something new for us.
+                let timeout = self
+                    .multi
+                    .get_timeout()?
+                    .unwrap_or_else(|| Duration::new(1, 0));
+                self.multi
+                    .wait(&mut [], timeout)
+                    .with_context(|| "failed to wait on curl `Multi`")?;
+            }
         }
     }
 }
@@ -774,6 +894,15 @@ impl<'cfg> Downloads<'cfg> {
     }
 }
 
+/// Turns header lines into the list curl wants.
+fn header_list(headers: &[String]) -> CargoResult<List> {
+    let mut list = List::new();
+    for header in headers {
+        list.append(header)?;
+    }
+    Ok(list)
+}
+
 mod tls {
     use super::Downloads;
     use std::cell::Cell;
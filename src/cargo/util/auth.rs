@@ -1,5 +1,6 @@
 //! Registry authentication support.
 
+use crate::util::network::retry::{RetryBuilder, RetryResult};
 use crate::util::{config, config::ConfigKey, CanonicalUrl, CargoResult, Config, IntoUrl};
 use anyhow::{bail, format_err, Context as _};
 use cargo_util::ProcessError;
@@ -9,7 +10,7 @@ use pasetors::paserk::FormatAsPaserk;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
@@ -664,11 +665,59 @@ pub fn logout(config: &Config, sid: &SourceId) -> CargoResult<()> {
     Ok(())
 }
 
+/// The exit code a credential process can use to say that it failed
+/// temporarily and should be run again, `EX_TEMPFAIL` from `sysexits.h`.
+const CREDENTIAL_PROCESS_TEMPFAIL: i32 = 75;
+
+/// Runs the credential process, retrying it if it fails temporarily.
+///
+/// Credential processes may need the network themselves, so a process that
+/// exits with [`CREDENTIAL_PROCESS_TEMPFAIL`], or that we fail to talk to
+/// because of an interrupted pipe or the like, is retried with the usual
+/// network retry backoff. Any other failure, such as denied access, is
+/// returned right away.
 fn run_command(
     config: &Config,
     process: &(PathBuf, Vec<String>),
     sid: &SourceId,
     action: Action,
+) -> CargoResult<Option<(bool, String)>> {
+    let mut retry = RetryBuilder::new(config)
+        .classifier(credential_process_is_spurious)
+        .operation(format!("credential process `{}`", process.0.display()))
+        .build()?;
+    loop {
+        match retry.r#try(|| run_command_once(config, process, sid, &action)) {
+            RetryResult::Success(token) => return Ok(token),
+            RetryResult::Err(e) => return Err(e),
+            RetryResult::Retry(delay) => retry.sleep(delay),
+        }
+    }
+}
+
+fn credential_process_is_spurious(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<ProcessError>() {
+            e.code == Some(CREDENTIAL_PROCESS_TEMPFAIL)
+        } else if let Some(e) = e.downcast_ref::<io::Error>() {
+            matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+            )
+        } else {
+            false
+        }
+    })
+}
+
+fn run_command_once(
+    config: &Config,
+    process: &(PathBuf, Vec<String>),
+    sid: &SourceId,
+    action: &Action,
 ) -> CargoResult<Option<(bool, String)>> {
     let index_url = sid.url().as_str();
     let cred_proc;
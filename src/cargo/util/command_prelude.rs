@@ -238,6 +238,14 @@ pub trait CommandExt: Sized {
         ))
     }
 
+    fn arg_retry(self) -> Self {
+        self._arg(
+            opt("retry", "Number of times to retry network errors")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32)),
+        )
+    }
+
     fn arg_quiet(self) -> Self {
         self._arg(flag("quiet", "Do not print cargo log messages").short('q'))
     }
@@ -61,9 +61,10 @@ use std::io::prelude::*;
 use std::io::{self, SeekFrom};
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Once;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use self::ConfigValue as CV;
 use crate::core::compiler::rustdoc::RustdocExternMap;
@@ -72,6 +73,8 @@ use crate::core::{features, CliUnstable, Shell, SourceId, Workspace, WorkspaceRo
 use crate::ops::{self, RegistryCredentialConfig};
 use crate::util::auth::Secret;
 use crate::util::errors::CargoResult;
+use crate::util::network::retry::{MetricsSink, NoopMetricsSink, RetrySession, UrlRewrite};
+use crate::util::network::retry_message::RetryMessageTemplate;
 use crate::util::validate_package_name;
 use crate::util::CanonicalUrl;
 use crate::util::{internal, toml as cargo_toml};
@@ -81,7 +84,7 @@ use cargo_util::paths;
 use curl::easy::Easy;
 use lazycell::LazyCell;
 use serde::de::IntoDeserializer as _;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use toml_edit::Item;
 use url::Url;
 
@@ -208,6 +211,23 @@ pub struct Config {
     /// Cache of credentials from configuration or credential providers.
     /// Maps from url to credential value.
     credential_cache: LazyCell<RefCell<HashMap<CanonicalUrl, CredentialCacheValue>>>,
+    /// Retry state shared by all network operations.
+    retry_session: LazyCell<RefCell<RetrySession>>,
+    /// The `net.retry-profile` entry that applies to the running command.
+    retry_profile: Option<String>,
+    /// The number of network retries from `--retry`, over any config.
+    retry_override: Option<u32>,
+    /// Retry settings made in code, see [`Config::set_net_retry`].
+    net_retry_settings: NetRetrySettings,
+    /// Where network retries are counted, see [`Config::set_retry_metrics`].
+    retry_metrics: Rc<dyn MetricsSink>,
+    /// Picks the URL for each retry, see [`Config::set_retry_url_rewrite`].
+    retry_url_rewrite: Option<Rc<dyn UrlRewrite>>,
+    /// When network operations stop being retried, from `--network-deadline`.
+    network_deadline: Option<Instant>,
+    /// Whether needing any network retry fails the command, from
+    /// `--strict-network`.
+    strict_network: bool,
     /// Lock, if held, of the global package cache along with the number of
     /// acquisitions so far.
     package_cache_lock: RefCell<Option<(Option<FileLock>, usize)>>,
@@ -306,6 +326,14 @@ impl Config {
             upper_case_env,
             updated_sources: LazyCell::new(),
             credential_cache: LazyCell::new(),
+            retry_session: LazyCell::new(),
+            retry_profile: None,
+            retry_override: None,
+            net_retry_settings: NetRetrySettings::default(),
+            retry_metrics: Rc::new(NoopMetricsSink),
+            retry_url_rewrite: None,
+            network_deadline: None,
+            strict_network: false,
             package_cache_lock: RefCell::new(None),
             http_config: LazyCell::new(),
             future_incompat_config: LazyCell::new(),
@@ -495,6 +523,13 @@ impl Config {
             .borrow_mut()
     }
 
+    /// Retry state shared by all network operations of this invocation.
+    pub fn retry_session(&self) -> RefMut<'_, RetrySession> {
+        self.retry_session
+            .borrow_with(|| RefCell::new(RetrySession::default()))
+            .borrow_mut()
+    }
+
     /// Gets all config values from disk.
     ///
     /// This will lazy-load the values as necessary. Callers are responsible
@@ -529,6 +564,121 @@ impl Config {
         }
     }
 
+    /// Selects the `net.retry-profile` entry used by network retries, for
+    /// commands like `cargo install` that users may want to retry
+    /// differently.
+    pub fn set_retry_profile(&mut self, command: impl Into<String>) {
+        self.retry_profile = Some(command.into());
+    }
+
+    /// The `net.retry-profile` entry selected by the running command.
+    pub fn retry_profile(&self) -> Option<&str> {
+        self.retry_profile.as_deref()
+    }
+
+    /// Sets the number of network retries, taking precedence over `net.retry`
+    /// and everything else that configures it, for `--retry`.
+    pub fn set_retry_override(&mut self, retries: u32) {
+        self.retry_override = Some(retries);
+    }
+
+    /// The number of network retries set with
+    /// [`Config::set_retry_override`], if any.
+    pub fn retry_override(&self) -> Option<u32> {
+        self.retry_override
+    }
+
+    /// Sets the number of network retries, for programs that use cargo as a
+    /// library and configure it in code.
+    ///
+    /// This takes precedence over all of the config that sets the number of
+    /// retries, including `net.retry-hosts` tables, except `net.no-retry`.
+    /// [`Config::set_retry_override`] still takes precedence over it.
+    pub fn set_net_retry(&mut self, retries: u32) {
+        self.net_retry_settings.retry = Some(retries);
+    }
+
+    /// Sets the first delay before retrying a network operation, over
+    /// `net.retry-backoff` and the `retry-backoff` of `net.retry-hosts`
+    /// tables.
+    pub fn set_net_retry_backoff(&mut self, backoff: Duration) {
+        self.net_retry_settings.backoff = Some(backoff);
+    }
+
+    /// Sets the first delay after a 502, over `net.retry-502-backoff` and
+    /// the `retry-502-backoff` of `net.retry-hosts` tables.
+    pub fn set_net_retry_502_backoff(&mut self, backoff: Duration) {
+        self.net_retry_settings.backoff_502 = Some(backoff);
+    }
+
+    /// Sets the longest delay between two attempts, over
+    /// `net.retry-max-backoff`.
+    pub fn set_net_retry_max_backoff(&mut self, backoff: Duration) {
+        self.net_retry_settings.max_backoff = Some(backoff);
+    }
+
+    /// The retry settings made with [`Config::set_net_retry`] and the other
+    /// setters next to it.
+    pub fn net_retry_settings(&self) -> &NetRetrySettings {
+        &self.net_retry_settings
+    }
+
+    /// Sends the numbers about network retries to `sink`, for programs that
+    /// use cargo as a library and collect their own metrics.
+    pub fn set_retry_metrics(&mut self, sink: Rc<dyn MetricsSink>) {
+        self.retry_metrics = sink;
+    }
+
+    /// Where network retries are counted, see [`Config::set_retry_metrics`].
+    pub fn retry_metrics(&self) -> &dyn MetricsSink {
+        &*self.retry_metrics
+    }
+
+    /// Has every download that is retried pick the URL for each retry with
+    /// `rewrite`, for programs that use cargo as a library against registry
+    /// deployments that move between retries.
+    ///
+    /// This is the [`RetryBuilder::rewrite_url`] of every [`Retry`] made
+    /// with this config, unless the builder is given one of its own.
+    ///
+    /// [`RetryBuilder::rewrite_url`]: crate::util::network::retry::RetryBuilder::rewrite_url
+    /// [`Retry`]: crate::util::network::retry::Retry
+    pub fn set_retry_url_rewrite(&mut self, rewrite: Rc<dyn UrlRewrite>) {
+        self.retry_url_rewrite = Some(rewrite);
+    }
+
+    /// The URL rewrite for retries, if any, see
+    /// [`Config::set_retry_url_rewrite`].
+    pub fn retry_url_rewrite(&self) -> Option<Rc<dyn UrlRewrite>> {
+        self.retry_url_rewrite.clone()
+    }
+
+    /// Sets the point in time after which network operations are no longer
+    /// retried, and transfers are cut off, for `--network-deadline`.
+    pub fn set_network_deadline(&mut self, deadline: Instant) {
+        self.network_deadline = Some(deadline);
+    }
+
+    /// See [`Config::set_network_deadline`].
+    pub fn network_deadline(&self) -> Option<Instant> {
+        self.network_deadline
+    }
+
+    /// Makes the command fail once it is done if any network operation
+    /// needed a retry, as with `net.retry-strict`, for `--strict-network`.
+    pub fn set_strict_network(&mut self) {
+        self.strict_network = true;
+    }
+
+    /// Whether `--strict-network` or `net.retry-strict` is in effect, see
+    /// [`check_strict_retries`](crate::util::network::retry::check_strict_retries).
+    pub fn strict_network(&self) -> CargoResult<bool> {
+        if self.strict_network {
+            return Ok(true);
+        }
+        Ok(self.net_config()?.retry_strict == Some(true))
+    }
+
     /// Sets the path where ancestor config file searching will stop. The
     /// given path is included, but its ancestors are not.
     pub fn set_search_stop_path<P: Into<PathBuf>>(&mut self, path: P) {
@@ -2334,6 +2484,17 @@ pub struct CargoHttpConfig {
     pub debug: Option<bool>,
     pub multiplexing: Option<bool>,
     pub ssl_version: Option<SslVersionConfig>,
+    pub version: Option<HttpVersionConfig>,
+}
+
+/// Configuration for `http.version`: the HTTP version to use for every
+/// request, instead of the one picked from `http.multiplexing`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum HttpVersionConfig {
+    #[serde(rename = "1.1")]
+    Http11,
+    #[serde(rename = "2")]
+    Http2,
 }
 
 #[derive(Debug, Default, Deserialize, PartialEq)]
@@ -2391,10 +2552,174 @@ pub struct SslVersionConfigRange {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CargoNetConfig {
+    #[serde(default, deserialize_with = "net_count")]
     pub retry: Option<u32>,
+    /// The number of retries to use when `net.retry` isn't set, so that a
+    /// config file higher up (such as in `$CARGO_HOME`) can provide a default
+    /// that is still distinguishable from an explicit `net.retry`.
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_default: Option<u32>,
+    /// The most retries to use under `--locked` or `--frozen`.
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_locked: Option<u32>,
+    /// Whether the retry backoff carries over between the operations on a
+    /// host.
+    pub retry_escalate: Option<bool>,
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_backoff_reset: Option<u32>,
+    /// Disables retries altogether, overriding `net.retry`. Unlike most
+    /// booleans this also accepts `0` and `1`, so `CARGO_NET_NO_RETRY=1`
+    /// works.
+    #[serde(default)]
+    #[serde(deserialize_with = "bool_or_int")]
+    pub no_retry: Option<bool>,
     pub offline: Option<bool>,
     pub git_fetch_with_cli: Option<bool>,
     pub ssh: Option<CargoSshConfig>,
+    pub partial_download: Option<PartialDownloadConfig>,
+    pub retry_mode: Option<RetryModeConfig>,
+    pub retry_max_redownload: Option<String>,
+    pub retry_warn_level: Option<RetryWarnLevel>,
+    /// How long identical retry warnings are suppressed for, in
+    /// milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_dedup_window: Option<u64>,
+    /// How many failed HTTP responses are kept for the end-of-run report.
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_report_capacity: Option<u32>,
+    pub retry_body_patterns: Option<Vec<String>>,
+    /// HTTP status codes, as strings since config lists can only hold
+    /// strings.
+    pub retry_on_4xx: Option<Vec<String>>,
+    pub retry_message: Option<RetryMessageTemplate>,
+    pub retry_adaptive: Option<bool>,
+    /// The longest delay followed with `retry_adaptive`, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_adaptive_max_backoff: Option<u64>,
+    pub retry_after_ms: Option<bool>,
+    /// The first delay before retrying, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_backoff: Option<u64>,
+    /// The first delay after a 502, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_502_backoff: Option<u64>,
+    pub retry_fast_first: Option<bool>,
+    /// The longest delay between two attempts, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_max_backoff: Option<u64>,
+    /// The shortest delay between two attempts following the backoff
+    /// schedule, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_min_backoff: Option<u64>,
+    /// Like `retry_max_backoff`, keyed by the names of the
+    /// [`SpuriousReason`](crate::util::network::retry::SpuriousReason)s they
+    /// apply to.
+    #[serde(default, deserialize_with = "net_millis_map")]
+    pub retry_max_backoff_for: Option<HashMap<String, u64>>,
+    /// The most retries for some
+    /// [`SpuriousReason`](crate::util::network::retry::SpuriousReason)s,
+    /// keyed by their names, over `retry`.
+    #[serde(default, deserialize_with = "net_count_map")]
+    pub retry_limits: Option<HashMap<String, u32>>,
+    pub retry_on: Option<RetryOnConfig>,
+    pub retry_ipv4_fallback: Option<bool>,
+    /// How many HTTP/2 stream errors in a row make the retries use HTTP/1.1.
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_http1_fallback: Option<u32>,
+    pub retry_verbose: Option<bool>,
+    pub retry_strict: Option<bool>,
+    /// The most retries an operation makes, whatever `retry` says.
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry_ceiling: Option<u32>,
+    /// Per-command overrides, keyed by the name the command selects with
+    /// [`Config::set_retry_profile`].
+    pub retry_profile: Option<HashMap<String, RetryProfileConfig>>,
+    /// Per-host overrides, keyed by host name, see
+    /// [`NetRetryPolicy::for_host`](crate::util::network::retry::NetRetryPolicy::for_host).
+    pub retry_hosts: Option<HashMap<String, RetryHostConfig>>,
+}
+
+/// Retry settings made in code rather than read from config, see
+/// [`Config::set_net_retry`].
+#[derive(Debug, Default, Clone)]
+pub struct NetRetrySettings {
+    pub retry: Option<u32>,
+    pub backoff: Option<Duration>,
+    pub backoff_502: Option<Duration>,
+    pub max_backoff: Option<Duration>,
+}
+
+/// Configuration for one `net.retry-profile.<command>` table.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryProfileConfig {
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry: Option<u32>,
+}
+
+/// Configuration for one `net.retry-hosts.<host>` table.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryHostConfig {
+    #[serde(default, deserialize_with = "net_count")]
+    pub retry: Option<u32>,
+    /// The first delay before retrying, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_backoff: Option<u64>,
+    /// The first delay after a 502, in milliseconds.
+    #[serde(default, deserialize_with = "net_millis")]
+    pub retry_502_backoff: Option<u64>,
+    pub retry_fast_first: Option<bool>,
+}
+
+/// Configuration for the `net.retry-on` table: which
+/// [`SpuriousReason`](crate::util::network::retry::SpuriousReason)s to retry,
+/// by name.
+#[derive(Debug, Deserialize)]
+pub struct RetryOnConfig {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+}
+
+/// Configuration for `net.partial-download`: what to do when a transfer ends
+/// before all of the data arrived.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PartialDownloadConfig {
+    /// Retry, asking the server for only the missing bytes where possible.
+    Resume,
+    /// Retry the whole transfer from the start.
+    #[default]
+    Retry,
+    /// Don't retry; fail right away.
+    Fail,
+}
+
+/// Configuration for `net.retry-mode`: when the failed operations of a batch
+/// are retried.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryModeConfig {
+    /// Each operation is retried on its own, as soon as its delay has passed.
+    #[default]
+    Inline,
+    /// The failed operations are retried together once the whole batch has
+    /// been tried, after a single delay.
+    Batched,
+}
+
+/// Configuration for `net.retry-warn-level`: how loudly each retry of a
+/// spurious network error is reported.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryWarnLevel {
+    /// Always shown, as a warning.
+    #[default]
+    Warn,
+    /// Shown as a note with `--verbose`.
+    Info,
+    /// Shown as a note with `-vv`, and logged at the debug level.
+    Debug,
 }
 
 #[derive(Debug, Deserialize)]
@@ -2555,6 +2880,138 @@ where
     deserializer.deserialize_option(ProgressVisitor)
 }
 
+/// Deserializes a boolean that may also be written as `0` or `1`.
+fn bool_or_int<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    struct BoolOrIntVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BoolOrIntVisitor {
+        type Value = Option<bool>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("true/false or 0/1")
+        }
+
+        fn visit_bool<E>(self, b: bool) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(Some(b))
+        }
+
+        fn visit_i64<E>(self, i: i64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            match i {
+                0 => Ok(Some(false)),
+                1 => Ok(Some(true)),
+                _ => Err(E::invalid_value(serde::de::Unexpected::Signed(i), &self)),
+            }
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: serde::de::Deserializer<'de>,
+        {
+            deserializer.deserialize_any(self)
+        }
+    }
+
+    deserializer.deserialize_option(BoolOrIntVisitor)
+}
+
+/// Deserializes an integer, with an error that shows the value found instead,
+/// for the `net` settings that are easy to get wrong, such as a delay written
+/// as `"1s"` rather than in milliseconds.
+struct IntegerVisitor<T> {
+    expected: &'static str,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T> IntegerVisitor<T> {
+    fn new(expected: &'static str) -> IntegerVisitor<T> {
+        IntegerVisitor {
+            expected,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'de, T: TryFrom<i64>> serde::de::Visitor<'de> for IntegerVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(self.expected)
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<T, E>
+    where
+        E: serde::de::Error,
+    {
+        T::try_from(i).map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(i), &self))
+    }
+}
+
+/// A number of retries, see [`IntegerVisitor`].
+struct NetCount(u32);
+
+impl<'de> Deserialize<'de> for NetCount {
+    fn deserialize<D: serde::de::Deserializer<'de>>(d: D) -> Result<NetCount, D::Error> {
+        d.deserialize_any(IntegerVisitor::new("a non-negative integer"))
+            .map(NetCount)
+    }
+}
+
+/// A delay in milliseconds, see [`IntegerVisitor`].
+struct NetMillis(u64);
+
+impl<'de> Deserialize<'de> for NetMillis {
+    fn deserialize<D: serde::de::Deserializer<'de>>(d: D) -> Result<NetMillis, D::Error> {
+        d.deserialize_any(IntegerVisitor::new("a number of milliseconds"))
+            .map(NetMillis)
+    }
+}
+
+fn net_count<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    Ok(Option::<NetCount>::deserialize(deserializer)?.map(|n| n.0))
+}
+
+fn net_millis<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    Ok(Option::<NetMillis>::deserialize(deserializer)?.map(|n| n.0))
+}
+
+fn net_count_map<'de, D>(deserializer: D) -> Result<Option<HashMap<String, u32>>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let map = Option::<HashMap<String, NetCount>>::deserialize(deserializer)?;
+    Ok(map.map(|map| map.into_iter().map(|(k, n)| (k, n.0)).collect()))
+}
+
+fn net_millis_map<'de, D>(deserializer: D) -> Result<Option<HashMap<String, u64>>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let map = Option::<HashMap<String, NetMillis>>::deserialize(deserializer)?;
+    Ok(map.map(|map| map.into_iter().map(|(k, n)| (k, n.0)).collect()))
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 enum EnvConfigValueInner {
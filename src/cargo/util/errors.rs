@@ -1,8 +1,10 @@
 #![allow(unknown_lints)]
 
 use anyhow::Error;
+use std::borrow::Cow;
 use std::fmt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use super::truncate_with_ellipsis;
 
@@ -11,8 +13,15 @@ pub type CargoResult<T> = anyhow::Result<T>;
 #[derive(Debug)]
 pub struct HttpNotSuccessful {
     pub code: u32,
+    /// The method of the request, such as `GET`.
+    pub method: String,
+    /// Where the failing response came from, after following any redirects.
     pub url: String,
     pub body: Vec<u8>,
+    /// Response headers, as raw `name: value` lines.
+    pub headers: Vec<String>,
+    /// The URL originally requested, if redirects led to a different `url`.
+    pub redirected_from: Option<String>,
 }
 
 impl fmt::Display for HttpNotSuccessful {
@@ -23,14 +32,358 @@ impl fmt::Display for HttpNotSuccessful {
 
         write!(
             f,
-            "failed to get successful HTTP response from `{}`, got {}\nbody:\n{body}",
-            self.url, self.code
-        )
+            "failed to get successful HTTP response: {} on {} `{}`",
+            self.code,
+            self.method,
+            redact_url(&self.url)
+        )?;
+        if let Some(original) = &self.redirected_from {
+            write!(f, ", redirected from `{}`", redact_url(original))?;
+        }
+        if !self.headers.is_empty() {
+            write!(f, "\nheaders:\n\t{}", self.headers.join("\n\t"))?;
+        }
+        write!(f, "\nbody:\n{body}")
+    }
+}
+
+/// Hides the parts of `url` that may hold secrets, such as the signature of
+/// a pre-signed URL that a registry redirected to.
+pub(crate) fn redact_url(url: &str) -> Cow<'_, str> {
+    let Ok(mut parsed) = url::Url::parse(url) else {
+        return Cow::Borrowed(url);
+    };
+    if parsed.password().is_none() && parsed.query().is_none() {
+        return Cow::Borrowed(url);
+    }
+    let _ = parsed.set_password(None);
+    let _ = parsed.set_username("");
+    let redacted_query = parsed.query().is_some();
+    parsed.set_query(None);
+    let mut url = String::from(parsed);
+    if redacted_query {
+        url.push_str("?[REDACTED]");
+    }
+    Cow::Owned(url)
+}
+
+impl HttpNotSuccessful {
+    /// The delay asked for by a `Retry-After` header, if the response had
+    /// one giving a number of seconds. The HTTP-date form isn't supported.
+    pub fn retry_after(&self) -> Option<Duration> {
+        super::network::retry_after(&self.headers)
+    }
+
+    /// The delay asked for by an `X-Retry-After-Ms` header, if the response
+    /// had one giving a number of milliseconds.
+    pub fn retry_after_ms(&self) -> Option<Duration> {
+        super::network::retry_after_ms(&self.headers)
+    }
+
+    /// Whether the server said it closes the connection after this response,
+    /// with a `Connection: close` header.
+    pub fn closes_connection(&self) -> bool {
+        super::network::connection_close(&self.headers)
     }
 }
 
 impl std::error::Error for HttpNotSuccessful {}
 
+/// Returned by [`Retry`](crate::util::network::retry::Retry) when its cancellation
+/// token was set while a network operation was being retried.
+#[derive(Debug)]
+pub struct RetryCancelled;
+
+impl fmt::Display for RetryCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("network operation was cancelled")
+    }
+}
+
+impl std::error::Error for RetryCancelled {}
+
+/// Returned by [`Retry`](crate::util::network::retry::Retry), wrapped around
+/// the last error, when retrying would go past the deadline set with
+/// `--network-deadline`.
+#[derive(Debug)]
+pub struct NetworkDeadlinePassed;
+
+impl fmt::Display for NetworkDeadlinePassed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not retrying, the deadline set with `--network-deadline` has passed")
+    }
+}
+
+/// A `206 Partial Content` response to a request for the rest of a download
+/// that ended before the end of the range it said it holds.
+///
+/// This is retried like a transfer that was cut short, asking for what is
+/// still missing; see [`append_range_response`](crate::util::network::append_range_response).
+#[derive(Debug)]
+pub struct ShortRangeResponse {
+    pub url: String,
+    /// The length of the range the response said it holds.
+    pub expected: u64,
+    /// How much of it arrived.
+    pub received: u64,
+}
+
+impl fmt::Display for ShortRangeResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "partial response from `{}` ended after {} of its {} bytes",
+            redact_url(&self.url),
+            self.received,
+            self.expected
+        )
+    }
+}
+
+impl std::error::Error for ShortRangeResponse {}
+
+/// Returned by [`download_with_retry`](crate::util::network::download::download_with_retry),
+/// wrapped around the last error, when the download kept failing with spurious
+/// errors until no retries were left.
+#[derive(Debug)]
+pub struct RetriesExhausted {
+    /// How many times the download was tried, including the first try.
+    pub attempts: u32,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = if self.attempts == 1 { "" } else { "s" };
+        write!(f, "gave up after {} attempt{s}", self.attempts)
+    }
+}
+
+/// The errors of every failed attempt of an operation, wrapped around the
+/// last one by [`with_retry_all_errors`](crate::util::network::retry::with_retry_all_errors).
+///
+/// They are listed in the order the attempts were made, each with its attempt
+/// number. A run of attempts that failed the same way is listed once, as in
+/// "attempt 2–4: ...".
+#[derive(Debug, Default)]
+pub struct RetryErrors {
+    /// The message of each failed attempt, in order.
+    errors: Vec<String>,
+}
+
+impl RetryErrors {
+    /// Adds the error of the attempt after the ones added so far.
+    pub fn push(&mut self, error: impl fmt::Display) {
+        self.errors.push(error.to_string());
+    }
+
+    /// How many failed attempts were added.
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for RetryErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all {} attempts failed:", self.errors.len())?;
+        let mut first = 0;
+        while first < self.errors.len() {
+            let error = &self.errors[first];
+            let run = self.errors[first..]
+                .iter()
+                .take_while(|e| *e == error)
+                .count();
+            let last = first + run;
+            if run == 1 {
+                write!(f, "\n  attempt {}: ", last)?;
+            } else {
+                write!(f, "\n  attempt {}–{}: ", first + 1, last)?;
+            }
+            // Keeps the lines of multi-line errors, such as the body of an
+            // HTTP response, under their attempt.
+            write!(f, "{}", error.replace('\n', "\n    "))?;
+            first = last;
+        }
+        Ok(())
+    }
+}
+
+/// Marks an error returned from a [`Retry`](crate::util::network::retry::Retry)
+/// callback as fatal, even if it looks like a spurious network error.
+///
+/// This is for callbacks that know more than the error itself shows, such as
+/// a 503 whose body says the resource is gone for good. `Retry` gives up right
+/// away and returns the wrapped error.
+pub struct NonRetryable {
+    inner: Error,
+}
+
+impl NonRetryable {
+    pub fn new(inner: Error) -> NonRetryable {
+        NonRetryable { inner }
+    }
+
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.inner
+    }
+}
+
+impl std::error::Error for NonRetryable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl fmt::Debug for NonRetryable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl fmt::Display for NonRetryable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// The opposite of [`NonRetryable`]: marks an error returned from a
+/// [`Retry`](crate::util::network::retry::Retry) callback as spurious, even if it
+/// normally isn't.
+///
+/// This is for errors that are expected to go away, such as a 404 while a
+/// newly published crate propagates through a registry's index. Once the
+/// retries run out, `Retry` returns the wrapped error.
+pub struct ForceRetryable {
+    inner: Error,
+}
+
+impl ForceRetryable {
+    pub fn new(inner: Error) -> ForceRetryable {
+        ForceRetryable { inner }
+    }
+
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.inner
+    }
+}
+
+impl std::error::Error for ForceRetryable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl fmt::Debug for ForceRetryable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl fmt::Display for ForceRetryable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// Marks an error returned from a [`Retry`](crate::util::network::retry::Retry)
+/// callback as the server rejecting credentials that may have expired, such
+/// as a short-lived token, so getting new ones could fix it.
+///
+/// With a [`RetryBuilder::refresh_credentials`] hook, `Retry` calls it and
+/// tries once more. Without one, or if the new credentials are rejected too,
+/// the wrapped error is returned right away, like any other authentication
+/// failure.
+///
+/// [`RetryBuilder::refresh_credentials`]: crate::util::network::retry::RetryBuilder::refresh_credentials
+pub struct CredentialsExpired {
+    inner: Error,
+}
+
+impl CredentialsExpired {
+    pub fn new(inner: Error) -> CredentialsExpired {
+        CredentialsExpired { inner }
+    }
+
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.inner
+    }
+}
+
+impl std::error::Error for CredentialsExpired {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl fmt::Debug for CredentialsExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl fmt::Display for CredentialsExpired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// Returned, wrapped around the actual error, when a
+/// [`Retry`](crate::util::network::retry::Retry) couldn't be set up, for
+/// example because of a malformed `net.retry` setting.
+///
+/// This tells such errors apart from the ones of the operation being retried,
+/// which it hasn't even started. It displays the same as the error it wraps.
+pub struct RetrySetupError {
+    inner: Error,
+}
+
+impl RetrySetupError {
+    pub fn new(inner: Error) -> RetrySetupError {
+        RetrySetupError { inner }
+    }
+
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.inner
+    }
+}
+
+impl std::error::Error for RetrySetupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl fmt::Debug for RetrySetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl fmt::Display for RetrySetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
 // =============================================================================
 // Verbose error
 
@@ -264,3 +617,44 @@ impl From<std::io::Error> for CliError {
 pub fn internal<S: fmt::Display>(error: S) -> anyhow::Error {
     InternalError::new(anyhow::format_err!("{}", error)).into()
 }
+
+#[test]
+fn http_not_successful_display() {
+    let err = HttpNotSuccessful {
+        code: 502,
+        method: "PUT".to_string(),
+        url: "https://example.com/upload?signature=secret".to_string(),
+        body: b"bad gateway".to_vec(),
+        headers: vec!["HTTP/1.1 502".to_string(), "Content-Length: 11".to_string()],
+        redirected_from: Some("https://registry.example.com/api/v1/crates/new".to_string()),
+    };
+    assert_eq!(
+        err.to_string(),
+        "failed to get successful HTTP response: 502 on PUT `https://example.com/upload?[REDACTED]`, \
+         redirected from `https://registry.example.com/api/v1/crates/new`\n\
+         headers:\n\
+         \tHTTP/1.1 502\n\
+         \tContent-Length: 11\n\
+         body:\n\
+         bad gateway"
+    );
+}
+
+#[test]
+fn retry_errors_display() {
+    let mut errors = RetryErrors::default();
+    errors.push("[7] Couldn't connect to server");
+    for _ in 0..3 {
+        errors.push("got 503\nbody:\nunavailable");
+    }
+    errors.push("[28] Timeout was reached");
+    errors.push("got 503\nbody:\nunavailable");
+    assert_eq!(
+        errors.to_string(),
+        "all 6 attempts failed:\n  \
+         attempt 1: [7] Couldn't connect to server\n  \
+         attempt 2–4: got 503\n    body:\n    unavailable\n  \
+         attempt 5: [28] Timeout was reached\n  \
+         attempt 6: got 503\n    body:\n    unavailable"
+    );
+}
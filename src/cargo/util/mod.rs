@@ -83,6 +83,22 @@ pub fn human_readable_bytes(bytes: u64) -> (f32, &'static str) {
     (bytes / 1024_f32.powi(i as i32), UNITS[i])
 }
 
+/// Parses a size such as `500MiB` into a number of bytes, the inverse of
+/// [`human_readable_bytes`]. A number without units is a number of bytes.
+pub fn parse_human_readable_bytes(s: &str) -> Option<u64> {
+    static UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (quantity, unit) = s.split_at(split);
+    let quantity: u64 = quantity.parse().ok()?;
+    let unit = unit.trim();
+    let power = match unit {
+        "" => 0,
+        unit => UNITS.iter().position(|u| u.eq_ignore_ascii_case(unit))?,
+    };
+    quantity.checked_mul(1024_u64.pow(power as u32))
+}
+
 pub fn iter_join_onto<W, I, T>(mut w: W, iter: I, delim: &str) -> fmt::Result
 where
     W: fmt::Write,
@@ -165,4 +181,24 @@ mod test {
         );
         assert_eq!(human_readable_bytes(u64::MAX), (16., "EiB"));
     }
+
+    #[test]
+    fn test_parse_human_readable_bytes() {
+        assert_eq!(parse_human_readable_bytes("0"), Some(0));
+        assert_eq!(parse_human_readable_bytes("1000"), Some(1000));
+        assert_eq!(parse_human_readable_bytes("8B"), Some(8));
+        assert_eq!(parse_human_readable_bytes("420 KiB"), Some(420 * 1024));
+        assert_eq!(
+            parse_human_readable_bytes("500MiB"),
+            Some(500 * 1024 * 1024)
+        );
+        assert_eq!(
+            parse_human_readable_bytes("2gib"),
+            Some(2 * 1024 * 1024 * 1024)
+        );
+        assert_eq!(parse_human_readable_bytes("16EiB"), None);
+        assert_eq!(parse_human_readable_bytes("1.5MiB"), None);
+        assert_eq!(parse_human_readable_bytes("MiB"), None);
+        assert_eq!(parse_human_readable_bytes("5 parsecs"), None);
+    }
 }
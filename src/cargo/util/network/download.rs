@@ -0,0 +1,181 @@
+//! Downloading a single file over HTTP, retrying spurious failures.
+//!
+//! This puts together what cargo's own downloaders do for each transfer: a
+//! GET request on a handle from [`ops::http_handle`], which honors the
+//! `[http]` settings, retried through a [`Retry`] set up for the URL's host,
+//! with an optional hook to check what was received before it is written
+//! out.
+//! It's meant for tools built on cargo that need to fetch something the same
+//! way cargo would, without its parallel machinery.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::Context as _;
+use cargo_util::paths;
+
+use crate::ops;
+use crate::util::errors::{CargoResult, HttpNotSuccessful, RetriesExhausted};
+use crate::util::network::retry::{self, Provenance, Retry, RetryBuilder, RetryResult, RetryStats};
+use crate::util::Config;
+
+/// Downloads `url` to `dest`, retrying spurious network errors as configured
+/// in `[net]`.
+///
+/// A response other than `200 OK` fails with [`HttpNotSuccessful`]. If the
+/// download still fails once the retries run out, the last error is wrapped
+/// in [`RetriesExhausted`]. Retries are sent to the URL picked by
+/// [`Config::set_retry_url_rewrite`], if there is one.
+pub fn download_with_retry(config: &Config, url: &str, dest: &Path) -> CargoResult<()> {
+    download_with_retry_checked(config, url, dest, |_| Ok(()))
+}
+
+/// Like [`download_with_retry_checked`], unless `dest` already holds a copy
+/// that passes `verify`, in which case nothing is downloaded.
+///
+/// A copy that fails `verify`, for example one cut short by an earlier,
+/// interrupted download, is downloaded again. The returned [`Provenance`]
+/// says which it was, and how many attempts the download took.
+pub fn download_with_retry_cached(
+    config: &Config,
+    url: &str,
+    dest: &Path,
+    verify: impl Fn(&[u8]) -> CargoResult<()>,
+) -> CargoResult<Provenance> {
+    let cached = || {
+        let data = match paths::read_bytes(dest) {
+            Ok(data) => data,
+            Err(e) if not_found(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(verify(&data).ok())
+    };
+    let (result, provenance) =
+        retry::cached_or_retried(cached, || download(config, url, dest, &verify))?;
+    result.map(|()| provenance)
+}
+
+/// Like [`download_with_retry`], calling `verify` on the data of every
+/// successful response before it is written to `dest`, for example to check
+/// its checksum.
+///
+/// An error from `verify` is returned as is, without retrying. Nothing is
+/// written to `dest` in that case.
+pub fn download_with_retry_checked(
+    config: &Config,
+    url: &str,
+    dest: &Path,
+    verify: impl FnMut(&[u8]) -> CargoResult<()>,
+) -> CargoResult<()> {
+    download(config, url, dest, verify).0
+}
+
+/// Downloads `url` to `dest` as [`download_with_retry_checked`] says, and
+/// returns how many attempts it took, both on success and on failure.
+///
+/// The data is written to a temporary file next to `dest` first, which then
+/// replaces it, so `dest` is never left half written.
+fn download(
+    config: &Config,
+    url: &str,
+    dest: &Path,
+    verify: impl FnMut(&[u8]) -> CargoResult<()>,
+) -> (CargoResult<()>, RetryStats) {
+    let mut retry = match RetryBuilder::new(config).url(url).build() {
+        Ok(retry) => retry,
+        Err(e) => return (Err(e), RetryStats::default()),
+    };
+    let result = download_on(&mut retry, config, url, dest, verify);
+    (result, retry.stats())
+}
+
+fn download_on(
+    retry: &mut Retry<'_>,
+    config: &Config,
+    url: &str,
+    dest: &Path,
+    mut verify: impl FnMut(&[u8]) -> CargoResult<()>,
+) -> CargoResult<()> {
+    let mut attempt_url = url.to_string();
+    let data = loop {
+        match retry.r#try(|| get(config, &attempt_url)) {
+            RetryResult::Success(data) => break data,
+            RetryResult::Err(e) => {
+                let e = if retry.ran_out_of_retries(&e) {
+                    e.context(RetriesExhausted {
+                        attempts: retry.stats().attempts,
+                    })
+                } else {
+                    e
+                };
+                return Err(e.context(format!("failed to download from `{}`", url)));
+            }
+            RetryResult::Retry(delay) => {
+                if let Some(next) = retry.rewritten_url() {
+                    attempt_url = next;
+                }
+                retry.sleep(delay);
+            }
+        }
+    };
+    verify(&data).with_context(|| format!("failed to verify the download from `{}`", url))?;
+    let dir = dest.parent().unwrap_or_else(|| Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create a temporary file in `{}`", dir.display()))?;
+    file.write_all(&data)
+        .and_then(|()| file.flush())
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    file.persist(dest)
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    Ok(())
+}
+
+/// Whether `err`, from reading a cached copy, says there is none.
+fn not_found(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Makes one attempt at downloading `url`.
+fn get(config: &Config, url: &str) -> CargoResult<Vec<u8>> {
+    let mut handle = ops::http_handle(config)?;
+    handle.get(true)?;
+    handle.url(url)?;
+    handle.follow_location(true)?;
+    let mut body = Vec::new();
+    let mut headers = Vec::new();
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|buf| {
+            body.extend_from_slice(buf);
+            Ok(buf.len())
+        })?;
+        transfer.header_function(|line| {
+            let header = String::from_utf8_lossy(line).trim().to_string();
+            // Only keep the headers of the response a redirect led to.
+            if header.starts_with("HTTP/") {
+                headers.clear();
+            }
+            if !header.is_empty() {
+                headers.push(header);
+            }
+            true
+        })?;
+        transfer.perform()?;
+    }
+    let code = handle.response_code()?;
+    if code != 200 {
+        let effective_url = handle.effective_url()?.unwrap_or(url);
+        return Err(HttpNotSuccessful {
+            code,
+            method: "GET".to_string(),
+            url: effective_url.to_string(),
+            body,
+            headers,
+            redirected_from: (effective_url != url).then(|| url.to_string()),
+        }
+        .into());
+    }
+    Ok(body)
+}
@@ -0,0 +1,277 @@
+//! Utilities for networking.
+
+use crate::util::errors::{CargoResult, ShortRangeResponse};
+use crate::util::Config;
+use std::collections::HashSet;
+use std::task::Poll;
+use std::time::Duration;
+
+pub mod download;
+pub mod proxy;
+pub mod retry;
+pub mod retry_message;
+pub mod sleep;
+
+pub trait PollExt<T> {
+    fn expect(self, msg: &str) -> T;
+}
+
+impl<T> PollExt<T> for Poll<T> {
+    #[track_caller]
+    fn expect(self, msg: &str) -> T {
+        match self {
+            Poll::Ready(val) => val,
+            Poll::Pending => panic!("{}", msg),
+        }
+    }
+}
+
+/// Emits the contents of any `Warning` headers in an HTTP response as cargo
+/// warnings.
+///
+/// Registries use this header to tell clients about things like deprecated
+/// API usage, so it is surfaced even when the request itself succeeded.
+/// `headers` are the raw `name: value` lines of the response, and `seen`
+/// tracks which warnings have already been shown so that a notice attached to
+/// every response from a registry is only printed once.
+pub fn warn_from_headers(
+    config: &Config,
+    url: &str,
+    headers: &[String],
+    seen: &mut HashSet<String>,
+) -> CargoResult<()> {
+    for header in headers {
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        if !name.trim().eq_ignore_ascii_case("warning") {
+            continue;
+        }
+        for text in parse_warning_header(value.trim()) {
+            if seen.insert(text.clone()) {
+                config
+                    .shell()
+                    .warn(format!("`{}` responded with a warning: {}", url, text))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The delay asked for by a `Retry-After` header among the raw `name: value`
+/// response `headers`, if there is one giving a number of seconds. The
+/// HTTP-date form isn't supported.
+pub fn retry_after(headers: &[String]) -> Option<Duration> {
+    headers.iter().find_map(|header| {
+        let (name, value) = header.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("retry-after") {
+            return None;
+        }
+        value.trim().parse().ok().map(Duration::from_secs)
+    })
+}
+
+/// The delay asked for by an `X-Retry-After-Ms` header among the raw
+/// `name: value` response `headers`, if there is one giving a number of
+/// milliseconds.
+///
+/// Some registries send this along with their errors to throttle clients
+/// with more precision than `Retry-After` allows.
+pub fn retry_after_ms(headers: &[String]) -> Option<Duration> {
+    headers.iter().find_map(|header| {
+        let (name, value) = header.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("x-retry-after-ms") {
+            return None;
+        }
+        value.trim().parse().ok().map(Duration::from_millis)
+    })
+}
+
+/// Whether a `Connection: close` header among the raw `name: value` response
+/// `headers` says the server closes the connection after this response.
+pub fn connection_close(headers: &[String]) -> bool {
+    headers.iter().any(|header| {
+        let Some((name, value)) = header.split_once(':') else {
+            return false;
+        };
+        name.trim().eq_ignore_ascii_case("connection")
+            && value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+    })
+}
+
+/// The length of the range a `206 Partial Content` response holds, from the
+/// `Content-Range` header among its raw `name: value` `headers`.
+fn content_range_len(headers: &[String]) -> Option<u64> {
+    headers.iter().find_map(|header| {
+        let (name, value) = header.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("content-range") {
+            return None;
+        }
+        let range = value.trim().strip_prefix("bytes ")?;
+        let (range, _total) = range.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+        end.checked_sub(start).map(|len| len + 1)
+    })
+}
+
+/// Adds the `data` of a `206 Partial Content` response to what the earlier
+/// attempts of a resumed download received in `resumed`, and returns the
+/// whole download.
+///
+/// If the response holds less than its `Content-Range` header says, this
+/// fails with a [`ShortRangeResponse`] instead, which is retried. What it
+/// did hold is kept in `resumed`, so the retry only asks for the rest.
+pub fn append_range_response(
+    resumed: &mut Vec<u8>,
+    data: &[u8],
+    headers: &[String],
+    url: &str,
+) -> CargoResult<Vec<u8>> {
+    resumed.extend_from_slice(data);
+    if let Some(expected) = content_range_len(headers) {
+        let received = data.len() as u64;
+        if received < expected {
+            return Err(ShortRangeResponse {
+                url: url.to_string(),
+                expected,
+                received,
+            }
+            .into());
+        }
+    }
+    Ok(std::mem::take(resumed))
+}
+
+/// Extracts the `warn-text` of each entry in a `Warning` header value.
+///
+/// The value is a comma-separated list of `warn-code warn-agent "warn-text"`
+/// entries, each optionally followed by a quoted date (RFC 7234). If the value
+/// doesn't follow that format, it is returned as-is so nothing is lost.
+fn parse_warning_header(value: &str) -> Vec<String> {
+    fn quoted(s: &str) -> Option<(String, &str)> {
+        let mut chars = s.strip_prefix('"')?.char_indices();
+        let mut text = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => text.push(chars.next()?.1),
+                '"' => return Some((text, &s[i + 2..])),
+                c => text.push(c),
+            }
+        }
+        None
+    }
+
+    fn parse(mut rest: &str) -> Option<Vec<String>> {
+        let mut texts = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if rest.is_empty() {
+                return Some(texts);
+            }
+            // Skip over the `warn-code` and `warn-agent`.
+            for _ in 0..2 {
+                let end = rest.find(char::is_whitespace)?;
+                rest = rest[end..].trim_start();
+            }
+            let (text, after) = quoted(rest)?;
+            texts.push(text);
+            rest = after.trim_start();
+            if rest.starts_with('"') {
+                // An optional `warn-date`, which isn't interesting to show.
+                rest = quoted(rest)?.1;
+            }
+        }
+    }
+
+    match parse(value) {
+        Some(texts) if !texts.is_empty() => texts,
+        _ => vec![value.to_string()],
+    }
+}
+
+// When dynamically linked against libcurl, we want to ignore some failures
+// when using old versions that don't support certain features.
+#[macro_export]
+macro_rules! try_old_curl {
+    ($e:expr, $msg:expr) => {
+        let result = $e;
+        if cfg!(target_os = "macos") {
+            if let Err(e) = result {
+                warn!("ignoring libcurl {} error: {}", $msg, e);
+            }
+        } else {
+            result.with_context(|| {
+                anyhow::format_err!("failed to enable {}, is curl not built right?", $msg)
+            })?;
+        }
+    };
+}
+
+/// Like [`try_old_curl!`] for several options set one after another, each
+/// given as an `(expr, msg)` pair.
+///
+/// The same leniency applies to all of them: on macOS each failure is logged
+/// and the rest are still set, elsewhere the first failure is returned.
+///
+/// ```no_run
+/// # use anyhow::Context;
+/// # use log::warn;
+/// # fn setup(handle: &mut curl::easy::Easy) -> cargo::CargoResult<()> {
+/// cargo::try_old_curl_all!(
+///     (handle.http_version(curl::easy::HttpVersion::V2), "HTTP2"),
+///     (handle.pipewait(true), "pipewait"),
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! try_old_curl_all {
+    ($(($e:expr, $msg:expr)),+ $(,)?) => {
+        $(
+            $crate::try_old_curl!($e, $msg);
+        )+
+    };
+}
+
+#[test]
+fn parses_warning_header_texts() {
+    assert_eq!(
+        parse_warning_header(r#"299 crates.io "this API is deprecated""#),
+        ["this API is deprecated"]
+    );
+    assert_eq!(
+        parse_warning_header(
+            r#"299 - "first" "Wed, 21 Oct 2015 07:28:00 GMT", 199 proxy "say \"hi\"""#
+        ),
+        ["first", r#"say "hi""#]
+    );
+    assert_eq!(parse_warning_header("not a warning"), ["not a warning"]);
+}
+
+#[test]
+fn short_range_responses_are_retried() {
+    use crate::core::Shell;
+    use retry::{Retry, RetryResult};
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let url = "https://static.crates.io/crates/foo/foo-1.0.0.crate";
+    let headers = |range: &str| vec![format!("Content-Range: bytes {range}/11")];
+    let mut retry = Retry::new(&config).unwrap();
+    let mut resumed = b"hello ".to_vec();
+
+    // The server says the response holds bytes 6 to 10 but sends two.
+    let result = retry.r#try(|| append_range_response(&mut resumed, b"wo", &headers("6-10"), url));
+    assert!(matches!(result, RetryResult::Retry(_)));
+    assert_eq!(resumed, b"hello wo");
+
+    let result = retry.r#try(|| append_range_response(&mut resumed, b"rld", &headers("8-10"), url));
+    let RetryResult::Success(data) = result else {
+        panic!("expected the download to be complete");
+    };
+    assert_eq!(data, b"hello world");
+    assert_eq!(retry.stats().attempts, 2);
+}
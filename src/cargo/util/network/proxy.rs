@@ -0,0 +1,52 @@
+//! Utilities for network proxies.
+
+use crate::util::config::Config;
+use crate::util::errors::CargoResult;
+
+/// Finds an explicit HTTP proxy if one is available.
+///
+/// Favor cargo's `http.proxy`, then git's `http.proxy`. Proxies specified
+/// via environment variables are picked up by libcurl.
+pub fn http_proxy(config: &Config) -> CargoResult<Option<String>> {
+    let http = config.http_config()?;
+    if let Some(s) = &http.proxy {
+        return Ok(Some(s.clone()));
+    }
+    if let Ok(cfg) = git2::Config::open_default() {
+        if let Ok(s) = cfg.get_string("http.proxy") {
+            return Ok(Some(s));
+        }
+    }
+    Ok(None)
+}
+
+/// The proxy to use, if any, from the same places as [`http_proxy_exists`]
+/// checks, in that order.
+pub fn http_proxy_url(config: &Config) -> CargoResult<Option<String>> {
+    if let Some(proxy) = http_proxy(config)? {
+        return Ok(Some(proxy));
+    }
+    Ok(["http_proxy", "HTTP_PROXY", "https_proxy", "HTTPS_PROXY"]
+        .iter()
+        .find_map(|v| config.get_env(v).ok()))
+}
+
+/// Determine if an http proxy exists.
+///
+/// Checks the following for existence, in order:
+///
+/// * cargo's `http.proxy`
+/// * git's `http.proxy`
+/// * `http_proxy` env var
+/// * `HTTP_PROXY` env var
+/// * `https_proxy` env var
+/// * `HTTPS_PROXY` env var
+pub fn http_proxy_exists(config: &Config) -> CargoResult<bool> {
+    if http_proxy(config)?.is_some() {
+        Ok(true)
+    } else {
+        Ok(["http_proxy", "HTTP_PROXY", "https_proxy", "HTTPS_PROXY"]
+            .iter()
+            .any(|v| config.get_env(v).is_ok()))
+    }
+}
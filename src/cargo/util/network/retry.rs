@@ -0,0 +1,6107 @@
+//! Utilities for retrying a network operation.
+//!
+//! Some network errors are considered "spurious", meaning it is not a real
+//! error (such as a 404 not found) and is likely a transient error (like a
+//! bad network connection) that we can hope will resolve itself shortly. The
+//! [`Retry`] type offers a way to repeatedly perform some kind of network
+//! operation with a delay if it detects one of these possibly transient
+//! errors.
+//!
+//! This supports errors from [`git2`], [`gix`], [`curl`], and
+//! [`HttpNotSuccessful`] 5xx, 408 and 429 HTTP errors (see
+//! [`spurious_reason_for_status`]), as well as the equivalent
+//! [`crates_io::ResponseError`]s from the registry web API. A callback that
+//! knows better can wrap its error in [`NonRetryable`] to stop it from being
+//! retried regardless, or in [`ForceRetryable`] to retry an error that
+//! otherwise wouldn't be. One that was turned down because its credentials
+//! may have expired can wrap its error in [`CredentialsExpired`], to be
+//! retried once with new credentials from a
+//! [`RetryBuilder::refresh_credentials`] hook.
+//!
+//! Operations that aren't safe to repeat once the server got the request can
+//! say so with [`RetryBuilder::idempotent`]; only errors from before the
+//! request was sent are retried for them (see [`RequestPhase`]).
+//!
+//! TLS handshake failures are retried at most [`MAX_SSL_RETRIES`] times per
+//! operation, since they rarely go away on their own; certificate problems
+//! aren't retried at all.
+//!
+//! The number of retries can be configured by the user via the `net.retry`
+//! config option. This indicates the number of times to retry the operation
+//! (default 2 times for a total of 3 attempts). When `net.retry` isn't set,
+//! `net.retry-default` is used instead, falling back to [`DEFAULT_RETRIES`].
+//! Commands can select a `net.retry-profile.<command>` table with
+//! [`Config::set_retry_profile`]; its `retry` takes precedence over
+//! `net.retry`, so for example `cargo install` can retry harder than builds.
+//! `net.no-retry` (`CARGO_NET_NO_RETRY=1`) overrides all of these and makes
+//! every operation fail on its first error, for CI that would rather surface
+//! flakiness than paper over it. The `--retry` flag of commands like
+//! `cargo fetch` overrides everything else, see [`Config::set_retry_override`].
+//!
+//! Between attempts there is an exponential backoff: the first retry waits
+//! roughly `net.retry-backoff`, [`INITIAL_BACKOFF`] by default, and each
+//! following retry waits twice as long as the previous one, up to
+//! [`MAX_BACKOFF`]. Each delay is randomized between half and all of that
+//! amount so that many clients failing at the same moment don't all retry in
+//! lockstep.
+//!
+//! Each retry is reported to the user; `net.retry-warn-level` decides whether
+//! that is a warning or a note only shown in verbose output, though an
+//! operation still retrying after [`ESCALATE_WARNINGS_AFTER`] is always warned
+//! about. The wording can be replaced with `net.retry-message`, see
+//! [`RetryMessageTemplate`].
+//!
+//! Registries that report temporary trouble with a non-5xx status can be
+//! accommodated with `net.retry-body-patterns`: an [`HttpNotSuccessful`]
+//! response whose body contains one of those phrases is also retried.
+//!
+//! Likewise, `net.retry-on-4xx` lists 4xx statuses to retry, for proxies
+//! that send those transiently. With `net.retry-verbose`, every error is
+//! reported along with how it was classified and by which of these rules.
+//!
+//! A transfer that was cut short (curl's `is_partial_file`) is handled
+//! according to `net.partial-download`: it is either retried from scratch
+//! (the default), resumed where it stopped (for downloaders that support it,
+//! see [`Retry::resumes_partial_downloads`]), or treated as a hard failure.
+//! When the downloader tells us how much data arrived (see
+//! [`Retry::set_bytes_received`]), a transfer that got cut short without any
+//! data twice in a row is given up on, since that is more likely a server
+//! advertising a length it never sends than a flaky connection. A resumed
+//! download whose `206 Partial Content` response ends early is retried too
+//! (see [`ShortRangeResponse`]), asking for what is still missing.
+//! Similarly, downloaders pass along curl's name lookup time (see
+//! [`Retry::set_dns_lookup_time`]) so that warnings about hosts that failed
+//! to resolve can say whether DNS was slow or failed right away. After an
+//! HTTP/2 stream error they also open a new connection for the retry (see
+//! [`Retry::wants_new_connection`]), rather than multiplexing it onto the
+//! connection that just failed, and likewise for every operation on a host
+//! whose last response said `Connection: close`. With
+//! `net.retry-ipv4-fallback`, retries after a failed connect only use IPv4
+//! (see [`Retry::wants_ipv4`]), and after
+//! `net.retry-http1-fallback` HTTP/2 stream errors in a row, they use
+//! HTTP/1.1 (see [`Retry::wants_http1`]).
+//!
+//! Every retry is tallied by its [`SpuriousReason`] in the [`RetrySession`]
+//! kept on [`Config`], which is summarized at the end of the command with
+//! `--verbose`. Programs using cargo as a library can also have retries
+//! reported to a [`MetricsSink`] of their own, and each operation and its
+//! attempts are `tracing` spans named `retry` and `attempt`.
+//!
+//! With `net.retry-escalate`, the backoff also carries over between
+//! operations on the same host: each one that needs a retry starts its
+//! backoff one doubling higher than the previous one did (see
+//! [`RetrySession::backoff_level`]), so a flaky server isn't hammered by
+//! every new request. Once `net.retry-backoff-reset` operations on that host
+//! in a row succeed, the backoff goes back to [`INITIAL_BACKOFF`].
+//!
+//! A `502 Bad Gateway` is retried on a new connection, on a schedule that
+//! starts from the shorter `net.retry-502-backoff` (see
+//! [`DEFAULT_502_BACKOFF`]), since the next CDN edge will often do fine.
+//!
+//! With `net.retry-adaptive`, the `Retry-After` delays each host sends with
+//! its errors are remembered in the [`RetrySession`], and retries against
+//! that host wait at least the median of them (up to
+//! `net.retry-adaptive-max-backoff`, see [`MAX_ADAPTIVE_BACKOFF`]) instead
+//! of ramping up from [`INITIAL_BACKOFF`] every time.
+//!
+//! With `net.retry-after-ms`, a response with an `X-Retry-After-Ms` header
+//! and no `Retry-After` is retried after exactly the delay it asks for, up to
+//! `net.retry-max-backoff`, for registries that throttle more precisely than
+//! whole seconds.
+//!
+//! `--network-deadline` puts a limit on the whole invocation instead (see
+//! [`Config::network_deadline`]): no retry is scheduled to start past it, and
+//! downloaders shorten the connect and transfer timeouts of their retries to
+//! end there (see [`Retry::attempt_timeouts`]).
+//!
+//! The backoff is only ever computed once an attempt has failed with a
+//! spurious error, so operations that succeed on the first try never touch
+//! the clock or the random number generator.
+
+use crate::util::config::{CargoNetConfig, PartialDownloadConfig, RetryWarnLevel};
+use crate::util::errors::{
+    CargoResult, CredentialsExpired, ForceRetryable, HttpNotSuccessful, NetworkDeadlinePassed,
+    NonRetryable, RetryCancelled, RetryErrors, RetrySetupError, ShortRangeResponse,
+};
+use crate::util::network::proxy::http_proxy_url;
+use crate::util::network::retry_message::{RetryMessageFields, RetryMessageTemplate};
+use crate::util::network::sleep::{Clock, Sleeper, SystemClock, ThreadSleep, ThreadSleeper};
+use crate::util::Config;
+use anyhow::Error;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+/// The number of retries when neither `net.retry` nor `net.retry-default` is
+/// set.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// The most retries under `--locked` or `--frozen`, unless
+/// `net.retry-locked` says otherwise.
+///
+/// Those runs favor failing fast over riding out a flaky network.
+pub const LOCKED_RETRIES: u32 = 1;
+/// The most retries an operation makes, unless `net.retry-ceiling` says
+/// otherwise.
+///
+/// Asking for many more, such as `net.retry = 9999`, turns a sustained outage
+/// into a run that hangs for hours; `--network-deadline` keeps retrying for a
+/// set time instead.
+pub const DEFAULT_RETRY_CEILING: u32 = 10;
+
+/// How many failed responses are kept for the end-of-run report, unless
+/// `net.retry-report-capacity` says otherwise.
+pub const DEFAULT_REPORT_CAPACITY: usize = 10;
+
+/// How many times a single operation is retried for TLS handshake failures
+/// ([`SpuriousReason::Ssl`]), whatever `net.retry` says.
+pub const MAX_SSL_RETRIES: u32 = 1;
+
+/// Delay before the first retry.
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Delay before the first retry after a `502 Bad Gateway`, unless
+/// `net.retry-502-backoff` says otherwise.
+///
+/// A 502 usually comes from a CDN edge that couldn't reach the origin, and
+/// another edge reached over a new connection often can right away.
+pub const DEFAULT_502_BACKOFF: Duration = Duration::from_millis(100);
+/// How many HTTP/2 stream errors in a row make the retries use HTTP/1.1,
+/// unless `net.retry-http1-fallback` says otherwise.
+pub const DEFAULT_HTTP1_FALLBACK: u32 = 2;
+/// Upper bound on the exponential backoff between two attempts, unless
+/// `net.retry-max-backoff` says otherwise.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// How long an operation can keep retrying before its retries are always
+/// reported as warnings, whatever `net.retry-warn-level` says.
+pub const ESCALATE_WARNINGS_AFTER: Duration = Duration::from_secs(30);
+/// Upper bound on the delay between two attempts with `net.retry-adaptive`,
+/// which follows the server's `Retry-After` past [`MAX_BACKOFF`], unless
+/// `net.retry-adaptive-max-backoff` says otherwise.
+pub const MAX_ADAPTIVE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Number of successful attempts in a row after which the backoff shared by
+/// the session resets, unless `net.retry-backoff-reset` says otherwise.
+pub const DEFAULT_BACKOFF_RESET: u32 = 10;
+/// Highest [`RetrySession::backoff_level`]; enough to reach [`MAX_BACKOFF`].
+const MAX_BACKOFF_LEVEL: u32 = 5;
+
+/// How long a cancellable [`Retry`] sleeps before checking its token again.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long [`with_retry_poll`] waits before calling again after
+/// [`Poll::Pending`], so that a callback making no progress doesn't spin.
+const POLL_PENDING_SLEEP: Duration = Duration::from_millis(10);
+
+/// State for managing retrying a network operation.
+pub struct Retry<'a> {
+    config: &'a Config,
+    /// The number of retries this started out with.
+    retries: u32,
+    /// The number of retries left before giving up.
+    remaining: u32,
+    /// Whether the number of retries came from `net.retry`, rather than
+    /// from `net.retry-default` or [`DEFAULT_RETRIES`].
+    retries_user_specified: bool,
+    /// Number of times [`Retry::try`] has run its closure.
+    attempts: u32,
+    /// When set to `true` (possibly from another thread), no further attempts
+    /// are made and [`RetryCancelled`] is returned instead.
+    cancel: Option<Arc<AtomicBool>>,
+    /// The delay to use for every retry instead of the exponential backoff.
+    ///
+    /// This is only set by the testsuite, which doesn't want to wait.
+    fixed_backoff: Option<Duration>,
+    sleeper: Rc<dyn Sleeper>,
+    clock: Rc<dyn Clock>,
+    /// When the first retry of this operation was scheduled.
+    retrying_since: Option<Instant>,
+    /// The [`RetrySession::backoff_level`] when this operation first retried.
+    backoff_level: u32,
+    /// Whether the backoff carries over between operations, from
+    /// `net.retry-escalate`.
+    escalate: bool,
+    /// Successes in a row after which the session's backoff level resets,
+    /// from `net.retry-backoff-reset`.
+    backoff_reset: u32,
+    /// How to handle transfers that were cut short, from `net.partial-download`.
+    partial_download: PartialDownloadConfig,
+    /// How much data the attempt being classified received, if known.
+    bytes_received: Option<u64>,
+    /// How long the attempt being classified spent resolving the host name,
+    /// if known.
+    dns_lookup_time: Option<Duration>,
+    /// Number of attempts in a row cut short before receiving any data.
+    empty_partials: u32,
+    /// The span of this operation, once it has started.
+    span: Option<OperationSpan>,
+    /// The most data the session may download again because of retries,
+    /// from `net.retry-max-redownload`.
+    max_redownload: Option<u64>,
+    /// Number of retries made for [`SpuriousReason::Ssl`].
+    ssl_retries: u32,
+    /// Whether the retry just scheduled should open a new connection, see
+    /// [`Retry::wants_new_connection`].
+    new_connection: bool,
+    /// The host of the proxy cargo connects through, if there is one, for
+    /// telling its TLS failures from the server's.
+    proxy_host: Option<String>,
+    /// How retries are reported, from `net.retry-warn-level`.
+    warn_level: RetryWarnLevel,
+    /// How long identical retry warnings are suppressed for, from
+    /// `net.retry-dedup-window`.
+    dedup_window: Option<Duration>,
+    /// How many failed responses the session keeps for the end-of-run
+    /// report, from `net.retry-report-capacity`.
+    report_capacity: usize,
+    /// Phrases that mark an unsuccessful HTTP response as spurious, from
+    /// `net.retry-body-patterns`.
+    body_patterns: Vec<String>,
+    /// 4xx statuses to treat as spurious, from `net.retry-on-4xx`.
+    retry_on_4xx: Vec<u32>,
+    /// Whether to wait as long as the host usually asks for with
+    /// `Retry-After`, from `net.retry-adaptive`.
+    adaptive: bool,
+    /// The longest delay followed because of `adaptive`, from
+    /// `net.retry-adaptive-max-backoff`.
+    max_adaptive_backoff: Duration,
+    /// Whether to follow `X-Retry-After-Ms` headers, from
+    /// `net.retry-after-ms`.
+    retry_after_ms: bool,
+    /// The first backoff, from `net.retry-hosts`.
+    initial_backoff: Duration,
+    /// Upper bound on the backoff, from `net.retry-max-backoff`.
+    max_backoff: Duration,
+    /// Lower bound on the jittered backoff, from `net.retry-min-backoff`.
+    min_backoff: Duration,
+    /// Upper bounds on the backoff for some reasons, from
+    /// `net.retry-max-backoff-for` and [`RetryBuilder::max_backoff_for_reason`].
+    max_backoff_for: BTreeMap<SpuriousReason, Duration>,
+    /// The most retries for some reasons, from `net.retry-limits`.
+    retry_limits: BTreeMap<SpuriousReason, u32>,
+    /// The retries made so far for the reasons in `retry_limits`.
+    retries_by_reason: BTreeMap<SpuriousReason, u32>,
+    /// The only reasons retried, if limited by `net.retry-on.include`.
+    retry_on_include: Option<BTreeSet<SpuriousReason>>,
+    /// The reasons never retried, from `net.retry-on.exclude`.
+    retry_on_exclude: BTreeSet<SpuriousReason>,
+    /// The first backoff after a 502, from `net.retry-502-backoff`.
+    backoff_502: Duration,
+    /// Whether the first retry happens right away, from
+    /// `net.retry-fast-first`.
+    fast_first: bool,
+    /// Whether to fall back to IPv4 after failing to connect, from
+    /// `net.retry-ipv4-fallback`.
+    ipv4_fallback: bool,
+    /// Whether the retries should only use IPv4, see [`Retry::wants_ipv4`].
+    ipv4: bool,
+    /// How many HTTP/2 stream errors in a row make the retries use HTTP/1.1,
+    /// or 0 to never, from `net.retry-http1-fallback`.
+    http1_fallback: u32,
+    /// The number of attempts in a row that failed with an HTTP/2 stream
+    /// error.
+    http2_stream_errors: u32,
+    /// Whether the retries should use HTTP/1.1, see [`Retry::wants_http1`].
+    http1: bool,
+    /// Whether to report how every error was classified, from
+    /// `net.retry-verbose`.
+    verbose: bool,
+    /// Replaces all of the built-in spurious error detection when set.
+    classifier: Option<Box<dyn Fn(&Error) -> bool>>,
+    /// Gets new credentials before retrying a [`CredentialsExpired`] error,
+    /// see [`RetryBuilder::refresh_credentials`].
+    refresh_credentials: Option<Box<dyn FnMut() -> CargoResult<()>>>,
+    /// Whether `refresh_credentials` was called already.
+    credentials_refreshed: bool,
+    /// Where the jitter comes from, see [`RetryBuilder::jitter_rng`].
+    jitter_rng: Option<Box<dyn RngCore>>,
+    /// Whether the operation can be repeated after the server may have acted
+    /// on it, see [`RetryBuilder::idempotent`].
+    idempotent: bool,
+    /// Whether a `304 Not Modified` is spurious, see
+    /// [`RetryBuilder::spurious_not_modified`].
+    spurious_not_modified: bool,
+    /// Told about every retry that gets scheduled.
+    observer: Option<Box<dyn Fn(&RetryEvent)>>,
+    /// What is being retried, such as "yank of foo v1.2.3", for messages.
+    operation: Option<String>,
+    /// What the current attempt is doing, see [`Retry::try_with_context`].
+    context: Option<String>,
+    /// The URL being fetched, for the `{host}` of `message_template`.
+    url: Option<String>,
+    /// Picks the URL for each retry, see [`RetryBuilder::rewrite_url`].
+    rewrite_url: Option<Rc<dyn UrlRewrite>>,
+    /// Replaces the usual retry warning, from the builder or
+    /// `net.retry-message`.
+    message_template: Option<RetryMessageTemplate>,
+}
+
+/// The HTTP status of a failed response from a registry or its API, if `err`
+/// is one.
+fn status_code(err: &Error) -> Option<u32> {
+    match err.downcast_ref::<crates_io::ResponseError>() {
+        Some(
+            crates_io::ResponseError::Api { code, .. }
+            | crates_io::ResponseError::Code { code, .. },
+        ) => Some(*code),
+        _ => err.downcast_ref::<HttpNotSuccessful>().map(|e| e.code),
+    }
+}
+
+/// The result of attempting some operation via [`Retry::try`].
+pub enum RetryResult<T> {
+    /// The operation was successful.
+    ///
+    /// The wrapped value is the return value of the callback function.
+    Success(T),
+    /// The operation was an error, and it should not be tried again.
+    Err(anyhow::Error),
+    /// The operation failed, and should be tried again in the future.
+    ///
+    /// The wrapped value is how long to wait before trying again.
+    Retry(Duration),
+}
+
+/// The curl timeouts for one attempt, from [`Retry::attempt_timeouts`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttemptTimeouts {
+    /// How long the attempt may take to connect.
+    pub connect: Duration,
+    /// How long the whole attempt may take.
+    pub total: Duration,
+}
+
+impl AttemptTimeouts {
+    /// Sets these timeouts on the handle the attempt is made with.
+    pub fn configure(&self, handle: &mut curl::easy::Easy) -> CargoResult<()> {
+        use anyhow::Context as _;
+        use log::warn;
+        crate::try_old_curl!(handle.connect_timeout(self.connect), "connect timeout");
+        crate::try_old_curl!(handle.timeout(self.total), "transfer timeout");
+        Ok(())
+    }
+}
+
+/// The curl options [`Retry::configure_handle`] sets up for the next
+/// attempt on the handle a downloader reuses.
+///
+/// This is implemented for [`curl::easy::Easy`]; tests implement it to see
+/// what a retry asks of curl.
+pub trait RetryHandle {
+    /// See [`curl::easy::Easy::range`].
+    fn range(&mut self, range: &str) -> Result<(), curl::Error>;
+    /// See [`curl::easy::Easy::fresh_connect`].
+    fn fresh_connect(&mut self, enable: bool) -> Result<(), curl::Error>;
+    /// See [`curl::easy::Easy::ip_resolve`].
+    fn ip_resolve(&mut self, resolve: curl::easy::IpResolve) -> Result<(), curl::Error>;
+    /// See [`curl::easy::Easy::http_version`].
+    fn http_version(&mut self, version: curl::easy::HttpVersion) -> Result<(), curl::Error>;
+}
+
+impl RetryHandle for curl::easy::Easy {
+    fn range(&mut self, range: &str) -> Result<(), curl::Error> {
+        curl::easy::Easy::range(self, range)
+    }
+
+    fn fresh_connect(&mut self, enable: bool) -> Result<(), curl::Error> {
+        curl::easy::Easy::fresh_connect(self, enable)
+    }
+
+    fn ip_resolve(&mut self, resolve: curl::easy::IpResolve) -> Result<(), curl::Error> {
+        curl::easy::Easy::ip_resolve(self, resolve)
+    }
+
+    fn http_version(&mut self, version: curl::easy::HttpVersion) -> Result<(), curl::Error> {
+        curl::easy::Easy::http_version(self, version)
+    }
+}
+
+/// How [`RetryBuilder::retry_predicate_chain`] combines its predicates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PredicateCombine {
+    /// Retry only if every predicate returns `true`.
+    All,
+    /// Retry if any predicate returns `true`.
+    Any,
+}
+
+/// Constructs a [`Retry`] with non-default behavior.
+///
+/// [`Retry::new`] is enough for most callers; this is for the ones that need
+/// to cancel the operation or control how time passes (mostly tests).
+pub struct RetryBuilder<'a> {
+    config: &'a Config,
+    cancel: Option<Arc<AtomicBool>>,
+    sleeper: Option<Rc<dyn Sleeper>>,
+    clock: Option<Rc<dyn Clock>>,
+    classifier: Option<Box<dyn Fn(&Error) -> bool>>,
+    refresh_credentials: Option<Box<dyn FnMut() -> CargoResult<()>>>,
+    jitter_rng: Option<Box<dyn RngCore>>,
+    idempotent: bool,
+    spurious_not_modified: bool,
+    observer: Option<Box<dyn Fn(&RetryEvent)>>,
+    operation: Option<String>,
+    url: Option<String>,
+    rewrite_url: Option<Rc<dyn UrlRewrite>>,
+    message_template: Option<RetryMessageTemplate>,
+    max_backoff_for: Vec<(SpuriousReason, Duration)>,
+    min_backoff: Option<Duration>,
+    policy: Option<NetRetryPolicy>,
+}
+
+impl<'a> RetryBuilder<'a> {
+    pub fn new(config: &'a Config) -> RetryBuilder<'a> {
+        RetryBuilder {
+            config,
+            cancel: None,
+            sleeper: None,
+            clock: None,
+            classifier: None,
+            refresh_credentials: None,
+            jitter_rng: None,
+            idempotent: true,
+            spurious_not_modified: false,
+            observer: None,
+            operation: None,
+            url: None,
+            rewrite_url: config.retry_url_rewrite(),
+            message_template: None,
+            max_backoff_for: Vec::new(),
+            min_backoff: None,
+            policy: None,
+        }
+    }
+
+    /// Uses `token` to cancel the operation: once it is set, the next call
+    /// to [`Retry::try`] fails with [`RetryCancelled`] without calling its
+    /// closure, and [`Retry::sleep`] returns early.
+    pub fn cancel_token(mut self, token: Arc<AtomicBool>) -> RetryBuilder<'a> {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Replaces how [`Retry::sleep`] waits between attempts.
+    pub fn sleeper(mut self, sleeper: Rc<dyn Sleeper>) -> RetryBuilder<'a> {
+        self.sleeper = Some(sleeper);
+        self
+    }
+
+    /// Replaces where the current time comes from.
+    pub fn clock(mut self, clock: Rc<dyn Clock>) -> RetryBuilder<'a> {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Decides which errors are spurious with `classifier` instead of the
+    /// built-in logic.
+    ///
+    /// This bypasses the built-in logic entirely: none of the usual git,
+    /// curl, or HTTP status checks apply, and neither do
+    /// `net.partial-download = "fail"` or `net.retry-body-patterns`. An error
+    /// is retried exactly when `classifier` returns `true` for it (and there
+    /// are retries left).
+    pub fn classifier(mut self, classifier: impl Fn(&Error) -> bool + 'static) -> RetryBuilder<'a> {
+        self.classifier = Some(Box::new(classifier));
+        self
+    }
+
+    /// Like [`RetryBuilder::classifier`], but with several predicates whose
+    /// answers are combined with `combine`.
+    ///
+    /// The predicates are asked in order and evaluation stops as soon as the
+    /// answer is known, so cheap checks should come first. To keep the
+    /// built-in logic as one of the rules, include a predicate calling
+    /// [`spurious_reason`]. [`NonRetryable`] and [`ForceRetryable`] errors
+    /// are decided before any predicate is asked, and `net.retry-on` doesn't
+    /// apply, since it filters the reasons of the built-in logic. This
+    /// replaces a `classifier` set earlier, and vice versa.
+    pub fn retry_predicate_chain(
+        mut self,
+        combine: PredicateCombine,
+        predicates: Vec<Box<dyn Fn(&Error) -> bool>>,
+    ) -> RetryBuilder<'a> {
+        self.classifier = Some(Box::new(move |err| match combine {
+            PredicateCombine::All => predicates.iter().all(|p| p(err)),
+            PredicateCombine::Any => predicates.iter().any(|p| p(err)),
+        }));
+        self
+    }
+
+    /// Calls `refresh` to get new credentials when an attempt fails with a
+    /// [`CredentialsExpired`] error, and retries it once with them.
+    ///
+    /// `refresh` is expected to make the next attempt ask the credential
+    /// provider again, rather than reuse what was rejected. If the new
+    /// credentials are rejected too, the operation fails, since the access
+    /// is more likely denied than expired. Other authentication failures
+    /// aren't retried at all.
+    pub fn refresh_credentials(
+        mut self,
+        refresh: impl FnMut() -> CargoResult<()> + 'static,
+    ) -> RetryBuilder<'a> {
+        self.refresh_credentials = Some(Box::new(refresh));
+        self
+    }
+
+    /// Says whether the operation is safe to repeat once the server may have
+    /// acted on it, which is the default.
+    ///
+    /// For an operation that isn't, such as one creating something on the
+    /// server, only errors from before the request was sent are retried: see
+    /// [`SpuriousReason::request_phase`]. Errors marked [`ForceRetryable`]
+    /// and the decisions of a [`RetryBuilder::classifier`] are left alone.
+    pub fn idempotent(mut self, idempotent: bool) -> RetryBuilder<'a> {
+        self.idempotent = idempotent;
+        self
+    }
+
+    /// Treats a `304 Not Modified` as spurious.
+    ///
+    /// This is for requests that weren't conditional, so a 304 can only come
+    /// from a misbehaving cache between cargo and the server.
+    pub fn spurious_not_modified(mut self) -> RetryBuilder<'a> {
+        self.spurious_not_modified = true;
+        self
+    }
+
+    /// Draws the random part of every delay from `rng` instead of the thread
+    /// RNG, so that the delays can be reproduced exactly.
+    pub fn jitter_rng(mut self, rng: impl RngCore + 'static) -> RetryBuilder<'a> {
+        self.jitter_rng = Some(Box::new(rng));
+        self
+    }
+
+    /// Calls `observer` every time a retry is scheduled, after the user has
+    /// been warned about it.
+    ///
+    /// The observer doesn't know which operation is being retried, so it
+    /// usually captures that itself; see [`progress_observer`].
+    pub fn observer(mut self, observer: impl Fn(&RetryEvent) + 'static) -> RetryBuilder<'a> {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Names the operation being retried, such as "yank of foo v1.2.3", so
+    /// the retry warning can say what is being retried.
+    pub fn operation(mut self, operation: impl Into<String>) -> RetryBuilder<'a> {
+        self.operation = Some(operation.into());
+        self
+    }
+
+    /// The URL the operation talks to, so the retry warning can name its
+    /// host. Errors that carry their own URL, like [`HttpNotSuccessful`],
+    /// take precedence.
+    pub fn url(mut self, url: impl Into<String>) -> RetryBuilder<'a> {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Calls `rewrite` before every retry to pick the URL it should use,
+    /// for example to move away from a registry deployment that is being
+    /// replaced.
+    ///
+    /// `rewrite` gets the URL given to [`RetryBuilder::url`] and the number
+    /// of the attempt about to be made, starting at 2. Downloaders apply the
+    /// result through [`Retry::rewritten_url`]; without a rewrite, every
+    /// attempt uses the same URL. This replaces a rewrite from
+    /// [`Config::set_retry_url_rewrite`].
+    pub fn rewrite_url(
+        mut self,
+        rewrite: impl Fn(&str, u32) -> String + 'static,
+    ) -> RetryBuilder<'a> {
+        self.rewrite_url = Some(Rc::new(rewrite));
+        self
+    }
+
+    /// Formats the retry warning with `template`, overriding
+    /// `net.retry-message`.
+    pub fn message_template(mut self, template: RetryMessageTemplate) -> RetryBuilder<'a> {
+        self.message_template = Some(template);
+        self
+    }
+
+    /// Caps the delay before retrying an error spurious because of `reason`
+    /// at `max`, instead of [`MAX_BACKOFF`] or `net.retry-max-backoff`.
+    ///
+    /// This also caps the delays followed because of `net.retry-adaptive`,
+    /// instead of [`MAX_ADAPTIVE_BACKOFF`], so a long cap for
+    /// [`SpuriousReason::HttpStatus`]`(429)` honors long `Retry-After`s.
+    /// It takes precedence over `net.retry-max-backoff-for`.
+    pub fn max_backoff_for_reason(mut self, reason: SpuriousReason, max: Duration) -> Self {
+        self.max_backoff_for.push((reason, max));
+        self
+    }
+
+    /// Never waits less than `min` before a retry following the backoff
+    /// schedule, overriding `net.retry-min-backoff`.
+    ///
+    /// The jitter otherwise picks a delay anywhere between half of the
+    /// scheduled one and all of it, which can be too short for a server to
+    /// recover. `min` must be no longer than the first backoff and the first
+    /// one after a 502, which must be no longer than `net.retry-max-backoff`.
+    pub fn min_backoff(mut self, min: Duration) -> RetryBuilder<'a> {
+        self.min_backoff = Some(min);
+        self
+    }
+
+    /// Uses `policy` instead of reading the retry settings from config.
+    pub fn policy(mut self, policy: NetRetryPolicy) -> RetryBuilder<'a> {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Errors are wrapped in [`RetrySetupError`].
+    pub fn build(mut self) -> CargoResult<Retry<'a>> {
+        let policy = self.resolve_policy().map_err(setup_error)?;
+        Ok(self.build_with(policy))
+    }
+
+    /// The policy the [`Retry`] follows, for [`RetryBuilder::build`].
+    fn resolve_policy(&mut self) -> CargoResult<NetRetryPolicy> {
+        let mut policy = match self.policy.take() {
+            Some(policy) => policy,
+            None => {
+                let policy = NetRetryPolicy::new(self.config)?;
+                match &self.url {
+                    Some(url) => policy.for_url(self.config, url)?,
+                    None => policy,
+                }
+            }
+        };
+        if let Some(min) = self.min_backoff {
+            policy.min_backoff = min;
+            policy.check_min_backoff()?;
+        }
+        Ok(policy)
+    }
+
+    fn build_with(self, policy: NetRetryPolicy) -> Retry<'a> {
+        let mut max_backoff_for = policy.max_backoff_for;
+        max_backoff_for.extend(self.max_backoff_for);
+        Retry {
+            config: self.config,
+            retries: policy.retries,
+            remaining: policy.retries,
+            retries_user_specified: policy.retries_user_specified,
+            attempts: 0,
+            cancel: self.cancel,
+            fixed_backoff: policy.fixed_backoff,
+            sleeper: self.sleeper.unwrap_or_else(|| Rc::new(ThreadSleeper)),
+            clock: self.clock.unwrap_or_else(|| Rc::new(SystemClock)),
+            retrying_since: None,
+            backoff_level: 0,
+            escalate: policy.escalate,
+            backoff_reset: policy.backoff_reset,
+            partial_download: policy.partial_download,
+            bytes_received: None,
+            dns_lookup_time: None,
+            empty_partials: 0,
+            span: None,
+            max_redownload: policy.max_redownload,
+            ssl_retries: 0,
+            new_connection: false,
+            proxy_host: proxy_host(self.config),
+            warn_level: policy.warn_level,
+            dedup_window: policy.dedup_window,
+            report_capacity: policy.report_capacity,
+            body_patterns: policy.body_patterns,
+            retry_on_4xx: policy.retry_on_4xx,
+            adaptive: policy.adaptive,
+            max_adaptive_backoff: policy.max_adaptive_backoff,
+            retry_after_ms: policy.retry_after_ms,
+            initial_backoff: policy.initial_backoff,
+            max_backoff: policy.max_backoff,
+            min_backoff: policy.min_backoff,
+            max_backoff_for,
+            retry_limits: policy.retry_limits,
+            retries_by_reason: BTreeMap::new(),
+            retry_on_include: policy.retry_on_include,
+            retry_on_exclude: policy.retry_on_exclude,
+            backoff_502: policy.backoff_502,
+            fast_first: policy.fast_first,
+            ipv4_fallback: policy.ipv4_fallback,
+            verbose: policy.verbose,
+            ipv4: false,
+            http1_fallback: policy.http1_fallback,
+            http2_stream_errors: 0,
+            http1: false,
+            classifier: self.classifier,
+            refresh_credentials: self.refresh_credentials,
+            credentials_refreshed: false,
+            jitter_rng: self.jitter_rng,
+            idempotent: self.idempotent,
+            spurious_not_modified: self.spurious_not_modified,
+            observer: self.observer,
+            operation: self.operation,
+            context: None,
+            url: self.url,
+            rewrite_url: self.rewrite_url,
+            message_template: self.message_template.or(policy.message_template),
+        }
+    }
+}
+
+/// The retry settings from the `net` config table, resolved once so that
+/// many [`Retry`]s can be built from them without reading the config again.
+///
+/// A policy can be serialized, for tools that want to record the settings a
+/// run used, and read back with [`Retry::from_policy`]. Its fields are named
+/// after the `net` config keys they come from, with the defaults filled in,
+/// and durations are written in milliseconds. Fields that don't come from
+/// config are `retry-user-specified`, saying whether `retry` was configured,
+/// and `fixed-backoff`, which only the testsuite sets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetRetryPolicy {
+    #[serde(rename = "retry")]
+    retries: u32,
+    #[serde(rename = "retry-user-specified")]
+    retries_user_specified: bool,
+    #[serde(with = "duration_ms::option")]
+    fixed_backoff: Option<Duration>,
+    #[serde(rename = "retry-escalate")]
+    escalate: bool,
+    #[serde(rename = "retry-backoff-reset")]
+    backoff_reset: u32,
+    partial_download: PartialDownloadConfig,
+    #[serde(rename = "retry-warn-level")]
+    warn_level: RetryWarnLevel,
+    #[serde(rename = "retry-dedup-window", with = "duration_ms::option")]
+    dedup_window: Option<Duration>,
+    #[serde(rename = "retry-report-capacity")]
+    report_capacity: usize,
+    #[serde(rename = "retry-body-patterns")]
+    body_patterns: Vec<String>,
+    retry_on_4xx: Vec<u32>,
+    #[serde(rename = "retry-adaptive")]
+    adaptive: bool,
+    #[serde(rename = "retry-adaptive-max-backoff", with = "duration_ms")]
+    max_adaptive_backoff: Duration,
+    #[serde(rename = "retry-after-ms")]
+    retry_after_ms: bool,
+    #[serde(rename = "retry-backoff", with = "duration_ms")]
+    initial_backoff: Duration,
+    #[serde(rename = "retry-502-backoff", with = "duration_ms")]
+    backoff_502: Duration,
+    #[serde(rename = "retry-fast-first")]
+    fast_first: bool,
+    #[serde(rename = "retry-max-backoff", with = "duration_ms")]
+    max_backoff: Duration,
+    #[serde(rename = "retry-min-backoff", with = "duration_ms")]
+    min_backoff: Duration,
+    #[serde(rename = "retry-max-backoff-for", with = "duration_ms::map")]
+    max_backoff_for: BTreeMap<SpuriousReason, Duration>,
+    #[serde(rename = "retry-limits")]
+    retry_limits: BTreeMap<SpuriousReason, u32>,
+    #[serde(rename = "retry-on-include")]
+    retry_on_include: Option<BTreeSet<SpuriousReason>>,
+    #[serde(rename = "retry-on-exclude")]
+    retry_on_exclude: BTreeSet<SpuriousReason>,
+    #[serde(rename = "retry-ipv4-fallback")]
+    ipv4_fallback: bool,
+    #[serde(rename = "retry-http1-fallback")]
+    http1_fallback: u32,
+    #[serde(rename = "retry-verbose")]
+    verbose: bool,
+    #[serde(rename = "retry-message")]
+    message_template: Option<RetryMessageTemplate>,
+    #[serde(rename = "retry-max-redownload")]
+    max_redownload: Option<u64>,
+}
+
+/// (De)serializes a [`Duration`] as a number of milliseconds.
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(dur: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(dur.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_millis)
+    }
+
+    pub mod map {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::collections::BTreeMap;
+        use std::time::Duration;
+
+        pub fn serialize<K: Serialize, S: Serializer>(
+            map: &BTreeMap<K, Duration>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            s.collect_map(map.iter().map(|(k, dur)| (k, dur.as_millis() as u64)))
+        }
+
+        pub fn deserialize<'de, K, D>(d: D) -> Result<BTreeMap<K, Duration>, D::Error>
+        where
+            K: Deserialize<'de> + Ord,
+            D: Deserializer<'de>,
+        {
+            let map = BTreeMap::<K, u64>::deserialize(d)?;
+            Ok(map
+                .into_iter()
+                .map(|(k, ms)| (k, Duration::from_millis(ms)))
+                .collect())
+        }
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S: Serializer>(dur: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+            match dur {
+                Some(dur) => s.serialize_some(&(dur.as_millis() as u64)),
+                None => s.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+            Option::<u64>::deserialize(d).map(|ms| ms.map(Duration::from_millis))
+        }
+    }
+}
+
+impl NetRetryPolicy {
+    /// Reads the policy from config.
+    ///
+    /// Errors are wrapped in [`RetrySetupError`], as are the ones of
+    /// everything else that sets up a [`Retry`].
+    pub fn new(config: &Config) -> CargoResult<NetRetryPolicy> {
+        NetRetryPolicy::load(config).map_err(setup_error)
+    }
+
+    fn load(config: &Config) -> CargoResult<NetRetryPolicy> {
+        let fixed_backoff = config
+            .get_env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis);
+        let net_config = config.net_config()?;
+        let retry_on_4xx = net_config
+            .retry_on_4xx
+            .iter()
+            .flatten()
+            .map(|code| match code.parse::<u32>() {
+                Ok(code @ 400..=499) => Ok(code),
+                _ => anyhow::bail!(
+                    "`net.retry-on-4xx` must only contain 4xx HTTP status codes, found `{code}`"
+                ),
+            })
+            .collect::<CargoResult<_>>()?;
+        let max_redownload = net_config
+            .retry_max_redownload
+            .as_deref()
+            .map(|size| {
+                crate::util::parse_human_readable_bytes(size).ok_or_else(|| {
+                    anyhow::format_err!(
+                        "`net.retry-max-redownload` must be a size such as `500MiB`, found `{size}`"
+                    )
+                })
+            })
+            .transpose()?;
+        let max_backoff_for = net_config
+            .retry_max_backoff_for
+            .iter()
+            .flatten()
+            .map(|(reason, ms)| {
+                let reason = reason.parse::<SpuriousReason>().map_err(|e| {
+                    e.context("`net.retry-max-backoff-for` must be keyed by retry reasons")
+                })?;
+                Ok((reason, Duration::from_millis(*ms)))
+            })
+            .collect::<CargoResult<_>>()?;
+        let retry_limits = net_config
+            .retry_limits
+            .iter()
+            .flatten()
+            .map(|(reason, limit)| {
+                let reason = reason
+                    .parse::<SpuriousReason>()
+                    .map_err(|e| e.context("`net.retry-limits` must be keyed by retry reasons"))?;
+                Ok((reason, *limit))
+            })
+            .collect::<CargoResult<_>>()?;
+        let retry_on = net_config.retry_on.as_ref();
+        let parse_reasons = |reasons: &Vec<String>, key: &str| {
+            reasons
+                .iter()
+                .map(|reason| {
+                    reason
+                        .parse::<SpuriousReason>()
+                        .map_err(|e| e.context(format!("`{key}` must list retry reasons")))
+                })
+                .collect::<CargoResult<BTreeSet<_>>>()
+        };
+        let retry_on_include = retry_on
+            .and_then(|r| r.include.as_ref())
+            .map(|reasons| parse_reasons(reasons, "net.retry-on.include"))
+            .transpose()?;
+        let retry_on_exclude = match retry_on.and_then(|r| r.exclude.as_ref()) {
+            Some(reasons) => parse_reasons(reasons, "net.retry-on.exclude")?,
+            None => BTreeSet::new(),
+        };
+        let profile_retry = config
+            .retry_profile()
+            .and_then(|command| net_config.retry_profile.as_ref()?.get(command)?.retry);
+        let settings = config.net_retry_settings();
+        let retry = config
+            .retry_override()
+            .or(settings.retry)
+            .or(profile_retry)
+            .or(net_config.retry);
+        let (retries, retries_user_specified) = match retry {
+            Some(retry) if config.retry_override().is_some() => (retry, true),
+            _ if net_config.no_retry == Some(true) => (0, false),
+            Some(retry) => (retry, true),
+            None => (net_config.retry_default.unwrap_or(DEFAULT_RETRIES), false),
+        };
+        let mut policy = NetRetryPolicy {
+            retries,
+            retries_user_specified,
+            fixed_backoff,
+            escalate: net_config.retry_escalate.unwrap_or(false),
+            backoff_reset: net_config
+                .retry_backoff_reset
+                .unwrap_or(DEFAULT_BACKOFF_RESET),
+            partial_download: net_config.partial_download.unwrap_or_default(),
+            warn_level: net_config.retry_warn_level.unwrap_or_default(),
+            dedup_window: net_config
+                .retry_dedup_window
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            report_capacity: net_config
+                .retry_report_capacity
+                .map_or(DEFAULT_REPORT_CAPACITY, |n| n as usize),
+            body_patterns: net_config.retry_body_patterns.clone().unwrap_or_default(),
+            retry_on_4xx,
+            adaptive: net_config.retry_adaptive.unwrap_or(false),
+            max_adaptive_backoff: net_config
+                .retry_adaptive_max_backoff
+                .map_or(MAX_ADAPTIVE_BACKOFF, Duration::from_millis),
+            retry_after_ms: net_config.retry_after_ms.unwrap_or(false),
+            initial_backoff: settings.backoff.unwrap_or_else(|| {
+                net_config
+                    .retry_backoff
+                    .map_or(INITIAL_BACKOFF, Duration::from_millis)
+            }),
+            backoff_502: settings.backoff_502.unwrap_or_else(|| {
+                net_config
+                    .retry_502_backoff
+                    .map_or(DEFAULT_502_BACKOFF, Duration::from_millis)
+            }),
+            fast_first: net_config.retry_fast_first.unwrap_or(false),
+            max_backoff: settings.max_backoff.unwrap_or_else(|| {
+                net_config
+                    .retry_max_backoff
+                    .map_or(MAX_BACKOFF, Duration::from_millis)
+            }),
+            min_backoff: net_config
+                .retry_min_backoff
+                .map_or(Duration::ZERO, Duration::from_millis),
+            max_backoff_for,
+            retry_limits,
+            retry_on_include,
+            retry_on_exclude,
+            ipv4_fallback: net_config.retry_ipv4_fallback.unwrap_or(false),
+            // A version pinned with `http.version` is kept for the retries.
+            http1_fallback: match config.http_config()?.version {
+                Some(_) => 0,
+                None => net_config
+                    .retry_http1_fallback
+                    .unwrap_or(DEFAULT_HTTP1_FALLBACK),
+            },
+            verbose: net_config.retry_verbose.unwrap_or(false),
+            message_template: net_config.retry_message.clone(),
+            max_redownload,
+        };
+        policy.limit_to_ceiling(config, net_config)?;
+        policy.limit_when_locked(config, net_config)?;
+        policy.check_min_backoff()?;
+        Ok(policy)
+    }
+
+    /// Caps the retries at `net.retry-ceiling`, warning about it the first
+    /// time in a session.
+    fn limit_to_ceiling(
+        &mut self,
+        config: &Config,
+        net_config: &CargoNetConfig,
+    ) -> CargoResult<()> {
+        let ceiling = net_config.retry_ceiling.unwrap_or(DEFAULT_RETRY_CEILING);
+        if self.retries <= ceiling {
+            return Ok(());
+        }
+        if config.retry_session().note_retry_ceiling() {
+            config.shell().warn(format!(
+                "{} network retries were asked for, more than the `net.retry-ceiling` of \
+                 {ceiling}, so only {ceiling} are made\n\
+                 to keep retrying through a long outage, limit how long with \
+                 `--network-deadline` instead, or raise `net.retry-ceiling`",
+                self.retries
+            ))?;
+        }
+        self.retries = ceiling;
+        Ok(())
+    }
+
+    /// Checks that `net.retry-min-backoff`, if set, is no longer than the
+    /// first backoff and the first one after a 502, and that those are no
+    /// longer than `net.retry-max-backoff`.
+    fn check_min_backoff(&self) -> CargoResult<()> {
+        if self.min_backoff.is_zero() {
+            return Ok(());
+        }
+        let first = self.initial_backoff.max(self.backoff_502);
+        if self.min_backoff > self.initial_backoff.min(self.backoff_502) || first > self.max_backoff
+        {
+            anyhow::bail!(
+                "`net.retry-min-backoff` of {}ms must be no longer than the first backoff \
+                 of {}ms and the first backoff after a 502 of {}ms, which must be no longer \
+                 than `net.retry-max-backoff` of {}ms",
+                self.min_backoff.as_millis(),
+                self.initial_backoff.as_millis(),
+                self.backoff_502.as_millis(),
+                self.max_backoff.as_millis()
+            );
+        }
+        Ok(())
+    }
+
+    /// Caps the retries at `net.retry-locked` under `--locked` or `--frozen`,
+    /// unless `--retry` was given.
+    ///
+    /// Lowering a number of retries the user asked for is warned about once,
+    /// like [`NetRetryPolicy::limit_to_ceiling`] does.
+    fn limit_when_locked(
+        &mut self,
+        config: &Config,
+        net_config: &CargoNetConfig,
+    ) -> CargoResult<()> {
+        if !(config.locked() || config.frozen()) || config.retry_override().is_some() {
+            return Ok(());
+        }
+        let max = net_config.retry_locked.unwrap_or(LOCKED_RETRIES);
+        if self.retries <= max {
+            return Ok(());
+        }
+        if self.retries_user_specified && config.retry_session().note_retry_locked() {
+            config.shell().warn(format!(
+                "{} network retries were asked for, but only {max} are made with \
+                 `--locked` or `--frozen`\n\
+                 to retry more, pass `--retry` or raise `net.retry-locked`",
+                self.retries
+            ))?;
+        }
+        self.retries = max;
+        Ok(())
+    }
+
+    /// Applies the `net.retry-hosts.<host>` table for `host`, if there is
+    /// one, over this policy.
+    ///
+    /// The host's settings take precedence over the `net` ones, including
+    /// `net.retry-profile`. `--retry` and the settings made in code with
+    /// [`Config::set_net_retry`] and the like still take precedence over the
+    /// host's, and `net.no-retry` still disables retries.
+    pub fn for_host(self, config: &Config, host: &str) -> CargoResult<NetRetryPolicy> {
+        self.apply_host(config, host).map_err(setup_error)
+    }
+
+    fn apply_host(mut self, config: &Config, host: &str) -> CargoResult<NetRetryPolicy> {
+        let net_config = config.net_config()?;
+        let Some(host_config) = net_config.retry_hosts.as_ref().and_then(|h| h.get(host)) else {
+            return Ok(self);
+        };
+        let settings = config.net_retry_settings();
+        if let Some(retry) = host_config.retry {
+            if config.retry_override().is_none()
+                && settings.retry.is_none()
+                && net_config.no_retry != Some(true)
+            {
+                self.retries = retry;
+                self.retries_user_specified = true;
+                self.limit_to_ceiling(config, net_config)?;
+                self.limit_when_locked(config, net_config)?;
+            }
+        }
+        if let Some(ms) = host_config
+            .retry_backoff
+            .filter(|_| settings.backoff.is_none())
+        {
+            self.initial_backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = host_config
+            .retry_502_backoff
+            .filter(|_| settings.backoff_502.is_none())
+        {
+            self.backoff_502 = Duration::from_millis(ms);
+        }
+        if let Some(fast_first) = host_config.retry_fast_first {
+            self.fast_first = fast_first;
+        }
+        self.check_min_backoff()?;
+        Ok(self)
+    }
+
+    /// Like [`NetRetryPolicy::for_host`], with the host of `url`.
+    pub fn for_url(self, config: &Config, url: &str) -> CargoResult<NetRetryPolicy> {
+        match url::Url::parse(url)
+            .ok()
+            .as_ref()
+            .and_then(|u| u.host_str())
+        {
+            Some(host) => self.for_host(config, host),
+            None => Ok(self),
+        }
+    }
+
+    /// How many times an operation is retried before giving up.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Like the free [`spurious_reason_for_status`], also counting the codes
+    /// from `net.retry-on-4xx`.
+    pub fn spurious_reason_for_status(&self, code: u32) -> Option<SpuriousReason> {
+        if self.retry_on_4xx.contains(&code) {
+            return Some(SpuriousReason::HttpStatus(code));
+        }
+        spurious_reason_for_status(code)
+    }
+}
+
+impl<'a> Retry<'a> {
+    pub fn new(config: &'a Config) -> CargoResult<Retry<'a>> {
+        RetryBuilder::new(config).build()
+    }
+
+    /// Like [`Retry::new`], for an operation talking to `host`, applying its
+    /// `net.retry-hosts` table as described in [`NetRetryPolicy::for_host`].
+    pub fn for_host(config: &'a Config, host: &str) -> CargoResult<Retry<'a>> {
+        let policy = NetRetryPolicy::new(config)?.for_host(config, host)?;
+        Ok(Retry::from_policy(&policy, config))
+    }
+
+    /// Like [`Retry::new`], with settings resolved ahead of time.
+    pub fn from_policy(policy: &NetRetryPolicy, config: &'a Config) -> Retry<'a> {
+        RetryBuilder::new(config).build_with(policy.clone())
+    }
+
+    /// The policy this was built with, such that [`Retry::from_policy`]
+    /// builds an equivalent `Retry`. A message template given to the builder
+    /// is included; the rest of what a [`RetryBuilder`] can set isn't.
+    pub fn into_policy(self) -> NetRetryPolicy {
+        NetRetryPolicy {
+            retries: self.retries,
+            retries_user_specified: self.retries_user_specified,
+            fixed_backoff: self.fixed_backoff,
+            escalate: self.escalate,
+            backoff_reset: self.backoff_reset,
+            partial_download: self.partial_download,
+            warn_level: self.warn_level,
+            dedup_window: self.dedup_window,
+            report_capacity: self.report_capacity,
+            body_patterns: self.body_patterns,
+            retry_on_4xx: self.retry_on_4xx,
+            adaptive: self.adaptive,
+            max_adaptive_backoff: self.max_adaptive_backoff,
+            retry_after_ms: self.retry_after_ms,
+            initial_backoff: self.initial_backoff,
+            backoff_502: self.backoff_502,
+            fast_first: self.fast_first,
+            max_backoff: self.max_backoff,
+            min_backoff: self.min_backoff,
+            max_backoff_for: self.max_backoff_for,
+            retry_limits: self.retry_limits,
+            retry_on_include: self.retry_on_include,
+            retry_on_exclude: self.retry_on_exclude,
+            ipv4_fallback: self.ipv4_fallback,
+            http1_fallback: self.http1_fallback,
+            verbose: self.verbose,
+            message_template: self.message_template,
+            max_redownload: self.max_redownload,
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map_or(false, |token| token.load(Ordering::SeqCst))
+    }
+
+    /// Statistics about the attempts made so far.
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            attempts: self.attempts,
+        }
+    }
+
+    /// The number of retries left.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// The longest the next retry would wait, if the attempt about to be made
+    /// fails, without changing any state.
+    ///
+    /// The actual delay is jittered down to half of this. The shorter
+    /// schedule after a 502 and the `Retry-After` delays followed because of
+    /// `net.retry-adaptive` aren't included, since they depend on the error.
+    pub fn peek_backoff(&self) -> Duration {
+        if let Some(fixed) = self.fixed_backoff {
+            return fixed;
+        }
+        // The first retry picks up the session's backoff level, see `try`.
+        let level = match self.retrying_since {
+            Some(_) => self.backoff_level,
+            None if self.escalate => self
+                .config
+                .retry_session()
+                .backoff_level(self.url_host().as_deref()),
+            None => 0,
+        };
+        match self.schedule_position(self.attempts + 1) {
+            Some(attempts) => {
+                backoff_bound(self.initial_backoff, attempts, level, self.max_backoff)
+                    .max(self.min_backoff)
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Whether `net.retry` was set, as opposed to the number of retries
+    /// coming from `net.retry-default` or the built-in default.
+    pub fn retries_user_specified(&self) -> bool {
+        self.retries_user_specified
+    }
+
+    /// How long this operation has been retrying, measured from the moment
+    /// its first retry was scheduled.
+    pub fn elapsed(&self) -> Duration {
+        match self.retrying_since {
+            Some(since) => self.clock.now().saturating_duration_since(since),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Whether a transfer that was cut short should be resumed from where it
+    /// stopped, rather than started over, when it is retried.
+    ///
+    /// Downloaders that can send range requests check this after getting
+    /// [`RetryResult::Retry`] for an `is_partial_file` error, holding on to
+    /// what they got so far.
+    pub fn resumes_partial_downloads(&self) -> bool {
+        self.partial_download == PartialDownloadConfig::Resume
+    }
+
+    /// Sets up `handle` for the next attempt, after getting
+    /// [`RetryResult::Retry`].
+    ///
+    /// The attempt opens a new connection if [`Retry::wants_new_connection`],
+    /// only connects over IPv4 if [`Retry::wants_ipv4`], and uses HTTP/1.1 if
+    /// [`Retry::wants_http1`].
+    /// `resumed` is how many bytes the downloader held on to from a transfer
+    /// that was cut short, see [`Retry::resumes_partial_downloads`]; the
+    /// next attempt then only asks for the rest.
+    pub fn configure_handle(
+        &self,
+        handle: &mut impl RetryHandle,
+        resumed: usize,
+    ) -> CargoResult<()> {
+        use anyhow::Context as _;
+        use log::warn;
+        handle.fresh_connect(self.wants_new_connection())?;
+        if self.ipv4 {
+            crate::try_old_curl!(
+                handle.ip_resolve(curl::easy::IpResolve::V4),
+                "IPv4 fallback"
+            );
+        }
+        if self.http1 {
+            crate::try_old_curl!(
+                handle.http_version(curl::easy::HttpVersion::V11),
+                "HTTP/1.1 fallback"
+            );
+        }
+        if resumed > 0 {
+            handle.range(&format!("{resumed}-"))?;
+        }
+        Ok(())
+    }
+
+    /// The URL the next attempt should use, if [`RetryBuilder::rewrite_url`]
+    /// changes it from the one given to [`RetryBuilder::url`].
+    ///
+    /// Downloaders call this after getting [`RetryResult::Retry`].
+    pub fn rewritten_url(&self) -> Option<String> {
+        let rewrite = self.rewrite_url.as_ref()?;
+        let url = self.url.as_deref()?;
+        let next = rewrite.rewrite(url, self.attempts + 1);
+        (next != url).then_some(next)
+    }
+
+    /// Whether the next attempt should open a new connection instead of
+    /// reusing one from the pool.
+    ///
+    /// This is the case after an HTTP/2 stream error, since the connection
+    /// that carried the stream is likely to be in a bad state, and with
+    /// multiplexing curl would otherwise hand the retry the same one. It is
+    /// also the case after a 502, which a different CDN edge may not give,
+    /// and while the last response from the host of [`RetryBuilder::url`],
+    /// to any operation, said `Connection: close`, since the server is done
+    /// with that connection (see [`RetrySession::connection_closed`]).
+    /// Downloaders call this after getting [`RetryResult::Retry`].
+    pub fn wants_new_connection(&self) -> bool {
+        self.new_connection
+            || self.url_host().map_or(false, |host| {
+                self.config.retry_session().connection_closed(&host)
+            })
+    }
+
+    /// How long is left until the `--network-deadline` once `after` has
+    /// passed, or `None` without a deadline.
+    ///
+    /// Downloaders that reuse their curl handle for a retry call this with
+    /// the delay from [`RetryResult::Retry`], to shorten the transfer timeout
+    /// that was set when the handle was made.
+    pub fn time_left(&self, after: Duration) -> Option<Duration> {
+        let deadline = self.config.network_deadline()?;
+        Some(deadline.saturating_duration_since(self.clock.now() + after))
+    }
+
+    /// The curl timeouts for the attempt that starts once `after` has passed,
+    /// or `None` without a `--network-deadline`.
+    ///
+    /// `connect` is the connect timeout the handle was made with. Both
+    /// timeouts are cut down to the time left, so later attempts get less
+    /// and less time and a single slow one can't run past the deadline.
+    pub fn attempt_timeouts(&self, after: Duration, connect: Duration) -> Option<AttemptTimeouts> {
+        // Zero would mean no timeout at all to curl.
+        let left = self.time_left(after)?.max(Duration::from_millis(1));
+        Some(AttemptTimeouts {
+            connect: connect.min(left),
+            total: left,
+        })
+    }
+
+    /// Keeps count of the HTTP/2 stream errors in a row, falling back to
+    /// HTTP/1.1 once there are `net.retry-http1-fallback` of them.
+    fn count_http2_stream_errors(&mut self, err: &Error) {
+        let stream_error = err
+            .downcast_ref::<curl::Error>()
+            .map_or(false, |e| e.is_http2_stream_error());
+        if !stream_error {
+            self.http2_stream_errors = 0;
+            return;
+        }
+        self.http2_stream_errors += 1;
+        if self.http1 || self.http1_fallback == 0 || self.http2_stream_errors < self.http1_fallback
+        {
+            return;
+        }
+        self.http1 = true;
+        let host = self.host(err).unwrap_or_else(|| "the server".to_string());
+        let msg = format!(
+            "retrying with HTTP/1.1 instead of HTTP/2 for {host} after {} HTTP/2 stream errors",
+            self.http2_stream_errors
+        );
+        log::info!("{msg}");
+        let _ = self.config.shell().verbose(|shell| shell.note(&msg));
+    }
+
+    /// Whether the next attempt should only connect over IPv4.
+    ///
+    /// With `net.retry-ipv4-fallback`, this is the case once an attempt
+    /// failed to connect, for hosts whose IPv6 addresses are unreachable even
+    /// though curl's happy eyeballs didn't notice. It stays that way for the
+    /// rest of the retries. Downloaders call this after getting
+    /// [`RetryResult::Retry`].
+    pub fn wants_ipv4(&self) -> bool {
+        self.ipv4
+    }
+
+    /// Whether the next attempt should use HTTP/1.1 instead of HTTP/2.
+    ///
+    /// This is the case once `net.retry-http1-fallback` attempts in a row
+    /// failed with an HTTP/2 stream error, which points at something on the
+    /// way, such as a proxy, that breaks HTTP/2. It stays that way for the
+    /// rest of the retries. Downloaders have [`Retry::configure_handle`] act
+    /// on this after getting [`RetryResult::Retry`].
+    pub fn wants_http1(&self) -> bool {
+        self.http1
+    }
+
+    /// Whether [`Retry::try`] would retry if its closure failed with `err`
+    /// right now, without using up a retry.
+    pub fn would_retry(&self, err: &Error) -> bool {
+        match self.spurious_reason(err) {
+            Some(reason) => self.remaining > 0 && !self.reached_retry_limit(reason),
+            None => false,
+        }
+    }
+
+    /// Whether `err`, as returned by [`Retry::try`], was only given up on
+    /// because no retries were left, overall or for its reason in
+    /// `net.retry-limits`.
+    pub fn ran_out_of_retries(&self, err: &Error) -> bool {
+        match self.spurious_reason(err) {
+            Some(reason) => self.remaining == 0 || self.reached_retry_limit(reason),
+            None => false,
+        }
+    }
+
+    /// Whether the errors retried because of `reason` were retried as many
+    /// times as `net.retry-limits` allows.
+    fn reached_retry_limit(&self, reason: SpuriousReason) -> bool {
+        match self.retry_limits.get(&reason) {
+            Some(&limit) => self.retries_by_reason.get(&reason).copied().unwrap_or(0) >= limit,
+            None => false,
+        }
+    }
+
+    /// Whether `net.retry-on` lets the errors classified as `reason` be
+    /// retried. An excluded reason isn't, even if it is included too.
+    fn retries_reason(&self, reason: SpuriousReason) -> bool {
+        !self.retry_on_exclude.contains(&reason)
+            && self
+                .retry_on_include
+                .as_ref()
+                .map_or(true, |include| include.contains(&reason))
+    }
+
+    /// Why `err` is worth retrying, taking this operation's configuration
+    /// into account, or `None` if it isn't.
+    fn spurious_reason(&self, err: &Error) -> Option<SpuriousReason> {
+        self.classify(err).0
+    }
+
+    /// Like [`Retry::spurious_reason`], along with which rule decided, for
+    /// `net.retry-verbose`.
+    fn classify(&self, err: &Error) -> (Option<SpuriousReason>, &'static str) {
+        match retry_marker(err) {
+            Some(false) => return (None, "marked non-retryable"),
+            Some(true) => return (Some(SpuriousReason::Other), "marked retryable"),
+            None => {}
+        }
+        let classified = if err.chain().any(|e| e.is::<CredentialsExpired>()) {
+            match (&self.refresh_credentials, self.credentials_refreshed) {
+                (Some(_), false) => (Some(SpuriousReason::AuthExpired), "credentials expired"),
+                (Some(_), true) => return (None, "refreshed credentials rejected too"),
+                (None, _) => return (None, "credentials expired, with no way to refresh them"),
+            }
+        } else if let Some(classifier) = &self.classifier {
+            return (
+                classifier(err).then_some(SpuriousReason::Other),
+                "custom classifier",
+            );
+        } else {
+            self.classify_built_in(err)
+        };
+        match classified {
+            (Some(reason), _) if !self.retries_reason(reason) => (None, "net.retry-on"),
+            (Some(reason), _)
+                if !self.idempotent && reason.request_phase() != RequestPhase::PreSend =>
+            {
+                (None, "not idempotent")
+            }
+            classified => classified,
+        }
+    }
+
+    /// The part of [`Retry::classify`] that applies without a classifier.
+    fn classify_built_in(&self, err: &Error) -> (Option<SpuriousReason>, &'static str) {
+        if let Some(curl_err) = err.downcast_ref::<curl::Error>() {
+            if curl_err.is_partial_file() {
+                let retry = self.partial_download != PartialDownloadConfig::Fail
+                    && !(self.bytes_received == Some(0) && self.empty_partials > 0);
+                return (
+                    retry.then_some(SpuriousReason::PartialFile),
+                    "net.partial-download",
+                );
+            }
+        }
+        if !self.body_patterns.is_empty() {
+            if let Some(not_200) = err.downcast_ref::<HttpNotSuccessful>() {
+                let body = String::from_utf8_lossy(&not_200.body);
+                if self.body_patterns.iter().any(|p| body.contains(p.as_str())) {
+                    return (
+                        Some(SpuriousReason::HttpStatus(not_200.code)),
+                        "net.retry-body-patterns",
+                    );
+                }
+            }
+        }
+        if !self.retry_on_4xx.is_empty() {
+            if let Some(code) = status_code(err) {
+                if self.retry_on_4xx.contains(&code) {
+                    return (Some(SpuriousReason::HttpStatus(code)), "net.retry-on-4xx");
+                }
+            }
+        }
+        if self.spurious_not_modified && status_code(err) == Some(304) {
+            return (Some(SpuriousReason::HttpStatus(304)), "unexpected 304");
+        }
+        if self.is_proxy_ssl_failure(err) {
+            return (None, "TLS failure with the proxy");
+        }
+        match spurious_reason(err) {
+            Some(SpuriousReason::Ssl) if self.ssl_retries >= MAX_SSL_RETRIES => {
+                (None, "TLS retry limit")
+            }
+            reason => (reason, "built-in rules"),
+        }
+    }
+
+    /// Whether `err` is a failed TLS handshake with the proxy, rather than
+    /// with the server behind it, see [`is_proxy_ssl_failure`].
+    fn is_proxy_ssl_failure(&self, err: &Error) -> bool {
+        let Some(curl_err) = err
+            .chain()
+            .filter_map(|e| e.downcast_ref::<curl::Error>())
+            .find(|e| e.is_ssl_connect_error())
+        else {
+            return false;
+        };
+        match &self.proxy_host {
+            Some(host) => is_proxy_ssl_failure(curl_err, host),
+            None => false,
+        }
+    }
+
+    /// Explains what to check when `err` is a failed TLS handshake with the
+    /// proxy, which isn't retried.
+    fn add_proxy_ssl_hint(&self, err: Error) -> Error {
+        if !self.is_proxy_ssl_failure(&err) {
+            return err;
+        }
+        err.context(
+            "the TLS handshake with the proxy failed, \
+             check the scheme and port of the proxy in the `http.proxy` config \
+             or the `HTTPS_PROXY` environment variable",
+        )
+    }
+
+    /// Tells the user how `err` was classified, for `net.retry-verbose`.
+    fn log_classification(&self, err: &Error) {
+        let (reason, rule) = self.classify(err);
+        let msg = match reason {
+            Some(reason) => format!("classified as spurious: {reason} ({rule})"),
+            None => match status_code(err) {
+                Some(code) => format!("classified as not spurious: {code} ({rule})"),
+                None => format!("classified as not spurious: {} ({rule})", err.root_cause()),
+            },
+        };
+        let _ = self.config.shell().note(msg);
+    }
+
+    /// Turns this into an iterator over the attempts of the operation, for
+    /// callers that drive the attempts themselves; see [`ManualRetry`].
+    pub fn into_attempts(self) -> ManualRetry<'a> {
+        let max_attempts = self.attempts + self.remaining + 1;
+        ManualRetry {
+            retry: self,
+            max_attempts,
+        }
+    }
+
+    /// Records how much data the attempt about to be passed to
+    /// [`Retry::try`] received.
+    ///
+    /// This lets a transfer that was cut short before receiving anything be
+    /// told apart from one that was interrupted part way. It only applies to
+    /// the next call to [`Retry::try`].
+    pub fn set_bytes_received(&mut self, bytes: u64) {
+        self.bytes_received = Some(bytes);
+    }
+
+    /// Records how long the attempt about to be passed to [`Retry::try`]
+    /// spent resolving the host name, as reported by curl.
+    ///
+    /// When that attempt fails to resolve the host, the retry warning says
+    /// how long the lookup took, which tells a slow DNS server apart from
+    /// one that fails right away. It only applies to the next call to
+    /// [`Retry::try`].
+    pub fn set_dns_lookup_time(&mut self, time: Duration) {
+        self.dns_lookup_time = Some(time);
+    }
+
+    /// Tells the user about a retry at the configured `net.retry-warn-level`.
+    ///
+    /// Once the operation has been retrying for [`ESCALATE_WARNINGS_AFTER`],
+    /// this is a warning saying for how long, since that is more likely an
+    /// outage than a blip.
+    ///
+    /// With `net.retry-dedup-window`, a warning for the same host and
+    /// `reason` as one printed less than that long ago is left out, and
+    /// counted so that the next one to be printed says how many were. That
+    /// doesn't apply to the warning saying for how long the operation has
+    /// been retrying, which is never left out.
+    fn report(&self, msg: String, host: Option<&str>, reason: SpuriousReason) -> CargoResult<()> {
+        let elapsed = self.elapsed();
+        if elapsed >= ESCALATE_WARNINGS_AFTER {
+            return self
+                .config
+                .shell()
+                .warn(format!("still failing after {}s: {msg}", elapsed.as_secs()));
+        }
+        if let (Some(window), Some(host)) = (self.dedup_window, host) {
+            let now = self.clock.now();
+            let suppressed = self
+                .config
+                .retry_session()
+                .dedup_warning(host, reason, now, window);
+            match suppressed {
+                None => return Ok(()),
+                Some(0) => {}
+                Some(n) => self
+                    .config
+                    .shell()
+                    .warn(suppressed_message(host, reason, n))?,
+            }
+        }
+        let mut shell = self.config.shell();
+        match self.warn_level {
+            RetryWarnLevel::Warn => shell.warn(msg),
+            RetryWarnLevel::Info => shell.verbose(|s| s.note(&msg)),
+            RetryWarnLevel::Debug => {
+                log::debug!("{}", msg);
+                if self.config.extra_verbose() {
+                    shell.note(msg)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// The host that `err` came from, if known.
+    fn host(&self, err: &Error) -> Option<String> {
+        err.downcast_ref::<HttpNotSuccessful>()
+            .and_then(|e| url_host(&e.url))
+            .or_else(|| self.url_host())
+    }
+
+    /// The host of [`RetryBuilder::url`], if one was given.
+    fn url_host(&self) -> Option<String> {
+        url_host(self.url.as_deref()?)
+    }
+
+    /// The warning for retrying after `err`, before `remaining` goes down.
+    fn message(
+        &self,
+        err: &Error,
+        reason: SpuriousReason,
+        dns_lookup_time: Option<Duration>,
+    ) -> String {
+        if let Some(template) = &self.message_template {
+            let host = self.host(err).unwrap_or_else(|| "unknown host".to_string());
+            return template.render(&RetryMessageFields {
+                attempt: self.attempts + 1,
+                total: self.attempts + self.remaining,
+                remaining: self.remaining,
+                reason: &reason,
+                host: &host,
+                operation: self.operation.as_deref().unwrap_or_default(),
+                error: &err.root_cause(),
+            });
+        }
+        let context = match &self.context {
+            Some(context) => format!(" while {context}"),
+            None => String::new(),
+        };
+        let mut msg = match &self.operation {
+            Some(operation) => format!(
+                "spurious network error ({} tries remaining), retrying {}{}: {}",
+                self.remaining,
+                operation,
+                context,
+                err.root_cause(),
+            ),
+            None => format!(
+                "spurious network error ({} tries remaining){}: {}",
+                self.remaining,
+                context,
+                err.root_cause(),
+            ),
+        };
+        if let (SpuriousReason::Dns, Some(time)) = (reason, dns_lookup_time) {
+            msg.push_str(&format!(" (DNS lookup took {:.1}s)", time.as_secs_f64()));
+        }
+        msg
+    }
+
+    /// Calls the given callback, and returns a [`RetryResult`] which
+    /// indicates whether or not this needs to be called again at some point
+    /// in the future to retry the operation if it failed.
+    pub fn r#try<T>(&mut self, f: impl FnOnce() -> CargoResult<T>) -> RetryResult<T> {
+        if self.is_cancelled() {
+            self.finish_span(false);
+            return RetryResult::Err(RetryCancelled.into());
+        }
+        // The operation is a span from its first attempt until it succeeds
+        // or fails for good, and each attempt a span within it. The field
+        // names follow OpenTelemetry's where there is one.
+        if self.span.is_none() {
+            let host = self.url_host();
+            let span = tracing::info_span!(
+                "retry",
+                operation = self.operation.as_deref(),
+                server.address = host.as_deref(),
+                attempts = tracing::field::Empty,
+                success = tracing::field::Empty,
+            );
+            self.span = Some(OperationSpan {
+                span,
+                attempts: 0,
+                success: false,
+            });
+        }
+        self.attempts += 1;
+        let operation_span = self.span.as_mut().unwrap();
+        operation_span.attempts = self.attempts;
+        let attempt_span = tracing::info_span!(
+            parent: operation_span.span.id(),
+            "attempt",
+            http.request.resend_count = self.attempts - 1,
+            http.response.status_code = tracing::field::Empty,
+            "error.type" = tracing::field::Empty,
+            retried = tracing::field::Empty,
+        );
+        let result = attempt_span.in_scope(f);
+        self.record_connection_close(&result);
+        let code = result.as_ref().err().and_then(status_code);
+        let dns_lookup_time = self.dns_lookup_time.take();
+        if let (true, Err(e)) = (self.verbose, &result) {
+            self.log_classification(e);
+        }
+        let reason = match &result {
+            Err(e) if self.remaining > 0 => self.spurious_reason(e),
+            _ => None,
+        };
+        let is_partial = |e: &Error| {
+            e.downcast_ref::<curl::Error>()
+                .map_or(false, |e| e.is_partial_file())
+        };
+        let bytes_received = self.bytes_received.take();
+        let empty_partial = bytes_received == Some(0) && matches!(&result, Err(e) if is_partial(e));
+        if empty_partial {
+            self.empty_partials += 1;
+        } else {
+            self.empty_partials = 0;
+        }
+        if let Err(e) = &result {
+            self.record_failed_response(e, reason);
+        }
+        let outcome = self.finish_attempt(result, reason, dns_lookup_time, bytes_received);
+        if let Some(code) = code {
+            attempt_span.record("http.response.status_code", code);
+        }
+        if let Some(reason) = reason {
+            attempt_span.record("error.type", reason.to_string().as_str());
+        }
+        attempt_span.record("retried", matches!(outcome, RetryResult::Retry(_)));
+        drop(attempt_span);
+        match &outcome {
+            RetryResult::Success(_) => self.finish_span(true),
+            RetryResult::Err(_) => self.finish_span(false),
+            RetryResult::Retry(_) => {}
+        }
+        outcome
+    }
+
+    /// Like [`Retry::try`], saying what the attempt was doing in the warning
+    /// if its error is retried, such as "spurious network error (2 tries
+    /// remaining) while fetching index for crates-io: ...".
+    ///
+    /// The warning otherwise only shows the root cause of the error, without
+    /// the context `f` may have added to it. An error that isn't retried is
+    /// returned as is.
+    pub fn try_with_context<T>(
+        &mut self,
+        f: impl FnOnce() -> CargoResult<T>,
+        context: impl fmt::Display,
+    ) -> RetryResult<T> {
+        self.context = Some(context.to_string());
+        let result = self.r#try(f);
+        self.context = None;
+        result
+    }
+
+    /// Keeps a summary of `err` in the session for the end-of-run report, if
+    /// it is an unsuccessful HTTP response.
+    fn record_failed_response(&self, err: &Error, reason: Option<SpuriousReason>) {
+        if self.report_capacity == 0 {
+            return;
+        }
+        if let Some(not_200) = err.downcast_ref::<HttpNotSuccessful>() {
+            let response = FailedResponse {
+                code: not_200.code,
+                url: crate::util::errors::redact_url(&not_200.url).into_owned(),
+                reason,
+            };
+            self.config
+                .retry_session()
+                .record_failed_response(response, self.report_capacity);
+        }
+    }
+
+    /// Notes in the session whether the response to the attempt that just
+    /// finished with `result` said `Connection: close`, for
+    /// [`Retry::wants_new_connection`].
+    fn record_connection_close<T>(&self, result: &CargoResult<T>) {
+        let (host, closes) = match result {
+            Ok(_) => (self.url_host(), false),
+            Err(e) => (
+                self.host(e),
+                e.downcast_ref::<HttpNotSuccessful>()
+                    .map_or(false, |e| e.closes_connection()),
+            ),
+        };
+        if let Some(host) = host {
+            self.config
+                .retry_session()
+                .record_connection_close(&host, closes);
+        }
+    }
+
+    /// Closes the span of the operation, see [`Retry::try`].
+    fn finish_span(&mut self, success: bool) {
+        if let Some(mut span) = self.span.take() {
+            span.success = success;
+        }
+    }
+
+    /// Decides what to do about the attempt that just finished with
+    /// `result`, for [`Retry::try`].
+    fn finish_attempt<T>(
+        &mut self,
+        result: CargoResult<T>,
+        reason: Option<SpuriousReason>,
+        dns_lookup_time: Option<Duration>,
+        bytes_received: Option<u64>,
+    ) -> RetryResult<T> {
+        match (result, reason) {
+            (Err(e), Some(reason)) => {
+                if self.reached_retry_limit(reason) {
+                    self.config.retry_metrics().record_give_up(self.attempts);
+                    let limit = self.retry_limits[&reason];
+                    return RetryResult::Err(unwrap_retry_marker(e).context(format!(
+                        "not retrying, the errors retried because of `{reason}` were already \
+                         retried {limit} time(s), the limit in `net.retry-limits`"
+                    )));
+                }
+                if self.retry_limits.contains_key(&reason) {
+                    *self.retries_by_reason.entry(reason).or_default() += 1;
+                }
+                if let (SpuriousReason::AuthExpired, Some(refresh)) =
+                    (reason, &mut self.refresh_credentials)
+                {
+                    self.credentials_refreshed = true;
+                    if let Err(refresh_err) = refresh() {
+                        self.config.retry_metrics().record_give_up(self.attempts);
+                        return RetryResult::Err(refresh_err.context(format!(
+                            "failed to refresh the credentials the server rejected: {}",
+                            e.root_cause()
+                        )));
+                    }
+                }
+                if reason == SpuriousReason::Ssl {
+                    self.ssl_retries += 1;
+                }
+                if self.ipv4_fallback && reason == SpuriousReason::ConnectFailed {
+                    self.ipv4 = true;
+                }
+                self.count_http2_stream_errors(&e);
+                // Without a `RetryBuilder::url`, the session can't say that
+                // the host closed the connection, so this remembers it.
+                self.new_connection = reason == SpuriousReason::HttpStatus(502)
+                    || e.downcast_ref::<curl::Error>()
+                        .map_or(false, |e| e.is_http2_stream_error())
+                    || (self.url.is_none()
+                        && e.downcast_ref::<HttpNotSuccessful>()
+                            .map_or(false, |e| e.closes_connection()));
+                let mut session = self.config.retry_session();
+                if self.retrying_since.is_none() && self.escalate {
+                    self.backoff_level = session.escalate_backoff(self.url_host());
+                }
+                let retry_after = e
+                    .downcast_ref::<HttpNotSuccessful>()
+                    .and_then(|e| e.retry_after());
+                // `Retry-After: 0` asks for the retry right away, whatever the
+                // delays before it were, with or without `net.retry-adaptive`.
+                let retry_now = retry_after == Some(Duration::ZERO);
+                let mut min_backoff = None;
+                if self.adaptive {
+                    if let Some(host) = self.host(&e) {
+                        if let Some(retry_after) = retry_after {
+                            session.record_retry_after(&host, retry_after);
+                        }
+                        min_backoff = session.typical_retry_after(&host);
+                    }
+                }
+                // What this attempt received is downloaded again by the next
+                // one, unless it resumes where this one stopped. Only the
+                // attempts that got some data count towards the limit, so
+                // failing to connect is retried even once it was reached.
+                let resumed = matches!(
+                    reason,
+                    SpuriousReason::PartialFile | SpuriousReason::ShortRange
+                ) && self.resumes_partial_downloads();
+                let over_limit = match bytes_received {
+                    Some(bytes) if bytes > 0 && !resumed => {
+                        let redownloaded = session.record_redownload(bytes);
+                        self.max_redownload.filter(|max| redownloaded > *max)
+                    }
+                    _ => None,
+                };
+                drop(session);
+                if let Some(max) = over_limit {
+                    self.config.retry_metrics().record_give_up(self.attempts);
+                    let (max, max_unit) = crate::util::human_readable_bytes(max);
+                    return RetryResult::Err(unwrap_retry_marker(e).context(format!(
+                        "not retrying, the data downloaded again because of retries would go \
+                         over the `net.retry-max-redownload` limit of {max:.1}{max_unit}"
+                    )));
+                }
+                let mut delay = self.next_backoff(Some(reason), min_backoff);
+                if let Some(requested) = self.requested_delay_ms(&e) {
+                    let max = self.max_backoff_for.get(&reason).copied();
+                    delay = requested.min(max.unwrap_or(self.max_backoff));
+                }
+                if retry_now {
+                    delay = Duration::ZERO;
+                }
+                if self.time_left(delay) == Some(Duration::ZERO) {
+                    self.config.retry_metrics().record_give_up(self.attempts);
+                    return RetryResult::Err(unwrap_retry_marker(e).context(NetworkDeadlinePassed));
+                }
+                self.config.retry_session().record(reason);
+                let msg = self.message(&e, reason, dns_lookup_time);
+                if let Err(e) = self.report(msg, self.host(&e).as_deref(), reason) {
+                    return RetryResult::Err(e);
+                }
+                let event = RetryEvent {
+                    attempt: self.attempts + 1,
+                    max_attempts: self.attempts + self.remaining,
+                    reason,
+                    delay,
+                };
+                self.remaining -= 1;
+                if let Some(observer) = &self.observer {
+                    observer(&event);
+                }
+                self.config
+                    .retry_metrics()
+                    .record_retry(reason, event.attempt, event.delay);
+                RetryResult::Retry(event.delay)
+            }
+            (Err(e), None) => {
+                if self.attempts > 1 || self.spurious_reason(&e).is_some() {
+                    self.config.retry_metrics().record_give_up(self.attempts);
+                }
+                let e = add_proxy_auth_hint(add_redirect_hint(unwrap_retry_marker(e)));
+                RetryResult::Err(self.add_proxy_ssl_hint(e))
+            }
+            (Ok(r), _) => {
+                if self.escalate {
+                    self.config
+                        .retry_session()
+                        .record_success(self.url_host(), self.backoff_reset);
+                }
+                RetryResult::Success(r)
+            }
+        }
+    }
+
+    /// Computes how long to wait before the retry that was just scheduled,
+    /// after an attempt failed because of `reason`, if known.
+    ///
+    /// After a 502 the schedule starts from `net.retry-502-backoff` rather
+    /// than [`INITIAL_BACKOFF`].
+    ///
+    /// With `min_backoff`, from `net.retry-adaptive`, the delay is at least
+    /// that long, plus up to a tenth more as jitter, but no more than
+    /// `net.retry-adaptive-max-backoff`.
+    ///
+    /// A cap for `reason` from [`RetryBuilder::max_backoff_for_reason`]
+    /// replaces both `net.retry-max-backoff` and
+    /// `net.retry-adaptive-max-backoff`.
+    ///
+    /// The jitter never goes below `net.retry-min-backoff`, unless the cap
+    /// for `reason` is shorter still.
+    fn next_backoff(
+        &mut self,
+        reason: Option<SpuriousReason>,
+        min_backoff: Option<Duration>,
+    ) -> Duration {
+        let now = self.clock.now();
+        self.retrying_since.get_or_insert(now);
+        if let Some(fixed) = self.fixed_backoff {
+            return fixed;
+        }
+        let initial = match reason {
+            Some(SpuriousReason::HttpStatus(502)) => self.backoff_502,
+            _ => self.initial_backoff,
+        };
+        let reason_max = reason.and_then(|r| self.max_backoff_for.get(&r)).copied();
+        let max = reason_max.unwrap_or(self.max_backoff);
+        let delay = match self.schedule_position(self.attempts) {
+            Some(attempts) => {
+                let base = backoff_bound(initial, attempts, self.backoff_level, max);
+                let low = (base / 2).max(self.min_backoff.min(max));
+                self.jitter(low..=base.max(low))
+            }
+            None => Duration::ZERO,
+        };
+        match min_backoff {
+            Some(min) => {
+                let max = reason_max.unwrap_or(self.max_adaptive_backoff);
+                let min = min.min(max);
+                let adaptive = self.jitter(min..=min + min / 10);
+                delay.max(adaptive).min(max)
+            }
+            None => delay,
+        }
+    }
+
+    /// The delay asked for by an `X-Retry-After-Ms` header on the response
+    /// `err` is for, with `net.retry-after-ms`, unless a `Retry-After` header
+    /// asked for one too.
+    fn requested_delay_ms(&self, err: &Error) -> Option<Duration> {
+        if !self.retry_after_ms {
+            return None;
+        }
+        let http = err.downcast_ref::<HttpNotSuccessful>()?;
+        if http.retry_after().is_some() {
+            return None;
+        }
+        http.retry_after_ms()
+    }
+
+    /// Picks a delay in `range` at random, see [`RetryBuilder::jitter_rng`].
+    fn jitter(&mut self, range: RangeInclusive<Duration>) -> Duration {
+        match &mut self.jitter_rng {
+            Some(rng) => rng.gen_range(range),
+            None => rand::thread_rng().gen_range(range),
+        }
+    }
+
+    /// Where the retry after the `attempts`th attempt falls on the backoff
+    /// schedule, or `None` if it happens right away.
+    ///
+    /// With `net.retry-fast-first` the first retry is immediate, and the
+    /// schedule starts over with the second one.
+    fn schedule_position(&self, attempts: u32) -> Option<u32> {
+        match (self.fast_first, attempts) {
+            (false, _) => Some(attempts),
+            (true, 1) => None,
+            (true, _) => Some(attempts - 1),
+        }
+    }
+
+    /// Waits for `delay` before the next attempt.
+    ///
+    /// If a cancellation token was given, it is checked periodically, so that
+    /// cancelling doesn't have to wait for the whole delay to pass.
+    pub fn sleep(&self, delay: Duration) {
+        if self.cancel.is_none() {
+            self.sleeper.sleep(delay);
+            return;
+        }
+        let mut left = delay;
+        while !left.is_zero() && !self.is_cancelled() {
+            let step = left.min(CANCEL_CHECK_INTERVAL);
+            self.sleeper.sleep(step);
+            left -= step;
+        }
+    }
+
+    /// Like [`with_retry`], for an async operation: `op` is called to start
+    /// every attempt, and the delays between attempts are waited out with
+    /// `sleep`.
+    ///
+    /// Cargo doesn't use an async runtime itself, so the timer comes from the
+    /// caller, such as `tokio::time::sleep`. The [`Sleeper`] this was built
+    /// with isn't used, and a cancellation token is only checked before each
+    /// attempt.
+    pub async fn try_async_with_sleep<T, F, Fut, S, SleepFut>(
+        &mut self,
+        mut op: F,
+        mut sleep: S,
+    ) -> CargoResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = CargoResult<T>>,
+        S: FnMut(Duration) -> SleepFut,
+        SleepFut: Future<Output = ()>,
+    {
+        loop {
+            // `r#try` fails without calling its closure once cancelled.
+            let result = if self.is_cancelled() {
+                None
+            } else {
+                Some(op().await)
+            };
+            match self.r#try(|| result.expect("attempt skipped without being cancelled")) {
+                RetryResult::Success(r) => return Ok(r),
+                RetryResult::Err(e) => return Err(e),
+                RetryResult::Retry(delay) => sleep(delay).await,
+            }
+        }
+    }
+
+    /// Like [`Retry::try_async_with_sleep`], waiting between attempts with a
+    /// [`ThreadSleep`], which works with any executor.
+    pub async fn try_async<T, F, Fut>(&mut self, op: F) -> CargoResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = CargoResult<T>>,
+    {
+        self.try_async_with_sleep(op, ThreadSleep::new).await
+    }
+}
+
+/// The `tracing` span of a [`Retry`] operation, see [`Retry::try`]. It
+/// closes when dropped, which also covers an operation given up on between
+/// attempts, recorded as not successful.
+struct OperationSpan {
+    span: tracing::Span,
+    /// The number of attempts made so far.
+    attempts: u32,
+    success: bool,
+}
+
+impl Drop for OperationSpan {
+    fn drop(&mut self) {
+        self.span.record("attempts", self.attempts);
+        self.span.record("success", self.success);
+    }
+}
+
+/// A retry that was just scheduled, as passed to a
+/// [`RetryBuilder::observer`].
+#[derive(Clone, Copy, Debug)]
+pub struct RetryEvent {
+    /// The number of the attempt that is about to be made, starting at 1 for
+    /// the initial attempt (so this is at least 2).
+    pub attempt: u32,
+    /// The number of attempts that will be made at most.
+    pub max_attempts: u32,
+    /// Why the previous attempt is being retried.
+    pub reason: SpuriousReason,
+    /// How long until the next attempt.
+    pub delay: Duration,
+}
+
+/// Something displaying the progress of network operations, that wants to
+/// show when one of them is being retried.
+///
+/// `K` is what the operations are told apart by, such as the [`PackageId`]
+/// of the crate being downloaded.
+///
+/// [`PackageId`]: crate::core::PackageId
+pub trait RetryProgressSink<K = String> {
+    /// Called when `item` is about to be attempted for the `attempt`th time,
+    /// out of at most `max_attempts`.
+    fn retrying(&self, item: &K, attempt: u32, max_attempts: u32);
+}
+
+/// A [`RetryBuilder::observer`] that reports the retries of the operation on
+/// `item` to `sink`.
+pub fn progress_observer<K: 'static>(
+    sink: Rc<dyn RetryProgressSink<K>>,
+    item: K,
+) -> impl Fn(&RetryEvent) + 'static {
+    move |event| sink.retrying(&item, event.attempt, event.max_attempts)
+}
+
+/// Receives numbers about the retries of every [`Retry`] in a cargo
+/// invocation, for programs embedding cargo that want to feed them into
+/// their own metrics.
+///
+/// This is installed with [`Config::set_retry_metrics`]; by default retries
+/// go to [`NoopMetricsSink`]. Only [`Retry::try`] reports here, since
+/// [`ManualRetry`] leaves deciding what to retry to its caller.
+pub trait MetricsSink {
+    /// Called when an operation is about to be retried because of `reason`,
+    /// for its `attempt`th attempt, after waiting for `backoff`.
+    fn record_retry(&self, reason: SpuriousReason, attempt: u32, backoff: Duration);
+
+    /// Called when an operation that was retried, or that failed with a
+    /// spurious error but had no retries left, fails for good after
+    /// `attempts` attempts.
+    fn record_give_up(&self, attempts: u32);
+}
+
+/// Picks the URL for each retry, see [`RetryBuilder::rewrite_url`].
+///
+/// This is implemented for every `Fn(&str, u32) -> String`.
+pub trait UrlRewrite {
+    /// The URL for attempt number `attempt` of an operation on `url`.
+    fn rewrite(&self, url: &str, attempt: u32) -> String;
+}
+
+impl<F: Fn(&str, u32) -> String> UrlRewrite for F {
+    fn rewrite(&self, url: &str, attempt: u32) -> String {
+        self(url, attempt)
+    }
+}
+
+impl fmt::Debug for dyn UrlRewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UrlRewrite")
+    }
+}
+
+impl fmt::Debug for dyn MetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsSink")
+    }
+}
+
+/// The [`MetricsSink`] that ignores everything, used unless another one is
+/// installed.
+pub struct NoopMetricsSink;
+
+impl MetricsSink for NoopMetricsSink {
+    fn record_retry(&self, _reason: SpuriousReason, _attempt: u32, _backoff: Duration) {}
+
+    fn record_give_up(&self, _attempts: u32) {}
+}
+
+/// Whether a response with the HTTP status `code` is worth retrying, by
+/// cargo's built-in rules, and why.
+///
+/// Server errors (5xx) are retried, as are `408 Request Timeout` and
+/// `429 Too Many Requests`. The codes opted into with `net.retry-on-4xx` are
+/// covered by [`NetRetryPolicy::spurious_reason_for_status`].
+pub fn spurious_reason_for_status(code: u32) -> Option<SpuriousReason> {
+    match code {
+        408 | 429 | 500..=599 => Some(SpuriousReason::HttpStatus(code)),
+        _ => None,
+    }
+}
+
+/// How far a request got before it failed, as far as the error tells, see
+/// [`SpuriousReason::request_phase`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestPhase {
+    /// The request never fully left the client, so the server can't have
+    /// acted on it.
+    PreSend,
+    /// The request was sent, and may have been processed by the server.
+    PostSend,
+    /// There's no telling.
+    Unknown,
+}
+
+/// Why an error was considered spurious, see [`spurious_reason`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpuriousReason {
+    /// A transient git error, from [`git2`] or [`gix`].
+    Git,
+    /// The connection or the whole transfer took too long.
+    Timeout,
+    /// Couldn't connect to the server.
+    ConnectFailed,
+    /// Couldn't resolve the host or the proxy.
+    Dns,
+    /// Failed to send data, such as a connection reset while uploading.
+    SendError,
+    /// Failed to receive data, such as a connection reset while downloading.
+    RecvError,
+    /// An error in the HTTP/2 framing layer or of a single HTTP/2 stream.
+    Http2,
+    /// The proxy couldn't set up the tunnel to the server.
+    Proxy,
+    /// The TLS handshake failed.
+    Ssl,
+    /// The transfer ended before all of the data arrived.
+    PartialFile,
+    /// A resumed download got a `206 Partial Content` response that ended
+    /// early, see [`ShortRangeResponse`].
+    ShortRange,
+    /// The server responded with this HTTP status.
+    HttpStatus(u32),
+    /// Decided by a [`RetryBuilder::classifier`] or
+    /// [`RetryBuilder::retry_predicate_chain`].
+    Other,
+    /// The server rejected credentials that may have expired, and new ones
+    /// are fetched before retrying, see [`CredentialsExpired`].
+    AuthExpired,
+}
+
+impl fmt::Display for SpuriousReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpuriousReason::Git => f.write_str("git"),
+            SpuriousReason::Timeout => f.write_str("timeout"),
+            SpuriousReason::ConnectFailed => f.write_str("connect"),
+            SpuriousReason::Dns => f.write_str("dns"),
+            SpuriousReason::SendError => f.write_str("send"),
+            SpuriousReason::RecvError => f.write_str("recv"),
+            SpuriousReason::Http2 => f.write_str("http2"),
+            SpuriousReason::Proxy => f.write_str("proxy"),
+            SpuriousReason::Ssl => f.write_str("ssl"),
+            SpuriousReason::PartialFile => f.write_str("partial"),
+            SpuriousReason::ShortRange => f.write_str("short-range"),
+            SpuriousReason::HttpStatus(code) => write!(f, "{}", code),
+            SpuriousReason::Other => f.write_str("other"),
+            SpuriousReason::AuthExpired => f.write_str("auth-expired"),
+        }
+    }
+}
+
+impl SpuriousReason {
+    /// Every reason other than [`SpuriousReason::HttpStatus`], which are
+    /// named by their [`Display`](fmt::Display) output.
+    const NAMED: &'static [SpuriousReason] = &[
+        SpuriousReason::Git,
+        SpuriousReason::Timeout,
+        SpuriousReason::ConnectFailed,
+        SpuriousReason::Dns,
+        SpuriousReason::SendError,
+        SpuriousReason::RecvError,
+        SpuriousReason::Http2,
+        SpuriousReason::Proxy,
+        SpuriousReason::Ssl,
+        SpuriousReason::PartialFile,
+        SpuriousReason::ShortRange,
+        SpuriousReason::Other,
+        SpuriousReason::AuthExpired,
+    ];
+
+    /// How far the request got before failing this way.
+    ///
+    /// Failing to resolve, connect, set up a proxy tunnel or complete the TLS
+    /// handshake happens before any of the request is sent, and a send error
+    /// means it didn't fully leave the client. A request with expired
+    /// credentials was turned down without being acted on, so it counts as
+    /// not sent either. A receive error, a cut-off transfer or an HTTP status
+    /// mean the server got the request. A timeout or an HTTP/2 error can
+    /// happen at either point.
+    pub fn request_phase(&self) -> RequestPhase {
+        match self {
+            SpuriousReason::Dns
+            | SpuriousReason::ConnectFailed
+            | SpuriousReason::Ssl
+            | SpuriousReason::Proxy
+            | SpuriousReason::SendError
+            | SpuriousReason::AuthExpired => RequestPhase::PreSend,
+            SpuriousReason::RecvError
+            | SpuriousReason::PartialFile
+            | SpuriousReason::ShortRange
+            | SpuriousReason::HttpStatus(_) => RequestPhase::PostSend,
+            SpuriousReason::Timeout
+            | SpuriousReason::Http2
+            | SpuriousReason::Git
+            | SpuriousReason::Other => RequestPhase::Unknown,
+        }
+    }
+}
+
+/// Parses the names [`SpuriousReason`] is displayed with.
+impl FromStr for SpuriousReason {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> CargoResult<SpuriousReason> {
+        if let Some(reason) = SpuriousReason::NAMED.iter().find(|r| r.to_string() == s) {
+            return Ok(*reason);
+        }
+        match s.parse::<u32>() {
+            Ok(code @ 100..=599) => Ok(SpuriousReason::HttpStatus(code)),
+            _ => {
+                let names: Vec<_> = SpuriousReason::NAMED
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect();
+                anyhow::bail!(
+                    "unknown retry reason `{s}`, expected an HTTP status code or one of {}",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl Serialize for SpuriousReason {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpuriousReason {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<SpuriousReason, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Waits between polls of something expected to change soon, such as the
+/// index while waiting for a published crate to show up in it.
+///
+/// Each wait lasts the usual interval, or longer if a successful response
+/// since the last wait asked for it with a `Retry-After` header (see
+/// [`RetrySession::record_poll_after`]). Registries whose index is eventually
+/// consistent can use this to pace clients.
+pub struct PollWait<'a> {
+    config: &'a Config,
+    interval: Duration,
+    sleeper: Rc<dyn Sleeper>,
+}
+
+impl<'a> PollWait<'a> {
+    pub fn new(config: &'a Config, interval: Duration) -> PollWait<'a> {
+        PollWait {
+            config,
+            interval,
+            sleeper: Rc::new(ThreadSleeper),
+        }
+    }
+
+    /// Replaces how [`PollWait::wait`] waits.
+    pub fn sleeper(mut self, sleeper: Rc<dyn Sleeper>) -> PollWait<'a> {
+        self.sleeper = sleeper;
+        self
+    }
+
+    /// Waits before the next poll, but no longer than `limit`.
+    pub fn wait(&self, limit: Duration) {
+        let asked = self.config.retry_session().take_poll_after();
+        let delay = asked.map_or(self.interval, |d| d.max(self.interval));
+        self.sleeper.sleep(delay.min(limit));
+    }
+}
+
+/// Retry state shared by every [`Retry`] in a cargo invocation.
+///
+/// This lives on [`Config`], see [`Config::retry_session`].
+#[derive(Debug, Default)]
+pub struct RetrySession {
+    /// Number of retries made so far, by the reason they were made for.
+    reasons: BTreeMap<SpuriousReason, u32>,
+    /// The backoff carried over between operations, by the host they are
+    /// for, see [`RetrySession::backoff_level`].
+    backoff: HashMap<Option<String>, SessionBackoff>,
+    /// `Retry-After` delays seen so far, by host, for `net.retry-adaptive`.
+    retry_after: HashMap<String, Vec<Duration>>,
+    /// See [`RetrySession::record_poll_after`].
+    poll_after: Option<Duration>,
+    /// See [`RetrySession::record_redownload`].
+    redownloaded: u64,
+    /// See [`RetrySession::dedup_warning`].
+    warnings: BTreeMap<(String, SpuriousReason), WarningDedup>,
+    /// See [`RetrySession::record_failed_response`].
+    failed_responses: VecDeque<FailedResponse>,
+    /// See [`RetrySession::note_retry_ceiling`].
+    retry_ceiling_noted: bool,
+    /// See [`RetrySession::note_retry_locked`].
+    retry_locked_noted: bool,
+    /// See [`RetrySession::connection_closed`].
+    closed_connections: HashSet<String>,
+}
+
+/// What is kept of an [`HttpNotSuccessful`] response for the end-of-run
+/// report, see [`RetrySession::record_failed_response`].
+///
+/// The body and headers are left out, so that many failures don't take up
+/// much memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedResponse {
+    pub code: u32,
+    /// Where the response came from, with any secrets in it hidden.
+    pub url: String,
+    /// Why it was retried, or `None` if it wasn't.
+    pub reason: Option<SpuriousReason>,
+}
+
+/// The backoff carried over between the operations on one host, for
+/// `net.retry-escalate`.
+#[derive(Debug, Default)]
+struct SessionBackoff {
+    /// See [`RetrySession::backoff_level`].
+    level: u32,
+    /// Number of operations in a row that succeeded.
+    success_streak: u32,
+}
+
+/// The retry warnings for one host and reason, see
+/// [`RetrySession::dedup_warning`].
+#[derive(Debug)]
+struct WarningDedup {
+    /// When the last one was printed.
+    printed_at: Instant,
+    /// How many have been left out since.
+    suppressed: u32,
+}
+
+impl RetrySession {
+    /// Notes that an operation is being retried because of `reason`.
+    pub fn record(&mut self, reason: SpuriousReason) {
+        *self.reasons.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Keeps `response` for the end-of-run report, forgetting the oldest
+    /// ones so that no more than `capacity` are kept.
+    pub fn record_failed_response(&mut self, response: FailedResponse, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        while self.failed_responses.len() >= capacity {
+            self.failed_responses.pop_front();
+        }
+        self.failed_responses.push_back(response);
+    }
+
+    /// The failed responses kept so far, oldest first.
+    pub fn failed_responses(&self) -> impl Iterator<Item = &FailedResponse> {
+        self.failed_responses.iter()
+    }
+
+    /// Notes that an attempt on `host` succeeded. After `reset_after` of them
+    /// in a row the [`backoff_level`](Self::backoff_level) of `host` goes
+    /// back to 0; a `reset_after` of 0 never resets it.
+    pub fn record_success(&mut self, host: Option<String>, reset_after: u32) {
+        let backoff = self.backoff.entry(host).or_default();
+        backoff.success_streak += 1;
+        if reset_after > 0 && backoff.success_streak >= reset_after {
+            backoff.level = 0;
+        }
+    }
+
+    /// How many extra doublings the backoff of the next operation on `host`
+    /// to retry starts with, with `net.retry-escalate`.
+    ///
+    /// Each operation on `host` that has to retry raises this by one, up to
+    /// a limit, until enough attempts on it in a row succeed. Operations
+    /// without a URL count as being for the same `None` host.
+    pub fn backoff_level(&self, host: Option<&str>) -> u32 {
+        self.backoff
+            .get(&host.map(str::to_string))
+            .map_or(0, |backoff| backoff.level)
+    }
+
+    /// Returns the current backoff level for an operation on `host` that just
+    /// started retrying, raising it for the next one.
+    fn escalate_backoff(&mut self, host: Option<String>) -> u32 {
+        let backoff = self.backoff.entry(host).or_default();
+        let level = backoff.level;
+        backoff.level = (level + 1).min(MAX_BACKOFF_LEVEL);
+        backoff.success_streak = 0;
+        level
+    }
+
+    /// Notes that `host` asked to be retried after `delay`.
+    pub fn record_retry_after(&mut self, host: &str, delay: Duration) {
+        self.retry_after
+            .entry(host.to_string())
+            .or_default()
+            .push(delay);
+    }
+
+    /// Notes that `bytes` received by a failed attempt will be downloaded
+    /// again by its retry, returning how much that adds up to so far.
+    pub fn record_redownload(&mut self, bytes: u64) -> u64 {
+        self.redownloaded = self.redownloaded.saturating_add(bytes);
+        self.redownloaded
+    }
+
+    /// Decides whether to print a retry warning about `host` and `reason`,
+    /// for `net.retry-dedup-window`.
+    ///
+    /// Returns `None` if one was printed less than `window` before `now`, so
+    /// this one should be left out. Otherwise, returns how many were left out
+    /// since the last one printed.
+    pub fn dedup_warning(
+        &mut self,
+        host: &str,
+        reason: SpuriousReason,
+        now: Instant,
+        window: Duration,
+    ) -> Option<u32> {
+        match self.warnings.entry((host.to_string(), reason)) {
+            std::collections::btree_map::Entry::Occupied(mut entry) => {
+                let entry = entry.get_mut();
+                if now.saturating_duration_since(entry.printed_at) < window {
+                    entry.suppressed += 1;
+                    return None;
+                }
+                entry.printed_at = now;
+                Some(std::mem::take(&mut entry.suppressed))
+            }
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(WarningDedup {
+                    printed_at: now,
+                    suppressed: 0,
+                });
+                Some(0)
+            }
+        }
+    }
+
+    /// The total of [`RetrySession::record_redownload`] so far.
+    pub fn redownloaded(&self) -> u64 {
+        self.redownloaded
+    }
+
+    /// Notes that the retries were capped at `net.retry-ceiling`, returning
+    /// whether this is the first time, so the warning is only shown once.
+    pub fn note_retry_ceiling(&mut self) -> bool {
+        !std::mem::replace(&mut self.retry_ceiling_noted, true)
+    }
+
+    /// Notes whether the last response from `host` said
+    /// `Connection: close`.
+    pub fn record_connection_close(&mut self, host: &str, closes: bool) {
+        if closes {
+            self.closed_connections.insert(host.to_string());
+        } else {
+            self.closed_connections.remove(host);
+        }
+    }
+
+    /// Whether the last response from `host` said `Connection: close`, so
+    /// the next request to it, from any operation, should open a new
+    /// connection.
+    pub fn connection_closed(&self, host: &str) -> bool {
+        self.closed_connections.contains(host)
+    }
+
+    /// Notes that the retries asked for were lowered because of `--locked`
+    /// or `--frozen`, returning whether this is the first time, so the
+    /// warning is only shown once.
+    pub fn note_retry_locked(&mut self) -> bool {
+        !std::mem::replace(&mut self.retry_locked_noted, true)
+    }
+
+    /// Notes that a successful response asked to be polled again no sooner
+    /// than `delay` from now, for [`PollWait`].
+    pub fn record_poll_after(&mut self, delay: Duration) {
+        self.poll_after = Some(self.poll_after.map_or(delay, |d| d.max(delay)));
+    }
+
+    /// The longest delay asked for with [`RetrySession::record_poll_after`]
+    /// since the last call to this.
+    pub fn take_poll_after(&mut self) -> Option<Duration> {
+        self.poll_after.take()
+    }
+
+    /// The median of the `Retry-After` delays `host` asked for, if any.
+    pub fn typical_retry_after(&self, host: &str) -> Option<Duration> {
+        let mut delays = self.retry_after.get(host)?.clone();
+        delays.sort();
+        delays.get(delays.len() / 2).copied()
+    }
+
+    /// Number of retries made so far, by reason.
+    pub fn reasons(&self) -> &BTreeMap<SpuriousReason, u32> {
+        &self.reasons
+    }
+
+    /// Total number of retries made so far.
+    pub fn total(&self) -> u32 {
+        self.reasons.values().sum()
+    }
+
+    /// A one-line summary of the retries, such as
+    /// `retries: 5 (503×3, timeout×2)`, or `None` if there were none.
+    ///
+    /// The most common reasons come first.
+    pub fn summary(&self) -> Option<String> {
+        if self.reasons.is_empty() {
+            return None;
+        }
+        let mut reasons: Vec<_> = self.reasons.iter().collect();
+        reasons.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let reasons: Vec<_> = reasons
+            .into_iter()
+            .map(|(reason, count)| format!("{}×{}", reason, count))
+            .collect();
+        Some(format!(
+            "retries: {} ({})",
+            self.total(),
+            reasons.join(", ")
+        ))
+    }
+}
+
+/// Prints the [`RetrySession::summary`] for this invocation, if any retries
+/// were made, when running with `--verbose`.
+///
+/// Before that, it says how many of the retry warnings left out because of
+/// `net.retry-dedup-window` haven't been mentioned yet. After it come the
+/// failed HTTP responses kept for `net.retry-report-capacity`.
+pub fn print_session_summary(config: &Config) -> CargoResult<()> {
+    let suppressed: Vec<_> = config
+        .retry_session()
+        .warnings
+        .iter()
+        .filter(|(_, dedup)| dedup.suppressed > 0)
+        .map(|((host, reason), dedup)| suppressed_message(host, *reason, dedup.suppressed))
+        .collect();
+    for msg in suppressed {
+        config.shell().warn(msg)?;
+    }
+    let summary = config.retry_session().summary();
+    if let Some(summary) = summary {
+        config.shell().verbose(|s| s.note(&summary))?;
+    }
+    let failed: Vec<_> = config
+        .retry_session()
+        .failed_responses()
+        .map(|response| match response.reason {
+            Some(reason) => format!("\n  {} from `{}` ({reason})", response.code, response.url),
+            None => format!("\n  {} from `{}`", response.code, response.url),
+        })
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    config.shell().verbose(|s| {
+        s.note(format!(
+            "last {} failed HTTP responses, oldest first:{}",
+            failed.len(),
+            failed.concat()
+        ))
+    })
+}
+
+/// Fails if any network operation of this invocation needed a retry while
+/// `--strict-network` or `net.retry-strict` is in effect, with the
+/// [`RetrySession::summary`] of them.
+///
+/// The retries themselves still went ahead, so this is checked once the
+/// command is done, and only if it succeeded otherwise.
+pub fn check_strict_retries(config: &Config) -> CargoResult<()> {
+    // Only look at the configuration once something was retried, so commands
+    // that never touch the network don't fail on a broken config file.
+    let summary = match config.retry_session().summary() {
+        Some(summary) => summary,
+        None => return Ok(()),
+    };
+    if !config.strict_network()? {
+        return Ok(());
+    }
+    Err(anyhow::format_err!(summary).context(
+        "network operations were retried, \
+         which isn't allowed with `--strict-network` or `net.retry-strict`",
+    ))
+}
+
+/// Says that `n` retry warnings about `host` and `reason` were left out.
+fn suppressed_message(host: &str, reason: SpuriousReason, n: u32) -> String {
+    format!("{n} more retry warnings for `{host}` ({reason}) suppressed")
+}
+
+/// An iterator over the attempts of an operation, for callers that can't
+/// hand their work to [`with_retry`] as a closure, such as custom event
+/// loops.
+///
+/// It yields an [`Attempt`] for each try the configuration allows. After a
+/// failure, [`ManualRetry::retry_after`] decides whether it is worth another
+/// attempt, and waits for the backoff if so:
+///
+/// ```ignore
+/// let mut attempts = Retry::new(config)?.into_attempts();
+/// while let Some(_attempt) = attempts.next() {
+///     match do_work() {
+///         Ok(v) => return Ok(v),
+///         Err(e) if attempts.retry_after(&e) => continue,
+///         Err(e) => return Err(e),
+///     }
+/// }
+/// ```
+///
+/// Nothing is printed for the retries; callers are expected to report them
+/// as they see fit.
+pub struct ManualRetry<'a> {
+    retry: Retry<'a>,
+    max_attempts: u32,
+}
+
+impl Iterator for ManualRetry<'_> {
+    type Item = Attempt;
+
+    fn next(&mut self) -> Option<Attempt> {
+        if self.retry.is_cancelled() || self.retry.attempts >= self.max_attempts {
+            return None;
+        }
+        if self.retry.attempts > 0 && self.retry.time_left(Duration::ZERO) == Some(Duration::ZERO) {
+            return None;
+        }
+        self.retry.attempts += 1;
+        Some(Attempt {
+            number: self.retry.attempts,
+            max_attempts: self.max_attempts,
+        })
+    }
+}
+
+impl ManualRetry<'_> {
+    /// Whether `err`, from the attempt just made, is worth another attempt,
+    /// waiting for the backoff first if it is.
+    ///
+    /// `err` is classified the way [`Retry::try`] would, taking this
+    /// operation's configuration into account. The backoff is only worked
+    /// out here, so operations that don't need a retry never pick one.
+    pub fn retry_after(&mut self, err: &Error) -> bool {
+        if self.retry.attempts >= self.max_attempts {
+            return false;
+        }
+        let Some(reason) = self.retry.spurious_reason(err) else {
+            return false;
+        };
+        let delay = self.retry.next_backoff(Some(reason), None);
+        self.retry.sleep(delay);
+        true
+    }
+}
+
+/// A single try of an operation, as yielded by [`ManualRetry`].
+pub struct Attempt {
+    number: u32,
+    max_attempts: u32,
+}
+
+impl Attempt {
+    /// The number of this attempt, starting at 1.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The number of attempts that will be made at most.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether no further attempts will follow this one.
+    pub fn is_last(&self) -> bool {
+        self.number >= self.max_attempts
+    }
+}
+
+/// The backoff before jitter after the `attempts`th attempt failed, at the
+/// given [`RetrySession::backoff_level`], for a schedule starting at
+/// `initial` and going no higher than `max`.
+fn backoff_bound(initial: Duration, attempts: u32, level: u32, max: Duration) -> Duration {
+    // `attempts` is at least 1 here, since an attempt just failed.
+    let doublings = (attempts - 1 + level).min(31);
+    initial.saturating_mul(1 << doublings).min(max)
+}
+
+/// Finds the [`NonRetryable`] or [`ForceRetryable`] in the chain of `err`,
+/// however deeply it is buried under contexts, returning whether it makes
+/// `err` retryable.
+///
+/// If there are several, the innermost one wins, as it was added by the code
+/// closest to where the error happened.
+fn retry_marker(err: &Error) -> Option<bool> {
+    let mut marker = None;
+    let mut next = Some(err);
+    while let Some(err) = next.take() {
+        for e in err.chain() {
+            if let Some(e) = e.downcast_ref::<NonRetryable>() {
+                marker = Some(false);
+                next = Some(e.inner());
+                break;
+            }
+            if let Some(e) = e.downcast_ref::<ForceRetryable>() {
+                marker = Some(true);
+                next = Some(e.inner());
+                break;
+            }
+        }
+    }
+    marker
+}
+
+/// Removes a [`NonRetryable`], [`ForceRetryable`] or [`CredentialsExpired`] wrapped directly around
+/// `err`, now that it has served its purpose.
+///
+/// One underneath a context is left alone, since downcasting through the
+/// context would drop it.
+fn unwrap_retry_marker(err: Error) -> Error {
+    match err.chain().next() {
+        Some(e) if e.is::<NonRetryable>() => err.downcast::<NonRetryable>().unwrap().into_inner(),
+        Some(e) if e.is::<ForceRetryable>() => {
+            err.downcast::<ForceRetryable>().unwrap().into_inner()
+        }
+        Some(e) if e.is::<CredentialsExpired>() => {
+            err.downcast::<CredentialsExpired>().unwrap().into_inner()
+        }
+        _ => err,
+    }
+}
+
+/// Explains an error caused by curl giving up on following redirects, which
+/// otherwise only says that the maximum was reached.
+fn add_redirect_hint(err: Error) -> Error {
+    let too_many_redirects = err
+        .chain()
+        .filter_map(|e| e.downcast_ref::<curl::Error>())
+        .any(|e| e.is_too_many_redirects());
+    if !too_many_redirects {
+        return err;
+    }
+    err.context(
+        "gave up after too many redirects (curl's `CURLOPT_MAXREDIRS` limit), \
+         check that the registry URLs in your config, or a proxy, \
+         don't redirect in a loop",
+    )
+}
+
+/// Explains what to do when `err` is a proxy rejecting cargo's credentials
+/// for a tunnel, which isn't retried.
+fn add_proxy_auth_hint(err: Error) -> Error {
+    let proxy_auth = err
+        .chain()
+        .filter_map(|e| e.downcast_ref::<curl::Error>())
+        .filter_map(proxy_tunnel_failure)
+        .any(|failure| failure.status == Some(407));
+    if !proxy_auth {
+        return err;
+    }
+    err.context(
+        "the proxy rejected the credentials for the connection \
+         (407 Proxy Authentication Required), \
+         check the user name and password in the `http.proxy` config \
+         or the `HTTPS_PROXY` environment variable",
+    )
+}
+
+/// Statistics about a retried operation, see [`with_retry_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// How many times the operation ran, including the first try.
+    pub attempts: u32,
+}
+
+impl RetryStats {
+    /// Whether the operation needed more than one attempt.
+    ///
+    /// A failure that was not retried usually points at a real problem (bad
+    /// URL, missing crate, ...), while a failure after retries is more likely
+    /// to be flaky infrastructure.
+    pub fn retried(&self) -> bool {
+        self.attempts > 1
+    }
+}
+
+/// Where the result of an operation came from, see [`with_retry_provenance`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Whether the result was already cached, so the network wasn't used.
+    pub from_cache: bool,
+    /// How many times the network operation ran, including the first try,
+    /// or 0 for a result from the cache.
+    pub attempts: u32,
+}
+
+impl Provenance {
+    /// The provenance of a result from the cache.
+    pub fn cache() -> Provenance {
+        Provenance {
+            from_cache: true,
+            attempts: 0,
+        }
+    }
+
+    /// The provenance of a result from a network operation that ran as
+    /// `stats` says.
+    pub fn network(stats: RetryStats) -> Provenance {
+        Provenance {
+            from_cache: false,
+            attempts: stats.attempts,
+        }
+    }
+}
+
+/// What one attempt of an operation came to, see [`with_retry_trace`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttemptOutcome {
+    Ok,
+    /// The attempt failed, for a spurious reason, or `None` if the error
+    /// wasn't spurious.
+    Err(Option<SpuriousReason>),
+}
+
+/// The outcome of every attempt of an operation, in order, see
+/// [`with_retry_trace`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetryTrace {
+    pub attempts: Vec<AttemptOutcome>,
+}
+
+impl RetryTrace {
+    pub fn stats(&self) -> RetryStats {
+        RetryStats {
+            attempts: self.attempts.len() as u32,
+        }
+    }
+}
+
+/// Describes the attempts for logs, such as "succeeded on attempt 3 after
+/// 503, timeout".
+impl fmt::Display for RetryTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((last, failures)) = self.attempts.split_last() else {
+            return f.write_str("not attempted");
+        };
+        let failures: Vec<_> = failures
+            .iter()
+            .map(|outcome| match outcome {
+                AttemptOutcome::Err(Some(reason)) => reason.to_string(),
+                AttemptOutcome::Err(None) | AttemptOutcome::Ok => "error".to_string(),
+            })
+            .collect();
+        match last {
+            AttemptOutcome::Ok => write!(f, "succeeded on attempt {}", self.attempts.len())?,
+            AttemptOutcome::Err(reason) => {
+                write!(f, "failed on attempt {}", self.attempts.len())?;
+                if let Some(reason) = reason {
+                    write!(f, " with {reason}")?;
+                }
+            }
+        }
+        if !failures.is_empty() {
+            write!(f, " after {}", failures.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Why `err` is likely a transient failure that is worth retrying, or `None`
+/// if it doesn't look like one.
+///
+/// This is the built-in classification, without any of the adjustments made
+/// by a [`Retry`]'s configuration.
+pub fn spurious_reason(err: &Error) -> Option<SpuriousReason> {
+    if let Some(retryable) = retry_marker(err) {
+        return retryable.then_some(SpuriousReason::Other);
+    }
+    if let Some(git_err) = err.downcast_ref::<git2::Error>() {
+        if let Some(reason) = git2_spurious_reason(git_err) {
+            return Some(reason);
+        }
+    }
+    if let Some(curl_err) = err.downcast_ref::<curl::Error>() {
+        if let Some(reason) = curl_spurious_reason(curl_err) {
+            return Some(reason);
+        }
+    }
+    if err.is::<ShortRangeResponse>() {
+        return Some(SpuriousReason::ShortRange);
+    }
+    if let Some(not_200) = err.downcast_ref::<HttpNotSuccessful>() {
+        if let Some(reason) = spurious_reason_for_status(not_200.code) {
+            return Some(reason);
+        }
+    }
+    if let Some(response_err) = err.downcast_ref::<crates_io::ResponseError>() {
+        match response_err {
+            crates_io::ResponseError::Curl(curl_err) => {
+                if let Some(reason) = curl_spurious_reason(curl_err) {
+                    return Some(reason);
+                }
+            }
+            crates_io::ResponseError::Api { code, .. }
+            | crates_io::ResponseError::Code { code, .. } => {
+                if let Some(reason) = spurious_reason_for_status(*code) {
+                    return Some(reason);
+                }
+            }
+            crates_io::ResponseError::Other(_) => {}
+        }
+    }
+
+    use gix::protocol::transport::IsSpuriousError;
+
+    // This covers every way a gitoxide fetch can fail. Negotiation failures,
+    // including a server missing a capability, are not spurious: the same
+    // haves and wants would fail again. The fetch falls back to a full fetch
+    // for those instead, see `needs_full_fetch`.
+    if let Some(err) = err.downcast_ref::<crate::sources::git::fetch::Error>() {
+        if err.is_spurious() {
+            return Some(SpuriousReason::Git);
+        }
+    }
+
+    None
+}
+
+fn git2_spurious_reason(git_err: &git2::Error) -> Option<SpuriousReason> {
+    match git_err.class() {
+        git2::ErrorClass::Net
+        | git2::ErrorClass::Os
+        | git2::ErrorClass::Zlib
+        | git2::ErrorClass::Http => {
+            (git_err.code() != git2::ErrorCode::Certificate).then_some(SpuriousReason::Git)
+        }
+        // Errors of this class come from cargo's own callbacks. Only the ones
+        // made with `transient_callback_error` are retried; anything else,
+        // such as an unknown host key or a denied authentication, would fail
+        // the same way again.
+        git2::ErrorClass::Callback => {
+            is_transient_callback_error(git_err).then_some(SpuriousReason::Git)
+        }
+        _ => None,
+    }
+}
+
+/// Makes the error for a git callback, such as the credential callback, that
+/// failed for a reason that's likely to go away when tried again, such as a
+/// credential helper that didn't start. Such errors are retried, unlike other
+/// errors returned from callbacks.
+pub fn transient_callback_error(msg: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Callback,
+        format!("{msg}{TRANSIENT_CALLBACK_TAG}"),
+    )
+}
+
+/// Whether a git callback failed with an error from
+/// `transient_callback_error`.
+pub fn is_transient_callback_error(git_err: &git2::Error) -> bool {
+    git_err.class() == git2::ErrorClass::Callback
+        && git_err.message().ends_with(TRANSIENT_CALLBACK_TAG)
+}
+
+/// Appended to the message of the errors from `transient_callback_error`.
+/// libgit2 only passes the code, class and message of an error returned from
+/// a callback back to cargo, so the message is the only place to tag it.
+const TRANSIENT_CALLBACK_TAG: &str = " (this may be a temporary failure)";
+
+/// `CURLE_PROXY`, returned by curl 7.73 and later when the handshake with a
+/// proxy fails. `curl-sys` doesn't define it.
+const CURLE_PROXY: curl_sys::CURLcode = 97;
+
+/// A failure to set up the CONNECT tunnel through an HTTP proxy, or the
+/// handshake with another kind of proxy.
+struct ProxyTunnelFailure {
+    /// What the proxy answered the CONNECT request with, if that's known.
+    status: Option<u32>,
+}
+
+/// Whether `curl_err` is about setting up a tunnel through a proxy.
+///
+/// curl reports a CONNECT that the proxy answered with an error status as a
+/// receive error, saying "CONNECT tunnel failed, response 407", or "Received
+/// HTTP code 407 from proxy after CONNECT" before curl 7.71. Telling these
+/// apart from other receive errors needs the message.
+fn proxy_tunnel_failure(curl_err: &curl::Error) -> Option<ProxyTunnelFailure> {
+    let description = curl_err.extra_description().unwrap_or_default();
+    let status_after = |prefix: &str| {
+        let rest = &description[description.find(prefix)? + prefix.len()..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    };
+    if curl_err.is_recv_error() {
+        if description.starts_with("CONNECT tunnel failed") {
+            return Some(ProxyTunnelFailure {
+                status: status_after("response "),
+            });
+        }
+        if description.contains("from proxy after CONNECT") {
+            return Some(ProxyTunnelFailure {
+                status: status_after("HTTP code "),
+            });
+        }
+        None
+    } else if curl_err.code() == CURLE_PROXY {
+        Some(ProxyTunnelFailure { status: None })
+    } else {
+        None
+    }
+}
+
+/// Wraps `err` in a [`RetrySetupError`], unless it already is one.
+fn setup_error(err: Error) -> Error {
+    if err.is::<RetrySetupError>() {
+        err
+    } else {
+        RetrySetupError::new(err).into()
+    }
+}
+
+/// The host part of `url`, if it has one.
+fn url_host(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+/// The host of the proxy cargo connects through, if there is one, see
+/// [`http_proxy_url`].
+fn proxy_host(config: &Config) -> Option<String> {
+    let proxy = http_proxy_url(config).ok()??;
+    let proxy = if proxy.contains("://") {
+        proxy
+    } else {
+        format!("http://{proxy}")
+    };
+    url::Url::parse(&proxy).ok()?.host_str().map(str::to_string)
+}
+
+/// Whether `curl_err` is a failed TLS handshake with the proxy at
+/// `proxy_host`, which is usually down to the proxy's config, rather than
+/// with the server behind it.
+///
+/// OpenSSL names the host it was talking to, as in "OpenSSL SSL_connect:
+/// SSL_ERROR_SYSCALL in connection to proxy.example.com:443". The other TLS
+/// backends don't, so their failures are taken to be the server's, and
+/// retried.
+fn is_proxy_ssl_failure(curl_err: &curl::Error, proxy_host: &str) -> bool {
+    if !curl_err.is_ssl_connect_error() {
+        return false;
+    }
+    let description = curl_err.extra_description().unwrap_or_default();
+    description.contains(&format!("in connection to {proxy_host}:"))
+}
+
+fn curl_spurious_reason(curl_err: &curl::Error) -> Option<SpuriousReason> {
+    if let Some(failure) = proxy_tunnel_failure(curl_err) {
+        // The proxy turning the tunnel down, such as with a `407 Proxy
+        // Authentication Required`, would happen again; see
+        // `add_proxy_auth_hint`. It being unable to reach the server may not.
+        return match failure.status {
+            None => Some(SpuriousReason::Proxy),
+            Some(code) => spurious_reason_for_status(code).map(|_| SpuriousReason::Proxy),
+        };
+    }
+    if curl_err.is_couldnt_connect() {
+        Some(SpuriousReason::ConnectFailed)
+    } else if curl_err.is_couldnt_resolve_proxy() || curl_err.is_couldnt_resolve_host() {
+        Some(SpuriousReason::Dns)
+    } else if curl_err.is_operation_timedout() {
+        Some(SpuriousReason::Timeout)
+    } else if curl_err.is_recv_error() {
+        Some(SpuriousReason::RecvError)
+    } else if curl_err.is_send_error() {
+        Some(SpuriousReason::SendError)
+    } else if curl_err.is_http2_error() || curl_err.is_http2_stream_error() {
+        Some(SpuriousReason::Http2)
+    } else if curl_err.is_ssl_connect_error() || curl_err.is_ssl_shutdown_failed() {
+        // Some TLS backends report a certificate that couldn't be verified
+        // as a generic handshake failure, which retrying won't fix.
+        let description = curl_err.extra_description().unwrap_or_default();
+        let description = description.to_lowercase();
+        if description.contains("certificate") || description.contains("verif") {
+            None
+        } else {
+            Some(SpuriousReason::Ssl)
+        }
+    } else if curl_err.is_partial_file() {
+        Some(SpuriousReason::PartialFile)
+    } else {
+        None
+    }
+}
+
+/// Wrapper method for network call retry logic.
+///
+/// Retry counts provided by Config object `net.retry`. Config shell outputs
+/// a warning on per retry.
+///
+/// Closure must return a `CargoResult`.
+///
+/// # Examples
+///
+/// ```
+/// # use crate::cargo::util::{CargoResult, Config};
+/// # let download_something = || return Ok(());
+/// # let config = Config::default().unwrap();
+/// use cargo::util::network::retry::with_retry;
+/// let cargo_result = with_retry(&config, || download_something());
+/// ```
+pub fn with_retry<T, F>(config: &Config, callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    run_with_retry(&mut Retry::new(config)?, callback)
+}
+
+/// Like [`with_retry`], naming the operation in the retry warnings, as in
+/// "retrying yank of foo v1.2.3".
+pub fn with_retry_named<T, F>(config: &Config, operation: &str, callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    let mut retry = RetryBuilder::new(config).operation(operation).build()?;
+    run_with_retry(&mut retry, callback)
+}
+
+/// Like [`with_retry`], but stops early once `cancel` is set.
+///
+/// The token is checked before every attempt and while waiting between
+/// attempts, so setting it from another thread makes the loop return a
+/// [`RetryCancelled`] error promptly instead of trying the operation again.
+pub fn with_retry_cancellable<T, F>(
+    config: &Config,
+    cancel: Arc<AtomicBool>,
+    callback: F,
+) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    let mut retry = RetryBuilder::new(config).cancel_token(cancel).build()?;
+    run_with_retry(&mut retry, callback)
+}
+
+/// Like [`with_retry`], but also returns how many attempts were made, both
+/// on success and on failure.
+pub fn with_retry_stats<T, F>(config: &Config, callback: F) -> (CargoResult<T>, RetryStats)
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    match Retry::new(config) {
+        Ok(mut retry) => {
+            let result = run_with_retry(&mut retry, callback);
+            (result, retry.stats())
+        }
+        Err(e) => (Err(e), RetryStats::default()),
+    }
+}
+
+/// Like [`with_retry_stats`], for results that may be cached, saying whether
+/// the network was used.
+///
+/// `cached` is called first, and a value it returns is used as is. Otherwise
+/// `callback` is retried as with [`with_retry`], and its result is returned
+/// along with how many attempts it took, both on success and on failure. An
+/// error from `cached` is returned on its own, without calling `callback`.
+pub fn with_retry_provenance<T, C, F>(
+    config: &Config,
+    cached: C,
+    callback: F,
+) -> CargoResult<(CargoResult<T>, Provenance)>
+where
+    C: FnOnce() -> CargoResult<Option<T>>,
+    F: FnMut() -> CargoResult<T>,
+{
+    cached_or_retried(cached, || with_retry_stats(config, callback))
+}
+
+/// Like [`with_retry_provenance`], for a network operation that does its own
+/// retrying, such as one that needs a [`Retry`] set up with a
+/// [`RetryBuilder`], and returns how many attempts it took.
+pub fn cached_or_retried<T, C, N>(
+    cached: C,
+    network: N,
+) -> CargoResult<(CargoResult<T>, Provenance)>
+where
+    C: FnOnce() -> CargoResult<Option<T>>,
+    N: FnOnce() -> (CargoResult<T>, RetryStats),
+{
+    Ok(match cached()? {
+        Some(value) => (Ok(value), Provenance::cache()),
+        None => {
+            let (result, stats) = network();
+            (result, Provenance::network(stats))
+        }
+    })
+}
+
+/// Like [`with_retry_stats`], returning the outcome of each attempt rather
+/// than just how many there were, for tools that want to record why an
+/// operation needed retries even when it succeeded.
+///
+/// This costs an allocation per attempt, so it is only done when asked for.
+pub fn with_retry_trace<T, F>(config: &Config, callback: F) -> (CargoResult<T>, RetryTrace)
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    let retried = Rc::new(RefCell::new(Vec::new()));
+    let observed = retried.clone();
+    let observer = move |event: &RetryEvent| {
+        observed
+            .borrow_mut()
+            .push(AttemptOutcome::Err(Some(event.reason)))
+    };
+    let mut retry = match RetryBuilder::new(config).observer(observer).build() {
+        Ok(retry) => retry,
+        Err(e) => return (Err(e), RetryTrace::default()),
+    };
+    let result = run_with_retry(&mut retry, callback);
+    let last = match &result {
+        Ok(_) => AttemptOutcome::Ok,
+        Err(e) => AttemptOutcome::Err(retry.spurious_reason(e)),
+    };
+    let mut attempts = retried.take();
+    attempts.push(last);
+    (result, RetryTrace { attempts })
+}
+
+/// Like [`with_retry`], calling `refresh` to get new credentials before
+/// retrying an attempt that failed with [`CredentialsExpired`], see
+/// [`RetryBuilder::refresh_credentials`].
+pub fn with_retry_refreshing_credentials<T, F, R>(
+    config: &Config,
+    refresh: R,
+    callback: F,
+) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+    R: FnMut() -> CargoResult<()> + 'static,
+{
+    let mut retry = RetryBuilder::new(config)
+        .refresh_credentials(refresh)
+        .build()?;
+    run_with_retry(&mut retry, callback)
+}
+
+/// Like [`with_retry`], with a way out for when the retries run out, for
+/// operations that can make do with a result that may be out of date, such
+/// as the last copy of an index entry that was in the cache.
+///
+/// Once a spurious error has been retried as many times as allowed,
+/// `on_exhausted` is called with it, and what it returns is used instead of
+/// failing, after warning the user that it may be stale. If it returns
+/// `None`, or the error wasn't spurious, the error is returned as usual.
+pub fn with_retry_fallback<T, F, G>(config: &Config, callback: F, on_exhausted: G) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+    G: FnOnce(&Error) -> Option<T>,
+{
+    let mut retry = Retry::new(config)?;
+    let err = match run_with_retry(&mut retry, callback) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    if !retry.ran_out_of_retries(&err) {
+        return Err(err);
+    }
+    match on_exhausted(&err) {
+        Some(value) => {
+            config.shell().warn(format!(
+                "gave up after {} attempts, continuing with a result that may be out of date: {}",
+                retry.stats().attempts,
+                err
+            ))?;
+            Ok(value)
+        }
+        None => Err(err),
+    }
+}
+
+/// Like [`with_retry`], keeping the error of every failed attempt instead of
+/// only the last one.
+///
+/// If the operation fails after being retried, the last error is returned
+/// with a [`RetryErrors`] listing the errors of all attempts as its context, so
+/// an operation that failed in different ways shows each of them.
+pub fn with_retry_all_errors<T, F>(config: &Config, mut callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    let mut retry = Retry::new(config)?;
+    let mut errors = RetryErrors::default();
+    let result = run_with_retry(&mut retry, || {
+        let result = callback();
+        if let Err(e) = &result {
+            errors.push(e.root_cause());
+        }
+        result
+    });
+    match result {
+        Err(e) if errors.len() > 1 => Err(e.context(errors)),
+        result => result,
+    }
+}
+
+/// What one step of an operation run by [`with_retry_resumable`] came to.
+pub enum Step<S, T> {
+    /// The step is done, and the operation continues from this cursor.
+    Continue(S),
+    /// The operation is done.
+    Done(T),
+}
+
+/// Like [`with_retry`], for operations made of several steps, such as
+/// fetching one page after another, where a failure shouldn't start the
+/// whole operation over.
+///
+/// `callback` makes one step, starting from the cursor it is given, which is
+/// `start` for the first step. A spurious error retries that step from the
+/// same cursor, so the steps that are done stay done. Each step gets the
+/// full number of retries.
+pub fn with_retry_resumable<S, T, F>(config: &Config, start: S, mut callback: F) -> CargoResult<T>
+where
+    F: FnMut(&S) -> CargoResult<Step<S, T>>,
+{
+    let mut cursor = start;
+    loop {
+        let mut retry = Retry::new(config)?;
+        match run_with_retry(&mut retry, || callback(&cursor))? {
+            Step::Continue(next) => cursor = next,
+            Step::Done(value) => return Ok(value),
+        }
+    }
+}
+
+/// Like [`with_retry`], for operations that return [`Poll`], such as queries
+/// against a registry index.
+///
+/// [`Poll::Pending`] isn't a failure: the callback is simply called again
+/// after a short wait, without counting an attempt. It's up to the callback
+/// to make progress in that case, usually by calling `block_until_ready` on
+/// the source before querying it again. An error in [`Poll::Ready`] goes
+/// through the usual spurious error handling.
+pub fn with_retry_poll<T, F>(config: &Config, callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> Poll<CargoResult<T>>,
+{
+    poll_with_retry(&mut Retry::new(config)?, callback)
+}
+
+fn poll_with_retry<T, F>(retry: &mut Retry<'_>, mut callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> Poll<CargoResult<T>>,
+{
+    loop {
+        let Poll::Ready(result) = callback() else {
+            retry.sleep(POLL_PENDING_SLEEP);
+            continue;
+        };
+        match retry.r#try(|| result) {
+            RetryResult::Success(r) => return Ok(r),
+            RetryResult::Err(e) => return Err(e),
+            RetryResult::Retry(delay) => retry.sleep(delay),
+        }
+    }
+}
+
+/// Like [`with_retry`], for operations that stream their output to `dest` as
+/// it is received, such as downloads.
+///
+/// Each attempt writes into a fresh, empty temporary file next to `dest`,
+/// which only replaces `dest` once the attempt succeeds. Whatever a failed
+/// attempt wrote is deleted, so a retry starts from a clean slate instead of
+/// appending to a partial result, and `dest` is never left half written.
+pub fn with_retry_streaming<T, F>(config: &Config, dest: &Path, mut callback: F) -> CargoResult<T>
+where
+    F: FnMut(&mut File) -> CargoResult<T>,
+{
+    use anyhow::Context as _;
+
+    let dir = dest.parent().unwrap_or_else(|| Path::new("."));
+    let (result, file) = run_with_retry(&mut Retry::new(config)?, || {
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("failed to create a temporary file in `{}`", dir.display()))?;
+        let result = callback(file.as_file_mut())?;
+        Ok((result, file))
+    })?;
+    file.persist(dest)
+        .with_context(|| format!("failed to write `{}`", dest.display()))?;
+    Ok(result)
+}
+
+fn run_with_retry<T, F>(retry: &mut Retry<'_>, mut callback: F) -> CargoResult<T>
+where
+    F: FnMut() -> CargoResult<T>,
+{
+    loop {
+        match retry.r#try(&mut callback) {
+            RetryResult::Success(r) => return Ok(r),
+            RetryResult::Err(e) => return Err(e),
+            RetryResult::Retry(delay) => retry.sleep(delay),
+        }
+    }
+}
+
+/// A [`Clock`] and [`Sleeper`] for tests, where sleeping advances the clock
+/// instead of blocking.
+#[cfg(test)]
+struct FakeTime {
+    start: Instant,
+    sleeps: std::cell::RefCell<Vec<Duration>>,
+    clock_calls: std::cell::Cell<u32>,
+}
+
+#[cfg(test)]
+impl FakeTime {
+    fn new() -> Rc<FakeTime> {
+        Rc::new(FakeTime {
+            start: Instant::now(),
+            sleeps: Default::default(),
+            clock_calls: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeTime {
+    fn now(&self) -> Instant {
+        self.clock_calls.set(self.clock_calls.get() + 1);
+        self.start + self.sleeps.borrow().iter().sum::<Duration>()
+    }
+}
+
+#[cfg(test)]
+impl Sleeper for FakeTime {
+    fn sleep(&self, dur: Duration) {
+        self.sleeps.borrow_mut().push(dur);
+    }
+}
+
+#[cfg(test)]
+fn fake_retry<'a>(config: &'a Config, time: &Rc<FakeTime>) -> RetryBuilder<'a> {
+    RetryBuilder::new(config)
+        .sleeper(time.clone())
+        .clock(time.clone())
+}
+
+/// A [`Config`] set up with `cli` as `--config` values, whose shell output is
+/// thrown away.
+#[cfg(test)]
+fn config_with(cli: &[&str]) -> Config {
+    locked_config_with(false, false, cli)
+}
+
+/// Like [`config_with`], also passing `--frozen` and `--locked`.
+#[cfg(test)]
+fn locked_config_with(frozen: bool, locked: bool, cli: &[&str]) -> Config {
+    let mut config = Config::default().unwrap();
+    *config.shell() = crate::core::Shell::from_write(Box::new(Vec::new()));
+    let cli: Vec<_> = cli.iter().map(|s| s.to_string()).collect();
+    config
+        .configure(0, false, None, frozen, locked, false, &None, &[], &cli)
+        .unwrap();
+    config
+}
+
+/// A failed `GET` of `url` answered with `code` and `headers`.
+#[cfg(test)]
+fn http_error_at(code: u32, url: &str, headers: &[&str]) -> HttpNotSuccessful {
+    HttpNotSuccessful {
+        code,
+        method: "GET".to_string(),
+        url: url.to_string(),
+        body: Vec::new(),
+        headers: headers.iter().map(|h| h.to_string()).collect(),
+        redirected_from: None,
+    }
+}
+
+/// A [`RetryHandle`] writing down the options it is given, as
+/// `option=value`.
+#[cfg(test)]
+#[derive(Default)]
+struct RecordingHandle(Vec<String>);
+
+#[cfg(test)]
+impl RetryHandle for RecordingHandle {
+    fn range(&mut self, range: &str) -> Result<(), curl::Error> {
+        self.0.push(format!("range={range}"));
+        Ok(())
+    }
+
+    fn fresh_connect(&mut self, enable: bool) -> Result<(), curl::Error> {
+        self.0.push(format!("fresh_connect={enable}"));
+        Ok(())
+    }
+
+    fn ip_resolve(&mut self, resolve: curl::easy::IpResolve) -> Result<(), curl::Error> {
+        self.0.push(format!("ip_resolve={resolve:?}"));
+        Ok(())
+    }
+
+    fn http_version(&mut self, version: curl::easy::HttpVersion) -> Result<(), curl::Error> {
+        self.0.push(format!("http_version={version:?}"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn http_error(code: u32) -> Error {
+    http_error_at(code, "Uri", &[]).into()
+}
+
+#[test]
+fn with_retry_repeats_the_call_then_works() {
+    use crate::core::Shell;
+
+    //Error HTTP codes (5xx) are considered maybe_spurious and will prompt retry
+    let error1 = http_error(501);
+    let error2 = http_error(502);
+    let mut results: Vec<CargoResult<()>> = vec![Ok(()), Err(error1), Err(error2)];
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let result = with_retry(&config, || results.pop().unwrap());
+    assert!(result.is_ok())
+}
+
+#[test]
+fn with_retry_finds_nested_spurious_errors() {
+    use crate::core::Shell;
+
+    //Error HTTP codes (5xx) are considered maybe_spurious and will prompt retry
+    //String error messages are not considered spurious
+    let error1 = http_error(501);
+    let error1 = anyhow::Error::from(error1.context("A non-spurious wrapping err"));
+    let error2 = http_error(502);
+    let error2 = anyhow::Error::from(error2.context("A second chained error"));
+    let mut results: Vec<CargoResult<()>> = vec![Ok(()), Err(error1), Err(error2)];
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let result = with_retry(&config, || results.pop().unwrap());
+    assert!(result.is_ok())
+}
+
+#[test]
+fn with_retry_stops_when_cancelled() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut attempts = 0;
+    let result: CargoResult<()> = with_retry_cancellable(&config, cancel.clone(), || {
+        attempts += 1;
+        // Cancel from "another thread" while the first attempt is failing.
+        cancel.store(true, Ordering::SeqCst);
+        Err(http_error(503))
+    });
+    assert_eq!(attempts, 1);
+    assert!(result.unwrap_err().is::<RetryCancelled>());
+}
+
+#[test]
+fn with_retry_stats_counts_attempts() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+
+    // A 404 is not spurious, so it fails on the first try.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || Err(http_error(404)));
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1);
+    assert!(!retry.stats().retried());
+
+    // A 503 is retried until the default `net.retry` of 2 is used up.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || Err(http_error(503)));
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 3);
+    assert!(retry.stats().retried());
+}
+
+#[test]
+fn with_retry_poll_calls_again_while_pending() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut results = vec![
+        Poll::Ready(Ok(5)),
+        Poll::Ready(Err(http_error(503))),
+        Poll::Pending,
+    ];
+    let mut calls = 0;
+    let result = poll_with_retry(&mut retry, || {
+        calls += 1;
+        results.pop().unwrap()
+    });
+    assert_eq!(result.unwrap(), 5);
+    assert_eq!(calls, 3);
+    assert_eq!(retry.stats().attempts, 2);
+    // A short wait after `Pending`, then the backoff after the 503.
+    let sleeps = time.sleeps.borrow();
+    assert_eq!(sleeps.len(), 2);
+    assert_eq!(sleeps[0], POLL_PENDING_SLEEP);
+    assert!(INITIAL_BACKOFF / 2 <= sleeps[1] && sleeps[1] <= INITIAL_BACKOFF);
+}
+
+#[test]
+fn with_retry_streaming_discards_failed_writes() {
+    use crate::core::Shell;
+
+    use std::io::Write;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("output");
+    let mut calls = 0;
+    let result = with_retry_streaming(&config, &dest, |file| {
+        calls += 1;
+        if calls == 1 {
+            file.write_all(b"partial ")?;
+            return Err(http_error(503));
+        }
+        file.write_all(b"complete output")?;
+        Ok(calls)
+    });
+    assert_eq!(result.unwrap(), 2);
+    assert_eq!(std::fs::read(&dest).unwrap(), b"complete output");
+    // The file the failed attempt wrote to is gone.
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn classifier_replaces_spurious_detection() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+
+    // A 404 is normally given up on right away.
+    let mut retry = fake_retry(&config, &time)
+        .classifier(|e| matches!(e.downcast_ref::<HttpNotSuccessful>(), Some(e) if e.code == 404))
+        .build()
+        .unwrap();
+    let mut results = vec![Ok(()), Err(http_error(404))];
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+    assert_eq!(retry.stats().attempts, 2);
+
+    // ... and a 503 is no longer spurious.
+    let mut retry = fake_retry(&config, &time)
+        .classifier(|_| false)
+        .build()
+        .unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || Err(http_error(503)));
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1);
+}
+
+#[test]
+fn predicate_chains_combine_classifiers() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let is_404 =
+        |e: &Error| matches!(e.downcast_ref::<HttpNotSuccessful>(), Some(e) if e.code == 404);
+    let chain = |combine| -> Retry<'_> {
+        fake_retry(&config, &time)
+            .retry_predicate_chain(
+                combine,
+                vec![Box::new(|e| spurious_reason(e).is_some()), Box::new(is_404)],
+            )
+            .build()
+            .unwrap()
+    };
+
+    // Only the second predicate matches a 404, and only the first a 503.
+    let retry = chain(PredicateCombine::Any);
+    assert!(retry.would_retry(&http_error(404)));
+    assert!(retry.would_retry(&http_error(503)));
+    assert!(!retry.would_retry(&http_error(403)));
+
+    let retry = chain(PredicateCombine::All);
+    assert!(!retry.would_retry(&http_error(404)));
+    assert!(!retry.would_retry(&http_error(503)));
+    let retry = fake_retry(&config, &time)
+        .retry_predicate_chain(
+            PredicateCombine::All,
+            vec![
+                Box::new(|e| spurious_reason(e).is_some()),
+                Box::new(|_| true),
+            ],
+        )
+        .build()
+        .unwrap();
+    assert!(retry.would_retry(&http_error(503)));
+}
+
+#[test]
+fn session_counts_retries_by_reason() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let timeout = || Error::from(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT));
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut results = vec![Ok(()), Err(http_error(503)), Err(timeout())];
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut results: Vec<CargoResult<()>> =
+        vec![Err(http_error(503)), Err(timeout()), Err(http_error(503))];
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_err());
+
+    // The final 503 of the second operation was given up on, not retried.
+    let session = config.retry_session();
+    assert_eq!(session.total(), 4);
+    assert_eq!(session.reasons()[&SpuriousReason::HttpStatus(503)], 2);
+    assert_eq!(session.reasons()[&SpuriousReason::Timeout], 2);
+    assert_eq!(session.summary().unwrap(), "retries: 4 (timeout×2, 503×2)");
+}
+
+#[test]
+fn session_backoff_resets_after_success_streak() {
+    const INDEX: &str = "https://index.example.com/config.json";
+
+    let config = config_with(&["net.retry-escalate=true"]);
+    let time = FakeTime::new();
+    let flaky_once = |config, url: &str| {
+        let mut retry = fake_retry(config, &time).url(url).build().unwrap();
+        let mut results = vec![Ok(()), Err(http_error(503))];
+        assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+        *time.sleeps.borrow().last().unwrap()
+    };
+
+    // The first flaky operation gets the normal backoff, the next one starts
+    // a doubling higher.
+    let first = flaky_once(&config, INDEX);
+    assert!(INITIAL_BACKOFF / 2 <= first && first <= INITIAL_BACKOFF);
+    let second = flaky_once(&config, INDEX);
+    assert!(INITIAL_BACKOFF <= second && second <= INITIAL_BACKOFF * 2);
+    assert_eq!(
+        config
+            .retry_session()
+            .backoff_level(Some("index.example.com")),
+        2
+    );
+
+    // Enough successes in a row go back to the initial backoff.
+    for _ in 0..DEFAULT_BACKOFF_RESET {
+        let mut retry = fake_retry(&config, &time).url(INDEX).build().unwrap();
+        assert!(run_with_retry(&mut retry, || Ok(())).is_ok());
+    }
+    assert_eq!(
+        config
+            .retry_session()
+            .backoff_level(Some("index.example.com")),
+        0
+    );
+    let third = flaky_once(&config, INDEX);
+    assert!(INITIAL_BACKOFF / 2 <= third && third <= INITIAL_BACKOFF);
+}
+
+#[test]
+fn session_backoff_is_opt_in_and_per_host() {
+    const INDEX: &str = "https://index.example.com/config.json";
+
+    let time = FakeTime::new();
+    let flaky_once = |config, url: &str| {
+        let mut retry = fake_retry(config, &time).url(url).build().unwrap();
+        let mut results = vec![Ok(()), Err(http_error(503))];
+        assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+        *time.sleeps.borrow().last().unwrap()
+    };
+
+    // Off by default: every operation starts from the initial backoff.
+    let config = config_with(&[]);
+    for _ in 0..3 {
+        let delay = flaky_once(&config, INDEX);
+        assert!(INITIAL_BACKOFF / 2 <= delay && delay <= INITIAL_BACKOFF);
+    }
+
+    // A flaky host doesn't slow down the retries for another one.
+    let config = config_with(&["net.retry-escalate=true"]);
+    for _ in 0..3 {
+        flaky_once(&config, INDEX);
+    }
+    let other = flaky_once(&config, "https://static.example.com/foo.crate");
+    assert!(INITIAL_BACKOFF / 2 <= other && other <= INITIAL_BACKOFF);
+    assert_eq!(
+        config
+            .retry_session()
+            .backoff_level(Some("index.example.com")),
+        3
+    );
+}
+
+#[test]
+fn observer_reports_retries_to_progress() {
+    use crate::core::Shell;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct FakeProgress(RefCell<Vec<String>>);
+
+    impl RetryProgressSink for FakeProgress {
+        fn retrying(&self, item: &String, attempt: u32, max_attempts: u32) {
+            self.0
+                .borrow_mut()
+                .push(format!("{item} retry {attempt}/{max_attempts}"));
+        }
+    }
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let progress = Rc::new(FakeProgress::default());
+    let mut retry = fake_retry(&config, &time)
+        .observer(progress_observer(
+            progress.clone(),
+            "foo v1.0.0".to_string(),
+        ))
+        .build()
+        .unwrap();
+    let mut results = vec![Ok(()), Err(http_error(503)), Err(http_error(503))];
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+    assert_eq!(
+        *progress.0.borrow(),
+        ["foo v1.0.0 retry 2/3", "foo v1.0.0 retry 3/3"]
+    );
+}
+
+#[test]
+fn empty_partial_transfers_are_retried_once() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let partial =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_PARTIAL_FILE).into()) };
+
+    // Interrupted part way, every time: retried until out of retries.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    retry.set_bytes_received(100);
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+    retry.set_bytes_received(100);
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+
+    // Nothing at all, twice in a row: the second one is not retried.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    retry.set_bytes_received(0);
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+    retry.set_bytes_received(0);
+    assert!(matches!(retry.r#try(partial), RetryResult::Err(_)));
+
+    // Without a byte count, partial transfers are always retried.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+}
+
+#[test]
+fn resumed_partial_downloads_ask_for_the_rest() {
+    let time = FakeTime::new();
+    let partial =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_PARTIAL_FILE).into()) };
+
+    let config = config_with(&["net.partial-download='resume'"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+    assert!(retry.resumes_partial_downloads());
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 1000).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false", "range=1000-"]);
+
+    // Nothing held on to, so the retry starts over.
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false"]);
+}
+
+#[test]
+fn manual_retry_drives_attempts() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut attempts = fake_retry(&config, &time).build().unwrap().into_attempts();
+    let mut results = vec![Ok(7), Err(http_error(503)), Err(http_error(502))];
+
+    let mut numbers = Vec::new();
+    let mut result = None;
+    while let Some(attempt) = attempts.next() {
+        numbers.push((attempt.number(), attempt.max_attempts(), attempt.is_last()));
+        match results.pop().unwrap() {
+            Ok(v) => {
+                result = Some(v);
+                break;
+            }
+            Err(e) if attempts.retry_after(&e) => continue,
+            Err(e) => panic!("unexpected error {e:?}"),
+        }
+    }
+    assert_eq!(result, Some(7));
+    assert_eq!(numbers, [(1, 3, false), (2, 3, false), (3, 3, true)]);
+    assert_eq!(time.sleeps.borrow().len(), 2);
+
+    // An attempt that works right away never picks a backoff.
+    let mut attempts = fake_retry(&config, &time).build().unwrap().into_attempts();
+    assert!(attempts.next().is_some());
+    assert!(attempts.retry.retrying_since.is_none());
+
+    // Errors are classified with the operation's configuration.
+    let config = config_with(&["net.retry-on-4xx=['409']"]);
+    let time = FakeTime::new();
+    let mut attempts = fake_retry(&config, &time).build().unwrap().into_attempts();
+    assert!(attempts.next().is_some());
+    assert!(!attempts.retry_after(&http_error(404)));
+    assert!(attempts.retry_after(&http_error(409)));
+    assert_eq!(time.sleeps.borrow().len(), 1);
+}
+
+#[test]
+fn ssl_handshake_failures_are_spurious_but_certificates_are_not() {
+    let ssl_error = |code, extra: &str| {
+        let mut err = curl::Error::new(code);
+        err.set_extra(extra.to_string());
+        Error::from(err)
+    };
+    let handshake_timeout = ssl_error(
+        curl_sys::CURLE_SSL_CONNECT_ERROR,
+        "OpenSSL SSL_connect: SSL_ERROR_SYSCALL in connection to crates.io:443",
+    );
+    assert_eq!(
+        spurious_reason(&handshake_timeout),
+        Some(SpuriousReason::Ssl)
+    );
+    let shutdown = ssl_error(curl_sys::CURLE_SSL_SHUTDOWN_FAILED, "");
+    assert_eq!(spurious_reason(&shutdown), Some(SpuriousReason::Ssl));
+
+    let verification = ssl_error(
+        curl_sys::CURLE_PEER_FAILED_VERIFICATION,
+        "SSL certificate problem: unable to get local issuer certificate",
+    );
+    assert_eq!(spurious_reason(&verification), None);
+    let schannel_verification = ssl_error(
+        curl_sys::CURLE_SSL_CONNECT_ERROR,
+        "schannel: SEC_E_UNTRUSTED_ROOT (0x80090325) - The certificate chain was issued by an authority that is not trusted.",
+    );
+    assert_eq!(spurious_reason(&schannel_verification), None);
+}
+
+#[test]
+fn ssl_failures_are_retried_once() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || {
+        Err(curl::Error::new(curl_sys::CURLE_SSL_CONNECT_ERROR).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1 + MAX_SSL_RETRIES);
+}
+
+#[test]
+fn backoff_only_applies_on_retry() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+
+    // Succeeding right away never sleeps nor looks at the clock.
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(run_with_retry(&mut retry, || Ok(())).is_ok());
+    assert!(time.sleeps.borrow().is_empty());
+    assert_eq!(time.clock_calls.get(), 0);
+
+    // Each retry waits for an exponentially growing, jittered delay.
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut results = vec![Ok(()), Err(http_error(503)), Err(http_error(503))];
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+    let sleeps = time.sleeps.borrow();
+    assert_eq!(sleeps.len(), 2);
+    assert!(INITIAL_BACKOFF / 2 <= sleeps[0] && sleeps[0] <= INITIAL_BACKOFF);
+    assert!(INITIAL_BACKOFF <= sleeps[1] && sleeps[1] <= INITIAL_BACKOFF * 2);
+}
+
+#[test]
+fn curle_http2_stream_is_spurious() {
+    let code = curl_sys::CURLE_HTTP2_STREAM;
+    let err = curl::Error::new(code);
+    assert_eq!(curl_spurious_reason(&err), Some(SpuriousReason::Http2));
+}
+
+#[test]
+fn http2_stream_errors_retry_on_a_new_connection() {
+    let config = Config::default().unwrap();
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let stream_error = || Err(curl::Error::new(curl_sys::CURLE_HTTP2_STREAM).into());
+    assert!(matches!(
+        retry.r#try(stream_error),
+        RetryResult::<()>::Retry(_)
+    ));
+    assert!(retry.wants_new_connection());
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=true"]);
+    let timeout = || Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into());
+    assert!(matches!(retry.r#try(timeout), RetryResult::<()>::Retry(_)));
+    assert!(!retry.wants_new_connection());
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false"]);
+}
+
+#[test]
+fn retry_limits_cap_retries_by_reason() {
+    let config = config_with(&["net.retry=5", "net.retry-limits.timeout=1"]);
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let timeout =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into()) };
+    let unavailable = || -> CargoResult<()> { Err(http_error(503)) };
+    assert!(matches!(retry.r#try(unavailable), RetryResult::Retry(_)));
+    assert!(matches!(retry.r#try(timeout), RetryResult::Retry(_)));
+    assert!(matches!(retry.r#try(unavailable), RetryResult::Retry(_)));
+    // The second timeout goes over its limit, with retries left for 503s.
+    let err = curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into();
+    assert!(!retry.would_retry(&err));
+    assert!(retry.would_retry(&http_error(503)));
+    let RetryResult::Err(err) = retry.r#try(timeout) else {
+        panic!("expected the timeout not to be retried");
+    };
+    assert_eq!(retry.remaining(), 2);
+    assert!(retry.ran_out_of_retries(&err));
+    assert_eq!(
+        err.to_string(),
+        "not retrying, the errors retried because of `timeout` were already retried \
+         1 time(s), the limit in `net.retry-limits`"
+    );
+}
+
+#[test]
+fn retry_on_limits_the_reasons_retried() {
+    let timeout =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into()) };
+    let unavailable = || -> CargoResult<()> { Err(http_error(503)) };
+    let time = FakeTime::new();
+
+    let config = config_with(&["net.retry-on.include=['503']"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(unavailable), RetryResult::Retry(_)));
+    assert!(matches!(retry.r#try(timeout), RetryResult::Err(_)));
+
+    // Exclusion wins over inclusion, and over a limit for the reason.
+    let config = config_with(&[
+        "net.retry-on.include=['503', 'timeout']",
+        "net.retry-on.exclude=['timeout']",
+        "net.retry-limits.timeout=3",
+    ]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(timeout), RetryResult::Err(_)));
+    assert!(matches!(retry.r#try(unavailable), RetryResult::Retry(_)));
+
+    let config = config_with(&["net.retry-on.exclude=['bogus']"]);
+    let err = NetRetryPolicy::new(&config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`net.retry-on.exclude` must list retry reasons"
+    );
+}
+
+#[test]
+fn repeated_http2_stream_errors_fall_back_to_http1() {
+    let stream_error = || Err(curl::Error::new(curl_sys::CURLE_HTTP2_STREAM).into());
+    let timeout = || Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into());
+    let time = FakeTime::new();
+    let config = config_with(&["net.retry=5"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    // The errors have to come in a row.
+    assert!(matches!(
+        retry.r#try(stream_error),
+        RetryResult::<()>::Retry(_)
+    ));
+    assert!(matches!(retry.r#try(timeout), RetryResult::<()>::Retry(_)));
+    assert!(matches!(
+        retry.r#try(stream_error),
+        RetryResult::<()>::Retry(_)
+    ));
+    assert!(!retry.wants_http1());
+    assert!(matches!(
+        retry.r#try(stream_error),
+        RetryResult::<()>::Retry(_)
+    ));
+    assert!(retry.wants_http1());
+    // It sticks for the rest of the retries.
+    assert!(matches!(retry.r#try(timeout), RetryResult::<()>::Retry(_)));
+    assert!(retry.wants_http1());
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false", "http_version=V11"]);
+
+    let config = config_with(&["net.retry=5", "net.retry-http1-fallback=3"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    for _ in 0..2 {
+        assert!(matches!(
+            retry.r#try(stream_error),
+            RetryResult::<()>::Retry(_)
+        ));
+    }
+    assert!(!retry.wants_http1());
+    assert!(matches!(
+        retry.r#try(stream_error),
+        RetryResult::<()>::Retry(_)
+    ));
+    assert!(retry.wants_http1());
+
+    let config = config_with(&["net.retry=5", "net.retry-http1-fallback=0"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    for _ in 0..4 {
+        assert!(matches!(
+            retry.r#try(stream_error),
+            RetryResult::<()>::Retry(_)
+        ));
+    }
+    assert!(!retry.wants_http1());
+}
+
+#[test]
+fn curl_errors_are_classified_by_kind() {
+    let reason = |code| curl_spurious_reason(&curl::Error::new(code));
+    assert_eq!(
+        reason(curl_sys::CURLE_COULDNT_CONNECT),
+        Some(SpuriousReason::ConnectFailed)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_COULDNT_RESOLVE_HOST),
+        Some(SpuriousReason::Dns)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_COULDNT_RESOLVE_PROXY),
+        Some(SpuriousReason::Dns)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_OPERATION_TIMEDOUT),
+        Some(SpuriousReason::Timeout)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_RECV_ERROR),
+        Some(SpuriousReason::RecvError)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_SEND_ERROR),
+        Some(SpuriousReason::SendError)
+    );
+    assert_eq!(reason(curl_sys::CURLE_HTTP2), Some(SpuriousReason::Http2));
+    assert_eq!(
+        reason(curl_sys::CURLE_SSL_CONNECT_ERROR),
+        Some(SpuriousReason::Ssl)
+    );
+    assert_eq!(
+        reason(curl_sys::CURLE_PARTIAL_FILE),
+        Some(SpuriousReason::PartialFile)
+    );
+    assert_eq!(reason(curl_sys::CURLE_URL_MALFORMAT), None);
+    assert_eq!(reason(curl_sys::CURLE_LOGIN_DENIED), None);
+}
+
+#[cfg(test)]
+fn curl_error_with_message(code: curl_sys::CURLcode, msg: &str) -> curl::Error {
+    let mut err = curl::Error::new(code);
+    err.set_extra(msg.to_string());
+    err
+}
+
+#[test]
+fn transient_proxy_tunnel_failures_are_spurious() {
+    let reason = |code, msg| curl_spurious_reason(&curl_error_with_message(code, msg));
+    assert_eq!(
+        reason(
+            curl_sys::CURLE_RECV_ERROR,
+            "CONNECT tunnel failed, response 503"
+        ),
+        Some(SpuriousReason::Proxy)
+    );
+    assert_eq!(
+        reason(
+            curl_sys::CURLE_RECV_ERROR,
+            "Received HTTP code 502 from proxy after CONNECT"
+        ),
+        Some(SpuriousReason::Proxy)
+    );
+    assert_eq!(
+        reason(CURLE_PROXY, "Proxy handshake error"),
+        Some(SpuriousReason::Proxy)
+    );
+    // A proxy refusing to connect to the server would refuse again.
+    assert_eq!(
+        reason(
+            curl_sys::CURLE_RECV_ERROR,
+            "CONNECT tunnel failed, response 403"
+        ),
+        None
+    );
+    // Other receive errors are unaffected.
+    assert_eq!(
+        reason(curl_sys::CURLE_RECV_ERROR, "Connection reset by peer"),
+        Some(SpuriousReason::RecvError)
+    );
+}
+
+#[test]
+fn proxy_auth_rejections_are_not_retried() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    for msg in [
+        "CONNECT tunnel failed, response 407",
+        "Received HTTP code 407 from proxy after CONNECT",
+    ] {
+        let err = curl_error_with_message(curl_sys::CURLE_RECV_ERROR, msg);
+        assert_eq!(curl_spurious_reason(&err), None, "{msg}");
+
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        let RetryResult::Err(err) = retry.r#try(|| -> CargoResult<()> {
+            Err(curl_error_with_message(curl_sys::CURLE_RECV_ERROR, msg).into())
+        }) else {
+            panic!("expected an error");
+        };
+        assert_eq!(retry.stats().attempts, 1);
+        assert!(err.to_string().starts_with(
+            "the proxy rejected the credentials for the connection \
+             (407 Proxy Authentication Required), check the user name and password"
+        ));
+        assert!(err.root_cause().is::<curl::Error>());
+    }
+}
+
+#[test]
+fn tls_failures_with_the_proxy_are_not_retried() {
+    let config = config_with(&["http.proxy='https://proxy.example.com:3128'"]);
+    let time = FakeTime::new();
+    let ssl_error = |msg: &str| -> CargoResult<()> {
+        Err(curl_error_with_message(curl_sys::CURLE_SSL_CONNECT_ERROR, msg).into())
+    };
+
+    // OpenSSL names the proxy as the host it was talking to.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let RetryResult::Err(err) = retry.r#try(|| {
+        ssl_error("OpenSSL SSL_connect: SSL_ERROR_SYSCALL in connection to proxy.example.com:3128")
+    }) else {
+        panic!("expected an error");
+    };
+    assert_eq!(retry.stats().attempts, 1);
+    assert!(err
+        .to_string()
+        .starts_with("the TLS handshake with the proxy failed"));
+    assert!(err.root_cause().is::<curl::Error>());
+
+    // Or the server behind it, which is retried as before.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(
+        retry.r#try(|| {
+            ssl_error("OpenSSL SSL_connect: SSL_ERROR_SYSCALL in connection to index.crates.io:443")
+        }),
+        RetryResult::Retry(_)
+    ));
+
+    // Without a host to go by, the failure is taken to be the server's.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(
+        retry.r#try(|| ssl_error("schannel: failed to receive handshake")),
+        RetryResult::Retry(_)
+    ));
+}
+
+#[test]
+fn curl_errors_are_tagged_with_request_phase() {
+    let phase = |code| {
+        curl_spurious_reason(&curl::Error::new(code))
+            .unwrap()
+            .request_phase()
+    };
+    for code in [
+        curl_sys::CURLE_COULDNT_CONNECT,
+        curl_sys::CURLE_COULDNT_RESOLVE_HOST,
+        curl_sys::CURLE_COULDNT_RESOLVE_PROXY,
+        curl_sys::CURLE_SSL_CONNECT_ERROR,
+        curl_sys::CURLE_SEND_ERROR,
+    ] {
+        assert_eq!(phase(code), RequestPhase::PreSend, "{code}");
+    }
+    for code in [curl_sys::CURLE_RECV_ERROR, curl_sys::CURLE_PARTIAL_FILE] {
+        assert_eq!(phase(code), RequestPhase::PostSend, "{code}");
+    }
+    for code in [
+        curl_sys::CURLE_OPERATION_TIMEDOUT,
+        curl_sys::CURLE_HTTP2,
+        curl_sys::CURLE_HTTP2_STREAM,
+    ] {
+        assert_eq!(phase(code), RequestPhase::Unknown, "{code}");
+    }
+}
+
+#[test]
+fn non_idempotent_operations_only_retry_before_sending() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let curl_error = |code| move || -> CargoResult<()> { Err(curl::Error::new(code).into()) };
+
+    let mut retry = fake_retry(&config, &time)
+        .idempotent(false)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        retry.r#try(curl_error(curl_sys::CURLE_SEND_ERROR)),
+        RetryResult::Retry(_)
+    ));
+    assert!(matches!(
+        retry.r#try(curl_error(curl_sys::CURLE_RECV_ERROR)),
+        RetryResult::Err(_)
+    ));
+
+    let mut retry = fake_retry(&config, &time)
+        .idempotent(false)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) }),
+        RetryResult::Err(_)
+    ));
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(
+        retry.r#try(curl_error(curl_sys::CURLE_RECV_ERROR)),
+        RetryResult::Retry(_)
+    ));
+}
+
+#[test]
+fn git2_errors_are_classified_by_class() {
+    let reason = |code, class| git2_spurious_reason(&git2::Error::new(code, class, "oops"));
+    for class in [
+        git2::ErrorClass::Net,
+        git2::ErrorClass::Os,
+        git2::ErrorClass::Zlib,
+        git2::ErrorClass::Http,
+    ] {
+        assert_eq!(
+            reason(git2::ErrorCode::GenericError, class),
+            Some(SpuriousReason::Git)
+        );
+        assert_eq!(reason(git2::ErrorCode::Certificate, class), None);
+    }
+    assert_eq!(
+        reason(git2::ErrorCode::NotFound, git2::ErrorClass::Reference),
+        None
+    );
+    assert_eq!(reason(git2::ErrorCode::Auth, git2::ErrorClass::Ssh), None);
+}
+
+#[test]
+fn gix_fetch_errors_are_classified() {
+    use crate::sources::git::fetch::{self, needs_full_fetch};
+    use gix::protocol::fetch::response::Error as ResponseError;
+    use gix::protocol::transport::client;
+    use gix::remote::fetch::Error as FetchError;
+
+    let config = config_with(&[]);
+    let time = FakeTime::new();
+
+    // A connection dropped while fetching is retried.
+    let reset = || -> CargoResult<()> {
+        let io_err = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let err = FetchError::FetchResponse(ResponseError::Transport(client::Error::Io(io_err)));
+        Err(fetch::Error::Fetch(err).into())
+    };
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(reset), RetryResult::Retry(_)));
+    assert!(!needs_full_fetch(
+        reset().unwrap_err().downcast_ref::<fetch::Error>().unwrap()
+    ));
+
+    // A server without a capability the fetch needs isn't retried, but makes
+    // the fetch fall back to a full one.
+    let missing = || -> CargoResult<()> {
+        let err = ResponseError::MissingServerCapability { feature: "shallow" };
+        Err(fetch::Error::Fetch(FetchError::FetchResponse(err)).into())
+    };
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(missing), RetryResult::Err(_)));
+    assert!(needs_full_fetch(
+        missing()
+            .unwrap_err()
+            .downcast_ref::<fetch::Error>()
+            .unwrap()
+    ));
+}
+
+#[test]
+fn transient_callback_errors_are_retried() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+
+    let mut calls = 0;
+    let result = with_retry(&config, || {
+        calls += 1;
+        if calls == 1 {
+            Err(transient_callback_error("credential helper didn't start").into())
+        } else {
+            Ok(calls)
+        }
+    });
+    assert_eq!(result.unwrap(), 2);
+
+    let mut calls = 0;
+    let result: CargoResult<()> = with_retry(&config, || {
+        calls += 1;
+        Err(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Callback,
+            "error: unknown SSH host key",
+        )
+        .into())
+    });
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .starts_with("error: unknown SSH host key"));
+    assert_eq!(calls, 1);
+
+    let mut calls = 0;
+    let result: CargoResult<()> = with_retry(&config, || {
+        calls += 1;
+        Err(git2::Error::new(
+            git2::ErrorCode::Auth,
+            git2::ErrorClass::Callback,
+            "credentials were rejected",
+        )
+        .into())
+    });
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .starts_with("credentials were rejected"));
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn expired_credentials_are_refreshed_once() {
+    use crate::core::Shell;
+    use std::cell::RefCell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let expired =
+        |msg: &str| -> Error { CredentialsExpired::new(anyhow::format_err!("{msg}")).into() };
+
+    // A stubbed credential provider, which hands out a fresh token the second
+    // time it is asked.
+    let tokens = Rc::new(RefCell::new(vec!["fresh", "expired"]));
+    let token = Rc::new(RefCell::new(tokens.borrow_mut().pop().unwrap()));
+    let refresh = {
+        let (tokens, token) = (tokens.clone(), token.clone());
+        move || {
+            *token.borrow_mut() = tokens.borrow_mut().pop().unwrap();
+            Ok(())
+        }
+    };
+    let mut retry = fake_retry(&config, &time)
+        .refresh_credentials(refresh)
+        .build()
+        .unwrap();
+    let fetch = || match *token.borrow() {
+        "fresh" => Ok("fetched"),
+        _ => Err(expired("token expired")),
+    };
+    assert_eq!(run_with_retry(&mut retry, fetch).unwrap(), "fetched");
+    assert!(tokens.borrow().is_empty());
+    assert_eq!(retry.stats().attempts, 2);
+
+    // New credentials that are rejected too mean that access is denied.
+    let mut retry = fake_retry(&config, &time)
+        .refresh_credentials(|| Ok(()))
+        .build()
+        .unwrap();
+    let err = run_with_retry(&mut retry, || -> CargoResult<()> {
+        Err(expired("access denied"))
+    })
+    .unwrap_err();
+    assert_eq!(err.to_string(), "access denied");
+    assert!(!err.is::<CredentialsExpired>());
+    assert_eq!(retry.stats().attempts, 2);
+
+    // So does an authentication failure that isn't marked as expired.
+    let mut retry = fake_retry(&config, &time)
+        .refresh_credentials(|| panic!("not expired"))
+        .build()
+        .unwrap();
+    let result = run_with_retry(&mut retry, || -> CargoResult<()> {
+        Err(git2::Error::new(git2::ErrorCode::Auth, git2::ErrorClass::Callback, "denied").into())
+    });
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1);
+
+    // Without a way to refresh them, expired credentials aren't retried.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result = run_with_retry(&mut retry, || -> CargoResult<()> {
+        Err(expired("token expired"))
+    });
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1);
+
+    let mut retry = fake_retry(&config, &time)
+        .refresh_credentials(|| anyhow::bail!("credential helper crashed"))
+        .build()
+        .unwrap();
+    let err = run_with_retry(&mut retry, || -> CargoResult<()> {
+        Err(expired("token expired"))
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "failed to refresh the credentials the server rejected: token expired"
+    );
+    assert_eq!(err.root_cause().to_string(), "credential helper crashed");
+
+    // The server turned the request down, so a non-idempotent operation can
+    // repeat it too, but `net.retry-on` still applies.
+    assert_eq!(
+        SpuriousReason::AuthExpired.request_phase(),
+        RequestPhase::PreSend
+    );
+    let mut retry = fake_retry(&config, &time)
+        .idempotent(false)
+        .refresh_credentials(|| Ok(()))
+        .build()
+        .unwrap();
+    let result = retry.r#try(|| -> CargoResult<()> { Err(expired("token expired")) });
+    assert!(matches!(result, RetryResult::Retry(_)));
+
+    let config = config_with(&["net.retry-on.exclude=['auth-expired']"]);
+    let mut retry = fake_retry(&config, &time)
+        .refresh_credentials(|| panic!("excluded by net.retry-on"))
+        .build()
+        .unwrap();
+    let result = retry.r#try(|| -> CargoResult<()> { Err(expired("token expired")) });
+    assert!(matches!(result, RetryResult::Err(_)));
+}
+
+#[test]
+fn would_retry_does_not_use_up_retries() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let remaining = retry.remaining;
+    assert!(retry.would_retry(&http_error(503)));
+    assert!(retry.would_retry(&http_error(503)));
+    assert!(!retry.would_retry(&http_error(404)));
+    assert_eq!(retry.remaining, remaining);
+
+    // Once the retries run out, nothing would be retried.
+    let result: CargoResult<()> = run_with_retry(&mut retry, || Err(http_error(503)));
+    assert!(result.is_err());
+    assert!(!retry.would_retry(&http_error(503)));
+}
+
+#[test]
+fn dns_failures_report_lookup_time() {
+    let config = Config::default().unwrap();
+    let time = FakeTime::new();
+    let retry = fake_retry(&config, &time).build().unwrap();
+    let err: Error = curl::Error::new(curl_sys::CURLE_COULDNT_RESOLVE_HOST).into();
+    assert_eq!(spurious_reason(&err), Some(SpuriousReason::Dns));
+    let lookup = Some(Duration::from_millis(9800));
+    assert!(retry
+        .message(&err, SpuriousReason::Dns, lookup)
+        .ends_with(" (DNS lookup took 9.8s)"));
+    assert!(!retry
+        .message(&err, SpuriousReason::Dns, None)
+        .contains("DNS lookup took"));
+
+    // Only resolve failures mention the lookup.
+    let err: Error = curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into();
+    assert!(!retry
+        .message(&err, SpuriousReason::Timeout, lookup)
+        .contains("DNS lookup took"));
+}
+
+#[test]
+fn non_retryable_errors_are_not_retried() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> =
+        run_with_retry(
+            &mut retry,
+            || Err(NonRetryable::new(http_error(503)).into()),
+        );
+    let err = result.unwrap_err();
+    assert_eq!(retry.stats().attempts, 1);
+    // The wrapper is gone from the final error.
+    assert!(!err.is::<NonRetryable>());
+    assert_eq!(err.downcast_ref::<HttpNotSuccessful>().unwrap().code, 503);
+
+    // It is also honored underneath a context.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || {
+        Err(anyhow::Error::from(NonRetryable::new(http_error(503))).context("index is gone"))
+    });
+    assert_eq!(result.unwrap_err().to_string(), "index is gone");
+    assert_eq!(retry.stats().attempts, 1);
+
+    // And underneath several.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || {
+        Err(anyhow::Error::from(NonRetryable::new(http_error(503)))
+            .context("failed to fetch `foo`")
+            .context("failed to update the index"))
+    });
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "failed to update the index"
+    );
+    assert_eq!(retry.stats().attempts, 1);
+
+    // The innermost marker wins over one further out.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || {
+        let inner =
+            anyhow::Error::from(NonRetryable::new(http_error(503))).context("index is gone");
+        Err(ForceRetryable::new(inner).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(retry.stats().attempts, 1);
+}
+
+#[test]
+fn force_retryable_errors_are_retried() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut results: Vec<CargoResult<()>> =
+        vec![Ok(()), Err(ForceRetryable::new(http_error(404)).into())];
+    let result = run_with_retry(&mut retry, || results.pop().unwrap());
+    assert!(result.is_ok());
+    assert_eq!(retry.stats().attempts, 2);
+
+    // Once out of retries, the 404 itself is returned.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let result: CargoResult<()> = run_with_retry(&mut retry, || {
+        Err(ForceRetryable::new(http_error(404)).into())
+    });
+    let err = result.unwrap_err();
+    assert!(!err.is::<ForceRetryable>());
+    assert_eq!(err.downcast_ref::<HttpNotSuccessful>().unwrap().code, 404);
+    assert_eq!(retry.stats().attempts, 1 + DEFAULT_RETRIES);
+}
+
+#[test]
+fn rewrite_url_picks_the_url_of_each_retry() {
+    use crate::core::Shell;
+    use std::cell::RefCell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut retry = fake_retry(&config, &time)
+        .url("https://blue.example.com/crates/foo")
+        .rewrite_url({
+            let calls = Rc::clone(&calls);
+            move |url, attempt| {
+                calls.borrow_mut().push((url.to_string(), attempt));
+                url.replace("blue", &format!("green{attempt}"))
+            }
+        })
+        .build()
+        .unwrap();
+
+    let mut used = vec!["https://blue.example.com/crates/foo".to_string()];
+    while let RetryResult::Retry(_) = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) }) {
+        used.push(retry.rewritten_url().unwrap());
+    }
+    assert_eq!(
+        used,
+        [
+            "https://blue.example.com/crates/foo",
+            "https://green2.example.com/crates/foo",
+            "https://green3.example.com/crates/foo",
+        ]
+    );
+    assert_eq!(
+        *calls.borrow(),
+        [
+            ("https://blue.example.com/crates/foo".to_string(), 2),
+            ("https://blue.example.com/crates/foo".to_string(), 3),
+        ]
+    );
+
+    // Without a rewrite every attempt uses the original URL.
+    let retry = fake_retry(&config, &time)
+        .url("https://blue.example.com/crates/foo")
+        .build()
+        .unwrap();
+    assert_eq!(retry.rewritten_url(), None);
+
+    // A rewrite set on the config applies to every retry made with it, such
+    // as those of the crate downloads.
+    let mut config = config_with(&[]);
+    config.set_retry_url_rewrite(Rc::new(|url: &str, _| url.replace("blue", "green")));
+    let mut retry = fake_retry(&config, &time)
+        .url("https://blue.example.com/crates/foo")
+        .build()
+        .unwrap();
+    assert!(matches!(
+        retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) }),
+        RetryResult::Retry(_)
+    ));
+    assert_eq!(
+        retry.rewritten_url().as_deref(),
+        Some("https://green.example.com/crates/foo")
+    );
+}
+
+#[test]
+fn retries_from_a_cloned_policy_are_independent() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let policy = NetRetryPolicy::new(&config).unwrap();
+    let copy = policy.clone();
+    let mut first = Retry::from_policy(&policy, &config);
+    let mut second = Retry::from_policy(&copy, &config);
+
+    // Using up the retries of one leaves the other untouched.
+    let fail = || -> CargoResult<()> { Err(http_error(503)) };
+    while let RetryResult::Retry(_) = first.r#try(fail) {}
+    assert_eq!(first.stats().attempts, 1 + policy.retries());
+    assert_eq!(first.remaining, 0);
+    assert_eq!(second.remaining, copy.retries());
+
+    assert!(matches!(second.r#try(fail), RetryResult::Retry(_)));
+    assert_eq!(second.remaining, copy.retries() - 1);
+    assert_eq!(second.stats().attempts, 1);
+}
+
+#[cfg(test)]
+fn http_error_retry_after(code: u32, retry_after: &str) -> Error {
+    http_error_at(
+        code,
+        "https://index.example.com/config.json",
+        &[&format!("Retry-After: {retry_after}")],
+    )
+    .into()
+}
+
+#[test]
+fn adaptive_backoff_follows_retry_after() {
+    let config = config_with(&["net.retry-adaptive=true"]);
+    let time = FakeTime::new();
+
+    // Two responses ask for 30 seconds...
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    for _ in 0..2 {
+        let result = retry.r#try(|| -> CargoResult<()> { Err(http_error_retry_after(503, "30")) });
+        assert!(matches!(result, RetryResult::Retry(_)));
+    }
+
+    // ...so the next retry against that host waits about as long, even
+    // without a `Retry-After` of its own.
+    let mut retry = fake_retry(&config, &time)
+        .url("https://index.example.com/index/fo/o/foo")
+        .build()
+        .unwrap();
+    let RetryResult::Retry(delay) = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) })
+    else {
+        panic!("expected a retry");
+    };
+    assert!(delay >= Duration::from_secs(30), "{delay:?}");
+    assert!(delay <= Duration::from_secs(33), "{delay:?}");
+
+    // Other hosts are unaffected.
+    let mut retry = fake_retry(&config, &time)
+        .url("https://static.example.com/foo.crate")
+        .build()
+        .unwrap();
+    let RetryResult::Retry(delay) = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) })
+    else {
+        panic!("expected a retry");
+    };
+    assert!(delay <= MAX_BACKOFF, "{delay:?}");
+
+    // `Retry-After: 0` retries right away, even after the earlier responses
+    // asked for longer, but still uses up a retry.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let RetryResult::Retry(delay) =
+        retry.r#try(|| -> CargoResult<()> { Err(http_error_retry_after(503, "0")) })
+    else {
+        panic!("expected a retry");
+    };
+    assert_eq!(delay, Duration::ZERO);
+    assert_eq!(retry.remaining(), 1);
+}
+
+#[test]
+fn retry_after_zero_retries_right_away_by_default() {
+    let config = config_with(&[]);
+    let time = FakeTime::new();
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let RetryResult::Retry(delay) =
+        retry.r#try(|| -> CargoResult<()> { Err(http_error_retry_after(503, "0")) })
+    else {
+        panic!("expected a retry");
+    };
+    assert_eq!(delay, Duration::ZERO);
+    assert_eq!(retry.remaining(), 1);
+
+    // Other delays are only followed with `net.retry-adaptive`.
+    let RetryResult::Retry(delay) =
+        retry.r#try(|| -> CargoResult<()> { Err(http_error_retry_after(503, "30")) })
+    else {
+        panic!("expected a retry");
+    };
+    assert!(delay <= MAX_BACKOFF, "{delay:?}");
+}
+
+#[test]
+fn adaptive_backoff_is_capped_by_config() {
+    let config = config_with(&[
+        "net.retry-adaptive=true",
+        "net.retry-adaptive-max-backoff=5000",
+    ]);
+    let time = FakeTime::new();
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    for _ in 0..2 {
+        let result = retry.r#try(|| -> CargoResult<()> { Err(http_error_retry_after(503, "30")) });
+        assert!(matches!(result, RetryResult::Retry(_)));
+    }
+
+    let mut retry = fake_retry(&config, &time)
+        .url("https://index.example.com/index/fo/o/foo")
+        .build()
+        .unwrap();
+    let RetryResult::Retry(delay) = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) })
+    else {
+        panic!("expected a retry");
+    };
+    assert_eq!(delay, Duration::from_secs(5));
+}
+
+#[test]
+fn retry_after_ms_header_sets_the_backoff() {
+    let config = config_with(&["net.retry-after-ms=true"]);
+    let time = FakeTime::new();
+    let failure =
+        |headers: &[&str]| http_error_at(429, "https://index.example.com/config.json", headers);
+    let first_delay = |headers: &[&str]| {
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        let err = failure(headers);
+        match retry.r#try(|| -> CargoResult<()> { Err(err.into()) }) {
+            RetryResult::Retry(delay) => delay,
+            _ => panic!("expected a retry"),
+        }
+    };
+
+    assert_eq!(
+        first_delay(&["X-Retry-After-Ms: 250"]),
+        Duration::from_millis(250)
+    );
+    // Capped at `net.retry-max-backoff`.
+    assert_eq!(first_delay(&["x-retry-after-ms: 60000"]), MAX_BACKOFF);
+    // Values that aren't a number of milliseconds are ignored.
+    let delay = first_delay(&["X-Retry-After-Ms: soon"]);
+    assert!(delay >= INITIAL_BACKOFF / 2, "{delay:?}");
+    // `Retry-After` takes precedence.
+    let delay = first_delay(&["Retry-After: 1", "X-Retry-After-Ms: 250"]);
+    assert!(delay >= INITIAL_BACKOFF / 2, "{delay:?}");
+
+    // The header is ignored unless `net.retry-after-ms` is set.
+    let config = Config::default().unwrap();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let err = failure(&["X-Retry-After-Ms: 1"]);
+    let RetryResult::Retry(delay) = retry.r#try(|| -> CargoResult<()> { Err(err.into()) }) else {
+        panic!("expected a retry");
+    };
+    assert!(delay >= INITIAL_BACKOFF / 2, "{delay:?}");
+}
+
+#[test]
+fn metrics_sink_sees_retries_and_give_ups() {
+    use crate::core::Shell;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl MetricsSink for RecordingSink {
+        fn record_retry(&self, reason: SpuriousReason, attempt: u32, backoff: Duration) {
+            self.calls
+                .borrow_mut()
+                .push(format!("retry {reason} {attempt} {backoff:?}"));
+        }
+
+        fn record_give_up(&self, attempts: u32) {
+            self.calls.borrow_mut().push(format!("give up {attempts}"));
+        }
+    }
+
+    let mut config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let sink = Rc::new(RecordingSink::default());
+    config.set_retry_metrics(sink.clone());
+    let time = FakeTime::new();
+    let timeout = || curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into();
+
+    // Failing every attempt uses up both retries, then gives up.
+    let mut results: Vec<CargoResult<()>> =
+        vec![Err(http_error(503)), Err(timeout()), Err(http_error(503))];
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_err());
+
+    // Succeeding after a retry doesn't give up.
+    let mut results: Vec<CargoResult<()>> = vec![Ok(()), Err(http_error(503))];
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+
+    // Nor does an error that was never going to be retried.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(run_with_retry(&mut retry, || -> CargoResult<()> { Err(http_error(404)) }).is_err());
+
+    let sleeps = time.sleeps.borrow();
+    assert_eq!(
+        *sink.calls.borrow(),
+        [
+            format!("retry 503 2 {:?}", sleeps[0]),
+            format!("retry timeout 3 {:?}", sleeps[1]),
+            "give up 3".to_string(),
+            format!("retry 503 2 {:?}", sleeps[2]),
+        ]
+    );
+}
+
+#[test]
+fn operations_and_attempts_are_traced_as_spans() {
+    use crate::core::Shell;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    /// Records the spans opened and closed, with their fields.
+    #[derive(Clone, Default)]
+    struct RecordingLayer {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RecordingLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let mut fields = Fields(String::new());
+            attrs.record(&mut fields);
+            let parent = span.parent().map(|p| p.name()).unwrap_or("none");
+            self.events.lock().unwrap().push(format!(
+                "open {} parent={parent}{}",
+                span.name(),
+                fields.0
+            ));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields(String::new());
+            values.record(&mut fields);
+            let name = ctx.span(id).unwrap().name();
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("record {name}{}", fields.0));
+        }
+
+        fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+            let name = ctx.span(&id).unwrap().name();
+            self.events.lock().unwrap().push(format!("close {name}"));
+        }
+    }
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let layer = RecordingLayer::default();
+    let subscriber = tracing_subscriber::registry().with(layer.clone());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let mut results: Vec<CargoResult<()>> = vec![Ok(()), Err(http_error(503))];
+        let mut retry = fake_retry(&config, &time)
+            .operation("download of foo v1.0.0")
+            .url("https://example.com/foo")
+            .build()
+            .unwrap();
+        assert!(run_with_retry(&mut retry, || results.pop().unwrap()).is_ok());
+        drop(retry);
+
+        // An operation dropped between attempts still closes its span.
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        assert!(matches!(
+            retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) }),
+            RetryResult::Retry(_)
+        ));
+        drop(retry);
+    });
+
+    assert_eq!(
+        *layer.events.lock().unwrap(),
+        [
+            "open retry parent=none operation=\"download of foo v1.0.0\" \
+             server.address=\"example.com\"",
+            "open attempt parent=retry http.request.resend_count=0",
+            "record attempt http.response.status_code=503",
+            "record attempt error.type=\"503\"",
+            "record attempt retried=true",
+            "close attempt",
+            "open attempt parent=retry http.request.resend_count=1",
+            "record attempt retried=false",
+            "close attempt",
+            "record retry attempts=2",
+            "record retry success=true",
+            "close retry",
+            "open retry parent=none",
+            "open attempt parent=retry http.request.resend_count=0",
+            "record attempt http.response.status_code=503",
+            "record attempt error.type=\"503\"",
+            "record attempt retried=true",
+            "close attempt",
+            "record retry attempts=1",
+            "record retry success=false",
+            "close retry",
+        ]
+    );
+}
+
+#[test]
+fn peek_backoff_matches_the_next_delay() {
+    let config = config_with(&["net.retry-escalate=true"]);
+    let time = FakeTime::new();
+    let fail = || -> CargoResult<()> { Err(http_error(503)) };
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    for expected in [INITIAL_BACKOFF, INITIAL_BACKOFF * 2] {
+        let peeked = retry.peek_backoff();
+        assert_eq!(peeked, expected);
+        assert_eq!(retry.peek_backoff(), peeked);
+        let RetryResult::Retry(delay) = retry.r#try(fail) else {
+            panic!("expected a retry");
+        };
+        assert!(peeked / 2 <= delay && delay <= peeked, "{delay:?}");
+    }
+    assert_eq!(retry.remaining(), 0);
+
+    // With `net.retry-escalate`, the next operation starts one doubling
+    // higher.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let peeked = retry.peek_backoff();
+    assert_eq!(peeked, INITIAL_BACKOFF * 2);
+    let RetryResult::Retry(delay) = retry.r#try(fail) else {
+        panic!("expected a retry");
+    };
+    assert!(peeked / 2 <= delay && delay <= peeked, "{delay:?}");
+}
+
+#[test]
+fn bad_gateway_uses_a_shorter_backoff() {
+    let first_delay = |code: u32, cli: &[&str]| {
+        let config = config_with(cli);
+        let time = FakeTime::new();
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        let RetryResult::Retry(delay) =
+            retry.r#try(|| -> CargoResult<()> { Err(http_error(code)) })
+        else {
+            panic!("expected a retry");
+        };
+        (delay, retry.wants_new_connection())
+    };
+
+    let (delay, new_connection) = first_delay(502, &[]);
+    assert!(DEFAULT_502_BACKOFF / 2 <= delay && delay <= DEFAULT_502_BACKOFF);
+    assert!(new_connection);
+
+    let (delay, new_connection) = first_delay(503, &[]);
+    assert!(INITIAL_BACKOFF / 2 <= delay && delay <= INITIAL_BACKOFF);
+    assert!(!new_connection);
+
+    let (delay, _) = first_delay(502, &["net.retry-502-backoff=20"]);
+    assert!(Duration::from_millis(10) <= delay && delay <= Duration::from_millis(20));
+}
+
+#[test]
+fn connection_close_retries_on_a_new_connection() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let closing_503 = || -> CargoResult<()> {
+        Err(http_error_at(
+            503,
+            "https://index.example.com/config.json",
+            &["Connection: keep-alive, Close"],
+        )
+        .into())
+    };
+    assert!(matches!(retry.r#try(closing_503), RetryResult::Retry(_)));
+    assert!(retry.wants_new_connection());
+    // A 503 that leaves the connection open can reuse it.
+    assert!(matches!(
+        retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) }),
+        RetryResult::Retry(_)
+    ));
+    assert!(!retry.wants_new_connection());
+}
+
+#[test]
+fn connection_close_is_tracked_per_host() {
+    let config = config_with(&["net.retry=3"]);
+    let time = FakeTime::new();
+    let index = |time| {
+        fake_retry(&config, time)
+            .url("https://index.example.com/index/fo/o/foo")
+            .build()
+            .unwrap()
+    };
+    let closing_503 = || -> CargoResult<()> {
+        Err(http_error_at(
+            503,
+            "https://index.example.com/config.json",
+            &["Connection: close"],
+        )
+        .into())
+    };
+
+    let mut retry = index(&time);
+    assert!(matches!(retry.r#try(closing_503), RetryResult::Retry(_)));
+    assert!(retry.wants_new_connection());
+    // The other operations on that host open a new connection too...
+    let other = index(&time);
+    assert!(other.wants_new_connection());
+    // ...unlike those on other hosts.
+    let static_host = fake_retry(&config, &time)
+        .url("https://static.example.com/foo.crate")
+        .build()
+        .unwrap();
+    assert!(!static_host.wants_new_connection());
+
+    // A response from the host that keeps the connection open lifts it.
+    let mut other = index(&time);
+    assert!(matches!(
+        other.r#try(|| -> CargoResult<()> { Ok(()) }),
+        RetryResult::Success(())
+    ));
+    assert!(!retry.wants_new_connection());
+    assert!(!index(&time).wants_new_connection());
+}
+
+#[test]
+fn network_deadline_stops_retries() {
+    use crate::core::Shell;
+
+    let mut config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    config.set_network_deadline(time.now() + Duration::from_secs(10));
+    let fail = || -> CargoResult<()> { Err(http_error(503)) };
+
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let RetryResult::Retry(delay) = retry.r#try(fail) else {
+        panic!("expected a retry");
+    };
+    assert_eq!(
+        retry.time_left(delay),
+        Some(Duration::from_secs(10) - delay)
+    );
+    time.sleep(delay);
+
+    // The download takes long enough that the next backoff would end past
+    // the deadline, so it isn't retried even though retries are left.
+    time.sleep(Duration::from_millis(9600));
+    let RetryResult::Err(err) = retry.r#try(fail) else {
+        panic!("expected an error");
+    };
+    assert!(err.is::<NetworkDeadlinePassed>());
+    assert!(err.root_cause().is::<HttpNotSuccessful>());
+    assert_eq!(retry.remaining(), 1);
+
+    // Attempts driven by hand stop being handed out as well.
+    time.sleep(Duration::from_secs(1));
+    let mut attempts = fake_retry(&config, &time).build().unwrap().into_attempts();
+    assert!(attempts.next().is_some());
+    assert!(attempts.next().is_none());
+}
+
+#[test]
+fn frozen_runs_retry_less() {
+    // Runs the operation until it gives up, returning the delays between
+    // its attempts.
+    let delays = |frozen: bool, locked: bool, cli: &[&str]| {
+        let config = locked_config_with(frozen, locked, cli);
+        let time = FakeTime::new();
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        let result = run_with_retry(&mut retry, || -> CargoResult<()> { Err(http_error(503)) });
+        assert!(result.is_err());
+        let sleeps = time.sleeps.borrow().clone();
+        sleeps
+    };
+
+    let default = delays(false, false, &[]);
+    let frozen = delays(true, false, &[]);
+    assert_eq!(default.len(), 2);
+    assert_eq!(frozen.len(), 1);
+    assert!(frozen.iter().sum::<Duration>() < default.iter().sum::<Duration>());
+    assert_eq!(delays(false, true, &[]).len(), 1);
+
+    // The limit is configurable, and never raises the number of retries.
+    assert_eq!(delays(true, false, &["net.retry-locked=0"]).len(), 0);
+    assert_eq!(delays(true, false, &["net.retry-locked=5"]).len(), 2);
+    assert_eq!(
+        delays(true, false, &["net.retry-locked=5", "net.retry=4"]).len(),
+        4
+    );
+}
+
+#[test]
+fn attempt_timeouts_shrink_with_the_deadline() {
+    let mut config = config_with(&["net.retry=5"]);
+    let time = FakeTime::new();
+    let connect = Duration::from_secs(30);
+    let retry = fake_retry(&config, &time).build().unwrap();
+    assert_eq!(retry.attempt_timeouts(Duration::ZERO, connect), None);
+
+    config.set_network_deadline(time.now() + Duration::from_secs(60));
+    let fail = || -> CargoResult<()> { Err(http_error(503)) };
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+
+    let mut totals = Vec::new();
+    for _ in 0..3 {
+        let RetryResult::Retry(delay) = retry.r#try(fail) else {
+            panic!("expected a retry");
+        };
+        let timeouts = retry.attempt_timeouts(delay, connect).unwrap();
+        assert!(timeouts.connect <= timeouts.total);
+        totals.push(timeouts.total);
+        // Each attempt takes a while before it fails again.
+        time.sleep(delay + Duration::from_secs(20));
+    }
+    assert!(totals.windows(2).all(|w| w[1] < w[0]), "{:?}", totals);
+    assert!(totals[2] < connect);
+
+    // Once the deadline has passed curl still gets a timeout, not none.
+    time.sleep(Duration::from_secs(60));
+    assert_eq!(
+        retry.attempt_timeouts(Duration::ZERO, connect),
+        Some(AttemptTimeouts {
+            connect: Duration::from_millis(1),
+            total: Duration::from_millis(1),
+        })
+    );
+}
+
+#[test]
+fn status_codes_are_classified() {
+    assert_eq!(spurious_reason_for_status(200), None);
+    assert_eq!(spurious_reason_for_status(404), None);
+    assert_eq!(
+        spurious_reason_for_status(503),
+        Some(SpuriousReason::HttpStatus(503))
+    );
+    assert_eq!(
+        spurious_reason_for_status(429),
+        Some(SpuriousReason::HttpStatus(429))
+    );
+    assert_eq!(
+        spurious_reason_for_status(408),
+        Some(SpuriousReason::HttpStatus(408))
+    );
+
+    let config = config_with(&["net.retry-on-4xx=['403']"]);
+    let policy = NetRetryPolicy::new(&config).unwrap();
+    assert_eq!(
+        policy.spurious_reason_for_status(403),
+        Some(SpuriousReason::HttpStatus(403))
+    );
+    assert_eq!(policy.spurious_reason_for_status(404), None);
+    assert_eq!(
+        policy.spurious_reason_for_status(503),
+        Some(SpuriousReason::HttpStatus(503))
+    );
+}
+
+#[test]
+fn not_modified_is_only_spurious_when_asked() {
+    let config = config_with(&[]);
+    let fail = || -> CargoResult<()> { Err(http_error(304)) };
+
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(fail), RetryResult::Err(_)));
+
+    let mut retry = fake_retry(&config, &time)
+        .spurious_not_modified()
+        .build()
+        .unwrap();
+    assert!(matches!(retry.r#try(fail), RetryResult::Retry(_)));
+}
+
+#[test]
+fn fast_first_retries_right_away() {
+    let config = config_with(&["net.retry-fast-first=true", "net.retry=3"]);
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert_eq!(retry.peek_backoff(), Duration::ZERO);
+    let fail = || -> CargoResult<()> { Err(http_error(503)) };
+    assert!(run_with_retry(&mut retry, fail).is_err());
+
+    // The backoff starts with the second retry.
+    let sleeps = time.sleeps.borrow();
+    assert_eq!(sleeps.len(), 3);
+    assert_eq!(sleeps[0], Duration::ZERO);
+    assert!(INITIAL_BACKOFF / 2 <= sleeps[1] && sleeps[1] <= INITIAL_BACKOFF);
+    assert!(INITIAL_BACKOFF <= sleeps[2] && sleeps[2] <= INITIAL_BACKOFF * 2);
+}
+
+#[test]
+fn connect_failures_fall_back_to_ipv4() {
+    let connect = || Err(curl::Error::new(curl_sys::CURLE_COULDNT_CONNECT).into());
+    let timeout = || Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into());
+    let time = FakeTime::new();
+
+    let config = Config::default().unwrap();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(connect), RetryResult::<()>::Retry(_)));
+    assert!(!retry.wants_ipv4());
+
+    let config = config_with(&["net.retry-ipv4-fallback=true", "net.retry=3"]);
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(timeout), RetryResult::<()>::Retry(_)));
+    assert!(!retry.wants_ipv4());
+    assert!(matches!(retry.r#try(connect), RetryResult::<()>::Retry(_)));
+    assert!(retry.wants_ipv4());
+    assert!(matches!(retry.r#try(timeout), RetryResult::<()>::Retry(_)));
+    assert!(retry.wants_ipv4());
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false", "ip_resolve=V4"]);
+}
+
+#[test]
+fn poll_wait_follows_retry_after_on_success() {
+    let config = Config::default().unwrap();
+    let time = FakeTime::new();
+    let poll_wait = PollWait::new(&config, Duration::from_secs(1)).sleeper(time.clone());
+    let limit = Duration::from_secs(60);
+    let succeed_with = |retry_after: Option<&str>| {
+        let headers: Vec<_> = retry_after
+            .map(|s| format!("Retry-After: {s}"))
+            .into_iter()
+            .collect();
+        if let Some(delay) = super::retry_after(&headers) {
+            config.retry_session().record_poll_after(delay);
+        }
+    };
+
+    // Two successful polls asking to be paced, then one that doesn't ask.
+    succeed_with(Some("3"));
+    poll_wait.wait(limit);
+    succeed_with(Some("5"));
+    poll_wait.wait(Duration::from_secs(4));
+    succeed_with(None);
+    poll_wait.wait(limit);
+    assert_eq!(
+        *time.sleeps.borrow(),
+        [
+            Duration::from_secs(3),
+            Duration::from_secs(4),
+            Duration::from_secs(1)
+        ]
+    );
+}
+
+#[test]
+fn only_the_most_recent_failed_responses_are_kept() {
+    let config = config_with(&["net.retry-report-capacity=3"]);
+    let time = FakeTime::new();
+    for i in 0..5 {
+        let mut retry = fake_retry(&config, &time).build().unwrap();
+        let _ = retry.r#try(|| -> CargoResult<()> {
+            Err(HttpNotSuccessful {
+                body: vec![b'x'; 1000],
+                ..http_error_at(404, &format!("https://example.com/{i}?token=secret"), &[])
+            }
+            .into())
+        });
+    }
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let _ = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) });
+
+    let session = config.retry_session();
+    let kept: Vec<_> = session.failed_responses().collect();
+    assert_eq!(
+        kept,
+        [
+            &FailedResponse {
+                code: 404,
+                url: "https://example.com/3?[REDACTED]".to_string(),
+                reason: None,
+            },
+            &FailedResponse {
+                code: 404,
+                url: "https://example.com/4?[REDACTED]".to_string(),
+                reason: None,
+            },
+            &FailedResponse {
+                code: 503,
+                url: "Uri".to_string(),
+                reason: Some(SpuriousReason::HttpStatus(503)),
+            },
+        ]
+    );
+}
+
+#[test]
+fn jitter_rng_makes_delays_reproducible() {
+    use rand::rngs::mock::StepRng;
+
+    let delays = || {
+        let config = config_with(&["net.retry=4"]);
+        let time = FakeTime::new();
+        let rng = StepRng::new(0, u64::MAX / 4 + 1);
+        let mut retry = fake_retry(&config, &time).jitter_rng(rng).build().unwrap();
+        let result = run_with_retry(&mut retry, || -> CargoResult<()> { Err(http_error(503)) });
+        assert!(result.is_err());
+        let sleeps = time.sleeps.borrow().clone();
+        sleeps
+    };
+    let expected = [
+        Duration::from_millis(250),
+        Duration::from_millis(500),
+        Duration::from_millis(1500),
+        Duration::from_millis(3500),
+    ];
+    assert_eq!(delays(), expected);
+    assert_eq!(delays(), expected);
+}
+
+#[test]
+fn min_backoff_floors_the_jitter() {
+    use rand::rngs::mock::StepRng;
+
+    let config_with = |args: &[&str]| {
+        let mut config = config_with(args);
+        config.set_net_retry_backoff(Duration::from_millis(250));
+        config
+    };
+
+    // Always drawing the lowest delay in the jitter range, the first retries
+    // would come after 125ms and 250ms without the floor.
+    let config = config_with(&[
+        "net.retry=5",
+        "net.retry-min-backoff=200",
+        "net.retry-502-backoff=200",
+    ]);
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time)
+        .jitter_rng(StepRng::new(0, 0))
+        .build()
+        .unwrap();
+    let result = run_with_retry(&mut retry, || -> CargoResult<()> { Err(http_error(503)) });
+    assert!(result.is_err());
+    let sleeps = time.sleeps.borrow().clone();
+    assert_eq!(sleeps.len(), 5);
+    assert!(
+        sleeps.iter().all(|&d| d >= Duration::from_millis(200)),
+        "{:?}",
+        sleeps
+    );
+    assert_eq!(sleeps[0], Duration::from_millis(200));
+
+    // The builder's floor takes precedence over config.
+    let config = config_with(&["net.retry=1", "net.retry-502-backoff=250"]);
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time)
+        .jitter_rng(StepRng::new(0, 0))
+        .min_backoff(Duration::from_millis(220))
+        .build()
+        .unwrap();
+    let result = run_with_retry(&mut retry, || -> CargoResult<()> { Err(http_error(503)) });
+    assert!(result.is_err());
+    assert_eq!(*time.sleeps.borrow(), [Duration::from_millis(220)]);
+
+    let config = config_with(&["net.retry-min-backoff=300", "net.retry-502-backoff=400"]);
+    let err = RetryBuilder::new(&config).build().err().unwrap();
+    assert!(err.is::<RetrySetupError>());
+    assert_eq!(
+        err.to_string(),
+        "`net.retry-min-backoff` of 300ms must be no longer than the first backoff \
+         of 250ms and the first backoff after a 502 of 400ms, which must be no longer \
+         than `net.retry-max-backoff` of 10000ms"
+    );
+    let config = config_with(&["net.retry-min-backoff=100", "net.retry-max-backoff=200"]);
+    assert!(RetryBuilder::new(&config).build().is_err());
+
+    // The first backoff after a 502 is held to the same bounds.
+    let config = config_with(&["net.retry-min-backoff=200"]);
+    let err = RetryBuilder::new(&config).build().err().unwrap();
+    assert!(err.is::<RetrySetupError>());
+    assert!(err.to_string().contains("after a 502 of 100ms"), "{err}");
+    let config = config_with(&["net.retry-min-backoff=100", "net.retry-502-backoff=20000"]);
+    assert!(RetryBuilder::new(&config).build().is_err());
+    // So is the builder's floor.
+    let err = RetryBuilder::new(&config_with(&[]))
+        .min_backoff(Duration::from_millis(200))
+        .build()
+        .err()
+        .unwrap();
+    assert!(err.is::<RetrySetupError>());
+}
+
+/// Runs `fut` to completion on the current thread.
+#[cfg(test)]
+fn block_on<F: Future>(fut: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+
+    impl std::task::Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[test]
+fn async_retries_wait_with_the_given_sleep() {
+    use crate::core::Shell;
+
+    let config = Config::default().unwrap();
+    *config.shell() = Shell::from_write(Box::new(Vec::new()));
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let sleeps = std::cell::RefCell::new(Vec::new());
+    let mut calls = 0;
+    let result = block_on(retry.try_async_with_sleep(
+        || {
+            calls += 1;
+            let result = if calls < 3 {
+                Err(http_error(503))
+            } else {
+                Ok(calls)
+            };
+            std::future::ready(result)
+        },
+        |delay| {
+            sleeps.borrow_mut().push(delay);
+            std::future::ready(())
+        },
+    ));
+    assert_eq!(result.unwrap(), 3);
+    let sleeps = sleeps.into_inner();
+    assert_eq!(sleeps.len(), 2);
+    assert!(sleeps.iter().all(|d| !d.is_zero()));
+    // Nothing waited on the builder's sleeper.
+    assert!(time.sleeps.borrow().is_empty());
+}
+
+#[test]
+fn async_retries_fall_back_to_a_sleeping_thread() {
+    let config = config_with(&["net.retry-max-backoff=1"]);
+    let mut retry = Retry::new(&config).unwrap();
+    let mut failed = false;
+    let result = block_on(retry.try_async(|| {
+        let result = if std::mem::replace(&mut failed, true) {
+            Ok(())
+        } else {
+            Err(http_error(503))
+        };
+        std::future::ready(result)
+    }));
+    assert!(result.is_ok());
+    assert_eq!(retry.stats().attempts, 2);
+}
+
+#[test]
+fn policy_round_trips_through_serde() {
+    let config = Config::default().unwrap();
+    let policy = NetRetryPolicy::new(&config).unwrap();
+    assert_eq!(
+        serde_json::to_string(&policy).unwrap(),
+        r#"{"retry":2,"retry-user-specified":false,"fixed-backoff":null,"#.to_string()
+            + r#""retry-escalate":false,"retry-backoff-reset":10,"partial-download":"retry","retry-warn-level":"warn","#
+            + r#""retry-dedup-window":null,"retry-report-capacity":10,"#
+            + r#""retry-body-patterns":[],"retry-on-4xx":[],"retry-adaptive":false,"#
+            + r#""retry-adaptive-max-backoff":60000,"retry-after-ms":false,"#
+            + r#""retry-backoff":500,"retry-502-backoff":100,"retry-fast-first":false,"retry-max-backoff":10000,"#
+            + r#""retry-min-backoff":0,"retry-max-backoff-for":{},"retry-limits":{},"#
+            + r#""retry-on-include":null,"retry-on-exclude":[],"retry-ipv4-fallback":false,"retry-http1-fallback":2,"#
+            + r#""retry-verbose":false,"retry-message":null,"retry-max-redownload":null}"#
+    );
+
+    let config = config_with(&[
+        "net.retry=3",
+        "net.partial-download='resume'",
+        "net.retry-on-4xx=['409']",
+        "net.retry-502-backoff=250",
+        "net.retry-max-backoff-for.429=300000",
+        "net.retry-message='{attempt}/{total}: {error}'",
+    ]);
+    let policy = NetRetryPolicy::new(&config).unwrap();
+    let json = serde_json::to_string(&policy).unwrap();
+    let read_back: NetRetryPolicy = serde_json::from_str(&json).unwrap();
+    assert_eq!(read_back, policy);
+    let retry = Retry::from_policy(&read_back, &config);
+    assert_eq!(retry.remaining(), 3);
+    assert_eq!(retry.into_policy(), policy);
+}
+
+#[test]
+fn too_many_redirects_are_not_retried() {
+    let config = Config::default().unwrap();
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let err = run_with_retry(&mut retry, || -> CargoResult<()> {
+        Err(curl::Error::new(curl_sys::CURLE_TOO_MANY_REDIRECTS).into())
+    })
+    .unwrap_err();
+    assert_eq!(retry.stats().attempts, 1);
+    assert!(err.to_string().contains("too many redirects"));
+    assert!(err.root_cause().is::<curl::Error>());
+}
+
+#[test]
+fn redownloads_are_capped() {
+    let config = config_with(&["net.retry=5", "net.retry-max-redownload='1MiB'"]);
+    let time = FakeTime::new();
+    let partial =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_PARTIAL_FILE).into()) };
+
+    // Two large transfers cut short: the second one goes over the limit.
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    retry.set_bytes_received(600 * 1024);
+    assert!(matches!(retry.r#try(partial), RetryResult::Retry(_)));
+    retry.set_bytes_received(600 * 1024);
+    let RetryResult::Err(e) = retry.r#try(partial) else {
+        panic!("expected to give up")
+    };
+    assert_eq!(
+        e.to_string(),
+        "not retrying, the data downloaded again because of retries would go over \
+         the `net.retry-max-redownload` limit of 1.0MiB"
+    );
+    assert_eq!(config.retry_session().redownloaded(), 1200 * 1024);
+
+    // Attempts that didn't download anything are still retried.
+    let connect =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_COULDNT_CONNECT).into()) };
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    assert!(matches!(retry.r#try(connect), RetryResult::Retry(_)));
+    retry.set_bytes_received(0);
+    assert!(matches!(retry.r#try(connect), RetryResult::Retry(_)));
+    assert_eq!(config.retry_session().redownloaded(), 1200 * 1024);
+}
+
+#[test]
+fn host_tables_override_the_global_policy() {
+    let config = config_with(&[
+        "net.retry=2",
+        "net.retry-hosts.'slow.example.com'.retry=5",
+        "net.retry-hosts.'slow.example.com'.retry-backoff=4000",
+    ]);
+
+    let slow = NetRetryPolicy::new(&config)
+        .unwrap()
+        .for_host(&config, "slow.example.com")
+        .unwrap();
+    assert_eq!(slow.retries(), 5);
+    assert_eq!(slow.initial_backoff, Duration::from_secs(4));
+    let retry = RetryBuilder::new(&config)
+        .url("https://slow.example.com/crates/foo")
+        .build()
+        .unwrap();
+    assert_eq!(retry.remaining(), 5);
+
+    // Other hosts get the global settings.
+    let other = Retry::for_host(&config, "example.com").unwrap();
+    assert_eq!(other.remaining(), 2);
+    assert_eq!(other.initial_backoff, INITIAL_BACKOFF);
+}
+
+#[test]
+fn host_backoff_falls_back_to_the_global_backoff() {
+    let config = config_with(&[
+        "net.retry-backoff=1500",
+        "net.retry-hosts.'slow.example.com'.retry-backoff=4000",
+        "net.retry-hosts.'flaky.example.com'.retry=5",
+    ]);
+
+    let slow = Retry::for_host(&config, "slow.example.com").unwrap();
+    assert_eq!(slow.initial_backoff, Duration::from_secs(4));
+    // A host table without `retry-backoff` uses `net.retry-backoff`, as do
+    // hosts without a table.
+    let flaky = Retry::for_host(&config, "flaky.example.com").unwrap();
+    assert_eq!(flaky.remaining(), 5);
+    assert_eq!(flaky.initial_backoff, Duration::from_millis(1500));
+    let other = Retry::for_host(&config, "example.com").unwrap();
+    assert_eq!(other.initial_backoff, Duration::from_millis(1500));
+}
+
+#[test]
+fn max_backoff_can_be_set_per_reason() {
+    let config = config_with(&[
+        "net.retry=10",
+        "net.retry-max-backoff=2000",
+        "net.retry-max-backoff-for.timeout=1000",
+    ]);
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time)
+        .max_backoff_for_reason(SpuriousReason::HttpStatus(429), Duration::from_secs(8))
+        .build()
+        .unwrap();
+    let timeout =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_OPERATION_TIMEDOUT).into()) };
+    let mut delays = Vec::new();
+    for _ in 0..6 {
+        let result = match delays.len() % 2 {
+            0 => retry.r#try(|| -> CargoResult<()> { Err(http_error(429)) }),
+            _ => retry.r#try(timeout),
+        };
+        let RetryResult::Retry(delay) = result else {
+            panic!("expected a retry")
+        };
+        delays.push(delay);
+    }
+    // By the fifth attempt, the exponential backoff is past the global cap,
+    // which rate limits aren't held to...
+    assert!(delays[4] > Duration::from_secs(2), "{delays:?}");
+    assert!(delays[4] <= Duration::from_secs(8), "{delays:?}");
+    // ...unlike timeouts, which have their own, lower, cap.
+    assert!(delays[5] <= Duration::from_secs(1), "{delays:?}");
+    // And other reasons use the global one.
+    let RetryResult::Retry(delay) = retry.r#try(|| -> CargoResult<()> { Err(http_error(503)) })
+    else {
+        panic!("expected a retry")
+    };
+    assert!(delay <= Duration::from_secs(2), "{delay:?}");
+
+    assert_eq!(
+        "429".parse::<SpuriousReason>().unwrap(),
+        SpuriousReason::HttpStatus(429)
+    );
+    assert!("rate-limit".parse::<SpuriousReason>().is_err());
+}
+
+#[test]
+fn spurious_reasons_parse_from_their_names() {
+    for reason in SpuriousReason::NAMED {
+        assert_eq!(
+            reason.to_string().parse::<SpuriousReason>().unwrap(),
+            *reason
+        );
+    }
+    let err = "rate-limit".parse::<SpuriousReason>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown retry reason `rate-limit`, expected an HTTP status code or one of \
+         git, timeout, connect, dns, send, recv, http2, proxy, ssl, partial, \
+         short-range, other, auth-expired"
+    );
+}
+
+#[test]
+fn settings_made_in_code_take_precedence_over_config() {
+    let mut config = config_with(&[
+        "net.retry=5",
+        "net.retry-max-backoff=10000",
+        "net.retry-hosts.'slow.example.com'.retry=7",
+        "net.retry-hosts.'slow.example.com'.retry-backoff=4000",
+    ]);
+    config.set_net_retry(1);
+    config.set_net_retry_backoff(Duration::from_millis(100));
+    config.set_net_retry_502_backoff(Duration::from_millis(20));
+    config.set_net_retry_max_backoff(Duration::from_millis(150));
+
+    let retry = Retry::new(&config).unwrap();
+    assert_eq!(retry.remaining(), 1);
+    assert!(retry.retries_user_specified());
+    assert_eq!(retry.peek_backoff(), Duration::from_millis(100));
+    let policy = retry.into_policy();
+    assert_eq!(policy.backoff_502, Duration::from_millis(20));
+    assert_eq!(policy.max_backoff, Duration::from_millis(150));
+
+    // They win over the host's table too.
+    let retry = Retry::for_host(&config, "slow.example.com").unwrap();
+    assert_eq!(retry.remaining(), 1);
+    assert_eq!(retry.peek_backoff(), Duration::from_millis(100));
+
+    // But `--retry` still wins over them.
+    config.set_retry_override(0);
+    assert_eq!(Retry::new(&config).unwrap().remaining(), 0);
+}
+
+#[test]
+fn strict_retries_fail_once_anything_was_retried() {
+    let config = config_with(&["net.retry-strict=true"]);
+    check_strict_retries(&config).unwrap();
+
+    let time = FakeTime::new();
+    let mut retry = fake_retry(&config, &time).build().unwrap();
+    let mut failed = false;
+    let result = run_with_retry(&mut retry, || {
+        if !failed {
+            failed = true;
+            return Err(http_error(503));
+        }
+        Ok(())
+    });
+    assert!(result.is_ok());
+    let err = check_strict_retries(&config).unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "network operations were retried, which isn't allowed with `--strict-network` \
+         or `net.retry-strict`: retries: 1 (503×1)"
+    );
+}
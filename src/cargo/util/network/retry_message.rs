@@ -0,0 +1,216 @@
+//! Templates for the warning printed when a network operation is retried.
+//!
+//! The template comes from `net.retry-message` or
+//! [`RetryBuilder::message_template`](super::retry::RetryBuilder::message_template).
+//! It is plain text with placeholders in braces, such as
+//! `retry {attempt}/{total} for {host}: {error}`. A literal brace is written
+//! by doubling it. Unknown placeholders are rejected when the template is
+//! parsed, so a typo shows up as a config error rather than in the warning.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, ser};
+
+/// A parsed retry warning template, see the [module docs](self).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryMessageTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// A placeholder that can appear in a [`RetryMessageTemplate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    /// The number of the attempt about to be made.
+    Attempt,
+    /// The number of attempts that will be made at most.
+    Total,
+    /// The number of retries left, counting this one.
+    Remaining,
+    /// The [`SpuriousReason`](super::retry::SpuriousReason) of the failure.
+    Reason,
+    /// The host being talked to.
+    Host,
+    /// The name of the operation, or empty if it has none.
+    Operation,
+    /// The error that caused the retry.
+    Error,
+}
+
+impl Field {
+    const ALL: &'static [(&'static str, Field)] = &[
+        ("attempt", Field::Attempt),
+        ("total", Field::Total),
+        ("remaining", Field::Remaining),
+        ("reason", Field::Reason),
+        ("host", Field::Host),
+        ("operation", Field::Operation),
+        ("error", Field::Error),
+    ];
+}
+
+/// The values substituted into a [`RetryMessageTemplate`].
+pub struct RetryMessageFields<'a> {
+    pub attempt: u32,
+    pub total: u32,
+    pub remaining: u32,
+    pub reason: &'a dyn fmt::Display,
+    pub host: &'a str,
+    pub operation: &'a str,
+    pub error: &'a dyn fmt::Display,
+}
+
+impl RetryMessageTemplate {
+    /// Formats the message for one retry.
+    pub fn render(&self, fields: &RetryMessageFields<'_>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Field(Field::Attempt) => out.push_str(&fields.attempt.to_string()),
+                Part::Field(Field::Total) => out.push_str(&fields.total.to_string()),
+                Part::Field(Field::Remaining) => out.push_str(&fields.remaining.to_string()),
+                Part::Field(Field::Reason) => out.push_str(&fields.reason.to_string()),
+                Part::Field(Field::Host) => out.push_str(fields.host),
+                Part::Field(Field::Operation) => out.push_str(fields.operation),
+                Part::Field(Field::Error) => out.push_str(&fields.error.to_string()),
+            }
+        }
+        out
+    }
+}
+
+impl FromStr for RetryMessageTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<RetryMessageTemplate> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        anyhow::bail!("unclosed `{{` in retry message template `{}`", s);
+                    };
+                    let name = &rest[..end];
+                    let Some(&(_, field)) = Field::ALL.iter().find(|(n, _)| *n == name) else {
+                        let known: Vec<_> = Field::ALL.iter().map(|(n, _)| *n).collect();
+                        anyhow::bail!(
+                            "unknown placeholder `{{{}}}` in retry message template `{}`, \
+                             expected one of {}",
+                            name,
+                            s,
+                            known.join(", ")
+                        );
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    anyhow::bail!("unmatched `}}` in retry message template `{}`", s);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(RetryMessageTemplate { parts })
+    }
+}
+
+/// Writes the template back out in the form it is parsed from.
+impl fmt::Display for RetryMessageTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => f.write_str(&s.replace('{', "{{").replace('}', "}}"))?,
+                Part::Field(field) => {
+                    let (name, _) = Field::ALL.iter().find(|(_, f)| f == field).unwrap();
+                    write!(f, "{{{}}}", name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serialize for RetryMessageTemplate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for RetryMessageTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<RetryMessageTemplate, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn renders_placeholders() {
+    let template: RetryMessageTemplate =
+        "{{{operation}}} retry {attempt}/{total} ({remaining} left) of {host} after {reason}: {error}"
+            .parse()
+            .unwrap();
+    let fields = RetryMessageFields {
+        attempt: 2,
+        total: 3,
+        remaining: 1,
+        reason: &"503",
+        host: "index.crates.io",
+        operation: "fetch",
+        error: &"service unavailable",
+    };
+    assert_eq!(
+        template.render(&fields),
+        "{fetch} retry 2/3 (1 left) of index.crates.io after 503: service unavailable"
+    );
+}
+
+#[test]
+fn rejects_bad_templates() {
+    let err = "retry {attempts}"
+        .parse::<RetryMessageTemplate>()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown placeholder `{attempts}` in retry message template `retry {attempts}`, \
+         expected one of attempt, total, remaining, reason, host, operation, error"
+    );
+    assert!("retry {attempt".parse::<RetryMessageTemplate>().is_err());
+    assert!("retry attempt}".parse::<RetryMessageTemplate>().is_err());
+}
+
+#[test]
+fn displays_as_parsed() {
+    let source = "{{{operation}}} retry {attempt}/{total}: {error}";
+    let template: RetryMessageTemplate = source.parse().unwrap();
+    assert_eq!(template.to_string(), source);
+}
@@ -0,0 +1,198 @@
+//! Utility for tracking network requests that will be retried in the future.
+
+use core::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Source of the current time for the retry machinery.
+///
+/// This exists so tests can drive time manually instead of waiting on the
+/// system clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The [`Clock`] used outside of tests, backed by [`Instant::now`].
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Blocks the current thread while waiting to retry an operation.
+///
+/// Like [`Clock`], this can be replaced in tests so that retries happen
+/// instantly while the requested delays are still observable.
+pub trait Sleeper {
+    fn sleep(&self, dur: Duration);
+}
+
+/// The [`Sleeper`] used outside of tests, backed by [`std::thread::sleep`].
+pub struct ThreadSleeper;
+
+impl Sleeper for ThreadSleeper {
+    fn sleep(&self, dur: Duration) {
+        std::thread::sleep(dur)
+    }
+}
+
+/// A future that completes once a delay has passed, for async code without a
+/// timer of its own, see [`Retry::try_async`](super::retry::Retry::try_async).
+///
+/// The delay is waited out on a thread spawned when the future is first
+/// polled, which works with any executor at the cost of a thread per sleep.
+pub struct ThreadSleep {
+    dur: Duration,
+    /// Shared with the sleeping thread, once it was spawned.
+    state: Option<Arc<Mutex<ThreadSleepState>>>,
+}
+
+struct ThreadSleepState {
+    done: bool,
+    /// Woken once the delay has passed.
+    waker: Option<Waker>,
+}
+
+impl ThreadSleep {
+    pub fn new(dur: Duration) -> ThreadSleep {
+        ThreadSleep { dur, state: None }
+    }
+}
+
+impl Future for ThreadSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.dur.is_zero() {
+            return Poll::Ready(());
+        }
+        let dur = self.dur;
+        let state = self.state.get_or_insert_with(|| {
+            let state = Arc::new(Mutex::new(ThreadSleepState {
+                done: false,
+                waker: None,
+            }));
+            let thread_state = state.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(dur);
+                let mut state = thread_state.lock().unwrap();
+                state.done = true;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+            state
+        });
+        let mut state = state.lock().unwrap();
+        if state.done {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// A tracker for network requests that have failed, and are awaiting to be
+/// retried in the future.
+///
+/// This is used by the parallel downloaders, which can't block the whole
+/// thread for a single request's backoff while other transfers are in flight.
+pub struct SleepTracker<T> {
+    /// This is a priority queue that tracks the time when the next sleeper
+    /// should awaken (based on the [`Sleeping::wakeup`] property).
+    heap: BinaryHeap<Sleeping<T>>,
+}
+
+/// An individual network request that is waiting to be retried in the future.
+struct Sleeping<T> {
+    /// The time when this requests should be retried.
+    wakeup: Instant,
+    /// Information about the network request.
+    data: T,
+}
+
+impl<T> PartialEq for Sleeping<T> {
+    fn eq(&self, other: &Sleeping<T>) -> bool {
+        self.wakeup == other.wakeup
+    }
+}
+
+impl<T> PartialOrd for Sleeping<T> {
+    fn partial_cmp(&self, other: &Sleeping<T>) -> Option<Ordering> {
+        // The sleepers with the earliest wakeup time should be at the top
+        // of the heap.
+        Some(other.wakeup.cmp(&self.wakeup))
+    }
+}
+
+impl<T> Eq for Sleeping<T> {}
+
+impl<T> Ord for Sleeping<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.wakeup.cmp(&self.wakeup)
+    }
+}
+
+impl<T> SleepTracker<T> {
+    pub fn new() -> SleepTracker<T> {
+        SleepTracker {
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Adds a new download that should be retried in the future.
+    pub fn push(&mut self, sleep: Duration, data: T) {
+        self.heap.push(Sleeping {
+            wakeup: Instant::now() + sleep,
+            data,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Returns any downloads that are ready to go now.
+    pub fn to_retry(&mut self) -> Vec<T> {
+        let now = Instant::now();
+        let mut result = Vec::new();
+        while let Some(next) = self.heap.peek() {
+            if next.wakeup <= now {
+                result.push(self.heap.pop().unwrap().data);
+            } else {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Returns the time when the next download is ready to go.
+    ///
+    /// Returns None if there are no sleepers remaining.
+    pub fn time_to_next(&self) -> Option<Duration> {
+        self.heap
+            .peek()
+            .map(|s| s.wakeup.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[test]
+fn returns_in_order() {
+    let mut s = SleepTracker::new();
+    s.push(Duration::from_millis(3), 3);
+    s.push(Duration::from_millis(1), 1);
+    s.push(Duration::from_millis(6), 6);
+    s.push(Duration::from_millis(5), 5);
+    s.push(Duration::from_millis(2), 2);
+    s.push(Duration::from_millis(10000), 10000);
+    assert_eq!(s.len(), 6);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(s.to_retry(), &[1, 2, 3, 5, 6]);
+}
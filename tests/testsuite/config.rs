@@ -22,6 +22,7 @@ pub struct ConfigBuilder {
     config_args: Vec<String>,
     cwd: Option<PathBuf>,
     enable_nightly_features: bool,
+    locked: bool,
 }
 
 impl ConfigBuilder {
@@ -32,6 +33,7 @@ impl ConfigBuilder {
             config_args: Vec::new(),
             cwd: None,
             enable_nightly_features: false,
+            locked: false,
         }
     }
 
@@ -53,6 +55,12 @@ impl ConfigBuilder {
         self
     }
 
+    /// Passes the `--locked` flag.
+    pub fn locked(&mut self) -> &mut Self {
+        self.locked = true;
+        self
+    }
+
     /// Passes a `--config` flag.
     pub fn config_arg(&mut self, arg: impl Into<String>) -> &mut Self {
         self.config_args.push(arg.into());
@@ -85,7 +93,7 @@ impl ConfigBuilder {
             false,
             None,
             false,
-            false,
+            self.locked,
             false,
             &None,
             &self.unstable,
@@ -1041,6 +1049,94 @@ ssl-version.max = 'tlsv1.3'
     };
 }
 
+#[cargo_test]
+fn config_http_version_pins_the_handle() {
+    use cargo::util::network::retry::{Retry, RetryHandle, RetryResult};
+    use curl::easy::{HttpVersion, IpResolve};
+
+    /// Writes down the HTTP versions it is set to.
+    #[derive(Default)]
+    struct VersionHandle(Vec<String>);
+
+    impl RetryHandle for VersionHandle {
+        fn range(&mut self, _range: &str) -> Result<(), curl::Error> {
+            Ok(())
+        }
+
+        fn fresh_connect(&mut self, _enable: bool) -> Result<(), curl::Error> {
+            Ok(())
+        }
+
+        fn ip_resolve(&mut self, _resolve: IpResolve) -> Result<(), curl::Error> {
+            Ok(())
+        }
+
+        fn http_version(&mut self, version: HttpVersion) -> Result<(), curl::Error> {
+            self.0.push(format!("{version:?}"));
+            Ok(())
+        }
+    }
+    let pinned_version = |config: &Config| {
+        let mut handle = VersionHandle::default();
+        cargo::ops::configure_pinned_http_version(config, &mut handle).unwrap();
+        handle.0
+    };
+
+    let stream_error =
+        || -> CargoResult<()> { Err(curl::Error::new(curl_sys::CURLE_HTTP2_STREAM).into()) };
+
+    let config = ConfigBuilder::new().config_arg("net.retry=5").build();
+    assert!(cargo::ops::pinned_http_version(&config).unwrap().is_none());
+    assert!(pinned_version(&config).is_empty());
+    let mut retry = Retry::new(&config).unwrap();
+    for _ in 0..2 {
+        assert!(matches!(retry.r#try(stream_error), RetryResult::Retry(_)));
+    }
+    assert!(retry.wants_http1());
+
+    write_config(
+        "\
+[http]
+version = '2'
+",
+    );
+    let config = ConfigBuilder::new().config_arg("net.retry=5").build();
+    assert!(matches!(
+        cargo::ops::pinned_http_version(&config).unwrap(),
+        Some(HttpVersion::V2)
+    ));
+    assert_eq!(pinned_version(&config), ["V2"]);
+    cargo::ops::http_handle(&config).unwrap();
+    // The pinned version isn't given up on after HTTP/2 stream errors.
+    let mut retry = Retry::new(&config).unwrap();
+    for _ in 0..4 {
+        assert!(matches!(retry.r#try(stream_error), RetryResult::Retry(_)));
+    }
+    assert!(!retry.wants_http1());
+    let mut handle = VersionHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert!(handle.0.is_empty());
+
+    let config = ConfigBuilder::new()
+        .config_arg("http.version='1.1'")
+        .build();
+    assert!(matches!(
+        cargo::ops::pinned_http_version(&config).unwrap(),
+        Some(HttpVersion::V11)
+    ));
+    assert_eq!(pinned_version(&config), ["V11"]);
+
+    let config = ConfigBuilder::new().config_arg("http.version='3'").build();
+    assert_error(
+        cargo::ops::pinned_http_version(&config).unwrap_err(),
+        "\
+error in --config cli option: could not load config key `http.version`
+
+Caused by:
+  unknown variant `3`, expected `1.1` or `2`",
+    );
+}
+
 #[cargo_test]
 fn config_get_ssl_version_both_forms_configured() {
     // this is not allowed
@@ -1569,3 +1665,18 @@ known-hosts = [
         Definition::Environment("CARGO_NET_SSH_KNOWN_HOSTS".to_string())
     );
 }
+
+#[cargo_test]
+fn retry_on_4xx_env_is_not_the_retry_on_table() {
+    let config = ConfigBuilder::new()
+        .env("CARGO_NET_RETRY_ON_4XX", "403")
+        .build();
+    let net_config = config.net_config().unwrap();
+    assert_eq!(net_config.retry_on_4xx, Some(vec!["403".to_string()]));
+    // Nothing is taken from it for `net.retry-on`, which would limit the
+    // reasons retried.
+    assert!(net_config
+        .retry_on
+        .as_ref()
+        .map_or(true, |r| r.include.is_none() && r.exclude.is_none()));
+}
@@ -493,3 +493,119 @@ fn invalid_token_output() {
         )
         .run();
 }
+
+/// Sets up a `yank` against a registry that uses a credential process whose
+/// `main` is `main_rs`; each run of the process appends to `runs.log`.
+fn flaky_token_test(main_rs: &str) -> (Project, TestRegistry) {
+    let server = registry::RegistryBuilder::new()
+        .no_configure_token()
+        .token(cargo_test_support::registry::Token::Plaintext(
+            "sekrit".to_string(),
+        ))
+        .alternative()
+        .http_api()
+        .build();
+    let cred_proj = project()
+        .at("cred_proj")
+        .file("Cargo.toml", &basic_manifest("test-cred", "1.0.0"))
+        .file("src/main.rs", main_rs)
+        .build();
+    cred_proj.cargo("build").run();
+
+    let p = project()
+        .file(
+            ".cargo/config",
+            &format!(
+                r#"
+                    [registries.alternative]
+                    index = "{}"
+                    credential-process = ["{}"]
+                "#,
+                server.index_url(),
+                toml_bin(&cred_proj, "test-cred")
+            ),
+        )
+        .file("Cargo.toml", &basic_manifest("foo", "0.1.0"))
+        .file("src/lib.rs", "")
+        .build();
+    (p, server)
+}
+
+#[cargo_test]
+fn retries_temporary_failures() {
+    // A process exiting with EX_TEMPFAIL is run again.
+    let (p, _t) = flaky_token_test(
+        r#"
+            use std::fs::File;
+            use std::io::Write;
+            fn main() {
+                let first = !std::path::Path::new("runs.log").exists();
+                let mut f = File::options()
+                    .create(true)
+                    .append(true)
+                    .open("runs.log")
+                    .unwrap();
+                write!(f, "+").unwrap();
+                if first {
+                    eprintln!("token server unreachable");
+                    std::process::exit(75);
+                }
+                println!("sekrit");
+            }
+        "#,
+    );
+
+    p.cargo("yank --version 0.1.0 --registry alternative -Z credential-process")
+        .masquerade_as_nightly_cargo(&["credential-process"])
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .with_stderr(
+            "\
+[UPDATING] [..]
+token server unreachable
+[WARNING] spurious network error (2 tries remaining), retrying credential process \
+`[..]test-cred[EXE]`: registry credential process `[..]test-cred[EXE]` failed to \
+authenticate to registry `alternative` (exit status: 75)
+[YANK] foo@0.1.0
+",
+        )
+        .run();
+
+    let calls = read_to_string(p.root().join("runs.log")).unwrap().len();
+    assert_eq!(calls, 2);
+}
+
+#[cargo_test]
+fn does_not_retry_denied_access() {
+    let (p, _t) = flaky_token_test(
+        r#"
+            use std::fs::File;
+            use std::io::Write;
+            fn main() {
+                let mut f = File::options()
+                    .create(true)
+                    .append(true)
+                    .open("runs.log")
+                    .unwrap();
+                write!(f, "+").unwrap();
+                eprintln!("access denied");
+                std::process::exit(1);
+            }
+        "#,
+    );
+
+    p.cargo("yank --version 0.1.0 --registry alternative -Z credential-process")
+        .masquerade_as_nightly_cargo(&["credential-process"])
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .with_status(101)
+        .with_stderr(
+            "\
+[UPDATING] [..]
+access denied
+[ERROR] registry credential process `[..]test-cred[EXE]` failed to authenticate to registry `alternative` (exit status: 1)
+",
+        )
+        .run();
+
+    let calls = read_to_string(p.root().join("runs.log")).unwrap().len();
+    assert_eq!(calls, 1);
+}
@@ -1,14 +1,16 @@
 //! Tests for git authentication.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
-use cargo_test_support::git::cargo_uses_gitoxide;
+use cargo_test_support::git::{self, cargo_uses_gitoxide};
 use cargo_test_support::paths;
 use cargo_test_support::{basic_manifest, project};
 
@@ -322,6 +324,7 @@ fn net_err_suggests_fetch_with_cli() {
 [UPDATING] git repository `ssh://needs-proxy.invalid/git`
 warning: spurious network error[..]
 warning: spurious network error[..]
+[NOTE] retries: 2 (git×2)
 [ERROR] failed to get `foo` as a dependency of package `foo v0.0.0 [..]`
 
 Caused by:
@@ -424,3 +427,207 @@ Caused by:
 
     t.join().ok().unwrap();
 }
+
+/// Serves the repositories in `root` over git's smart HTTP protocol, through
+/// `git http-backend`, to requests that authenticate as `foo:fresh`. Any other
+/// request is turned down with a 401. Returns the address to fetch from, and
+/// the passwords of the requests, in order.
+fn serve_git_over_http(root: PathBuf) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let passwords = Arc::new(Mutex::new(Vec::new()));
+    let seen = passwords.clone();
+    thread::spawn(move || {
+        for conn in server.incoming() {
+            let mut conn = BufReader::new(conn.unwrap());
+            let mut request = String::new();
+            conn.read_line(&mut request).unwrap();
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                conn.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(": ") else {
+                    break;
+                };
+                headers.insert(name.to_lowercase(), value.to_string());
+            }
+            if headers.get("expect").map(String::as_str) == Some("100-continue") {
+                conn.get_mut()
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .unwrap();
+            }
+            let mut body = Vec::new();
+            if let Some(len) = headers.get("content-length") {
+                body.resize(len.parse().unwrap(), 0);
+                conn.read_exact(&mut body).unwrap();
+            } else if headers.get("transfer-encoding").map(String::as_str) == Some("chunked") {
+                loop {
+                    let mut size = String::new();
+                    conn.read_line(&mut size).unwrap();
+                    let size = usize::from_str_radix(size.trim(), 16).unwrap();
+                    let mut chunk = vec![0; size + 2];
+                    conn.read_exact(&mut chunk).unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    body.extend_from_slice(&chunk[..size]);
+                }
+            }
+
+            let password = match headers.get("authorization").map(String::as_str) {
+                Some("Basic Zm9vOmV4cGlyZWQ=") => "expired",
+                Some("Basic Zm9vOmZyZXNo") => "fresh",
+                Some(_) => "other",
+                None => "none",
+            };
+            seen.lock().unwrap().push(password.to_string());
+            if password != "fresh" {
+                conn.get_mut()
+                    .write_all(
+                        b"HTTP/1.1 401 Unauthorized\r\n\
+                          WWW-Authenticate: Basic realm=\"wheee\"\r\n\
+                          Content-Length: 0\r\n\
+                          Connection: close\r\n\
+                          \r\n",
+                    )
+                    .unwrap();
+                continue;
+            }
+
+            let mut parts = request.split(' ');
+            let method = parts.next().unwrap();
+            let target = parts.next().unwrap();
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let mut backend = Command::new("git")
+                .arg("http-backend")
+                .env("GIT_PROJECT_ROOT", &root)
+                .env("GIT_HTTP_EXPORT_ALL", "1")
+                .env("REQUEST_METHOD", method)
+                .env("PATH_INFO", path)
+                .env("QUERY_STRING", query)
+                .env("CONTENT_LENGTH", body.len().to_string())
+                .env(
+                    "CONTENT_TYPE",
+                    headers.get("content-type").map_or("", String::as_str),
+                )
+                .env(
+                    "GIT_PROTOCOL",
+                    headers.get("git-protocol").map_or("", String::as_str),
+                )
+                .env(
+                    "HTTP_CONTENT_ENCODING",
+                    headers.get("content-encoding").map_or("", String::as_str),
+                )
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            backend.stdin.take().unwrap().write_all(&body).unwrap();
+            let output = backend.wait_with_output().unwrap().stdout;
+            let split = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let (cgi_headers, body) = (
+                String::from_utf8_lossy(&output[..split]).into_owned(),
+                &output[split + 4..],
+            );
+            let mut response = String::from("HTTP/1.1 200 OK\r\n");
+            let mut headers = String::new();
+            for header in cgi_headers.lines() {
+                match header.strip_prefix("Status: ") {
+                    Some(status) => response = format!("HTTP/1.1 {status}\r\n"),
+                    None => headers.push_str(&format!("{header}\r\n")),
+                }
+            }
+            response.push_str(&headers);
+            response.push_str(&format!(
+                "Content-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            ));
+            conn.get_mut().write_all(response.as_bytes()).unwrap();
+            conn.get_mut().write_all(body).unwrap();
+        }
+    });
+    (addr, passwords)
+}
+
+// Tests that a fetch rejecting credentials the helper said had expired gets
+// new ones from the helper, and succeeds with them.
+#[cargo_test]
+fn expired_credentials_are_refreshed() {
+    git::new("bar", |project| {
+        project
+            .file("Cargo.toml", &basic_manifest("bar", "0.5.0"))
+            .file("src/lib.rs", "")
+    });
+    let (addr, passwords) = serve_git_over_http(paths::root());
+
+    // The helper hands out a token that has expired first, and a fresh one
+    // when it is asked again.
+    let calls = paths::root().join("helper-calls");
+    let script = project()
+        .at("script")
+        .file("Cargo.toml", &basic_manifest("script", "0.1.0"))
+        .file(
+            "src/main.rs",
+            &format!(
+                r#"
+                    use std::io::Read;
+
+                    fn main() {{
+                        std::io::stdin().read_to_string(&mut String::new()).unwrap();
+                        if std::env::args().nth(1).as_deref() != Some("get") {{
+                            return;
+                        }}
+                        let calls = {calls:?};
+                        let n = std::fs::read_to_string(calls).map_or(0, |n| n.parse().unwrap());
+                        std::fs::write(calls, (n + 1).to_string()).unwrap();
+                        println!("username=foo");
+                        if n == 0 {{
+                            println!("password=expired");
+                            println!("password_expiry_utc=1");
+                        }} else {{
+                            println!("password=fresh");
+                        }}
+                    }}
+                "#
+            ),
+        )
+        .build();
+    script.cargo("build -v").run();
+    let script = script.bin("script");
+    let config = paths::home().join(".gitconfig");
+    let mut config = git2::Config::open(&config).unwrap();
+    config
+        .set_str(
+            "credential.helper",
+            &script.display().to_string().replace("\\", "/"),
+        )
+        .unwrap();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            &format!(
+                r#"
+                    [package]
+                    name = "foo"
+                    version = "0.0.1"
+                    authors = []
+
+                    [dependencies.bar]
+                    git = "http://{addr}/bar"
+                "#
+            ),
+        )
+        .file("src/lib.rs", "")
+        .build();
+
+    p.cargo("check")
+        .env("__CARGO_USE_GITOXIDE_INSTEAD_OF_GIT2", "1")
+        .with_stderr_contains(&format!("[UPDATING] git repository `http://{addr}/bar`"))
+        .with_stderr_contains("[CHECKING] bar v0.5.0 ([..])")
+        .run();
+
+    assert_eq!(std::fs::read_to_string(&calls).unwrap(), "2");
+    let passwords = passwords.lock().unwrap();
+    assert_eq!(passwords[..4], ["none", "expired", "none", "fresh"]);
+}
@@ -121,6 +121,7 @@ mod rustdocflags;
 mod rustflags;
 mod search;
 mod shell_quoting;
+mod simulate_retry;
 mod source_replacement;
 mod ssh;
 mod standard_lib;
@@ -1,6 +1,59 @@
 //! Tests for network configuration.
 
-use cargo_test_support::project;
+use crate::config::{assert_error, read_output, write_config_at, ConfigBuilder};
+use cargo::util::errors::{HttpNotSuccessful, RetriesExhausted, RetryErrors, RetrySetupError};
+use cargo::util::network::download;
+use cargo::util::network::retry::{
+    self, with_retry_all_errors, with_retry_fallback, with_retry_provenance, with_retry_resumable,
+    with_retry_stats, with_retry_trace, AttemptOutcome, Provenance, Retry, RetryBuilder,
+    RetryHandle, RetryResult, SpuriousReason, Step,
+};
+use cargo::util::network::sleep::Clock;
+use cargo_test_support::registry::{RegistryBuilder, Response};
+use cargo_test_support::{cargo_process, paths, project};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A failed `GET` of `url` answered with `code` and `headers`.
+fn http_error_at(code: u32, url: &str, headers: &[&str]) -> HttpNotSuccessful {
+    HttpNotSuccessful {
+        code,
+        method: "GET".to_string(),
+        url: url.to_string(),
+        body: Vec::new(),
+        headers: headers.iter().map(|h| h.to_string()).collect(),
+        redirected_from: None,
+    }
+}
+
+/// A [`RetryHandle`] writing down the options a retry sets, as
+/// `option=value`.
+#[derive(Default)]
+struct RecordingHandle(Vec<String>);
+
+impl RetryHandle for RecordingHandle {
+    fn range(&mut self, range: &str) -> Result<(), curl::Error> {
+        self.0.push(format!("range={range}"));
+        Ok(())
+    }
+
+    fn fresh_connect(&mut self, enable: bool) -> Result<(), curl::Error> {
+        self.0.push(format!("fresh_connect={enable}"));
+        Ok(())
+    }
+
+    fn ip_resolve(&mut self, resolve: curl::easy::IpResolve) -> Result<(), curl::Error> {
+        self.0.push(format!("ip_resolve={resolve:?}"));
+        Ok(())
+    }
+
+    fn http_version(&mut self, version: curl::easy::HttpVersion) -> Result<(), curl::Error> {
+        self.0.push(format!("http_version={version:?}"));
+        Ok(())
+    }
+}
 
 #[cargo_test]
 fn net_retry_loads_from_config() {
@@ -70,5 +123,1156 @@ fn net_retry_git_outputs_warning() {
              (2 tries remaining): [..]",
         )
         .with_stderr_contains("[WARNING] spurious network error (1 tries remaining): [..]")
+        .with_stderr_contains("[NOTE] retries: 2 ([..]×2)")
+        .run();
+}
+
+/// Runs a single attempt that fails with a partial transfer.
+fn try_partial_download(partial_download: Option<&str>) -> (RetryResult<()>, bool) {
+    let mut builder = ConfigBuilder::new();
+    if let Some(mode) = partial_download {
+        builder.config_arg(format!("net.partial-download='{mode}'"));
+    }
+    let config = builder.build();
+    let mut retry = Retry::new(&config).unwrap();
+    let result = retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(curl::Error::new(curl_sys::CURLE_PARTIAL_FILE).into())
+    });
+    (result, retry.resumes_partial_downloads())
+}
+
+#[cargo_test]
+fn partial_download_defaults_to_retry() {
+    let (result, resumes) = try_partial_download(None);
+    assert!(matches!(result, RetryResult::Retry(_)));
+    assert!(!resumes);
+}
+
+#[cargo_test]
+fn partial_download_retry() {
+    let (result, resumes) = try_partial_download(Some("retry"));
+    assert!(matches!(result, RetryResult::Retry(_)));
+    assert!(!resumes);
+}
+
+#[cargo_test]
+fn partial_download_resume() {
+    let (result, resumes) = try_partial_download(Some("resume"));
+    assert!(matches!(result, RetryResult::Retry(_)));
+    assert!(resumes);
+}
+
+#[cargo_test]
+fn partial_download_fail() {
+    let (result, resumes) = try_partial_download(Some("fail"));
+    match result {
+        RetryResult::Err(e) => {
+            assert!(e.downcast_ref::<curl::Error>().unwrap().is_partial_file())
+        }
+        _ => panic!("expected a partial transfer to fail"),
+    }
+    assert!(!resumes);
+}
+
+/// Runs a single attempt that fails with a 403 whose body is `body`.
+fn try_forbidden(patterns: Option<&str>, body: &str) -> RetryResult<()> {
+    let mut builder = ConfigBuilder::new();
+    if let Some(patterns) = patterns {
+        builder.config_arg(format!("net.retry-body-patterns={patterns}"));
+    }
+    let config = builder.build();
+    let mut retry = Retry::new(&config).unwrap();
+    retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(HttpNotSuccessful {
+            body: body.as_bytes().to_vec(),
+            ..http_error_at(403, "https://index.example.com/config.json", &[])
+        }
+        .into())
+    })
+}
+
+#[cargo_test]
+fn retry_body_patterns() {
+    let body = "the index is regenerating, try again later";
+    // Off by default.
+    assert!(matches!(try_forbidden(None, body), RetryResult::Err(_)));
+    assert!(matches!(
+        try_forbidden(Some(r#"["regenerating"]"#), body),
+        RetryResult::Retry(_)
+    ));
+    assert!(matches!(
+        try_forbidden(Some(r#"["maintenance"]"#), body),
+        RetryResult::Err(_)
+    ));
+}
+
+/// Runs a single attempt that fails with the HTTP status `code`.
+fn try_status(retry_on_4xx: &str, code: u32) -> RetryResult<()> {
+    let config = ConfigBuilder::new()
+        .config_arg(format!("net.retry-on-4xx={retry_on_4xx}"))
+        .build();
+    let mut retry = Retry::new(&config).unwrap();
+    retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(http_error_at(code, "https://index.example.com/config.json", &[]).into())
+    })
+}
+
+#[cargo_test]
+fn retry_message_template() {
+    let config = ConfigBuilder::new()
+        .config_arg(
+            "net.retry-message='{operation}: attempt {attempt}/{total} to {host} \
+             after {reason} ({remaining} left)'",
+        )
+        .build();
+    let mut retry = RetryBuilder::new(&config)
+        .operation("index update")
+        .build()
+        .unwrap();
+    let result = try_http_503(&mut retry);
+    assert!(matches!(result, RetryResult::Retry(_)));
+    drop(retry);
+    assert_eq!(
+        read_output(config),
+        "warning: index update: attempt 2/3 to index.example.com after 503 (2 left)\n"
+    );
+}
+
+#[cargo_test]
+fn retry_message_rejects_unknown_placeholders() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-message='retry {attempts} of {total}'")
+        .build();
+    let err = Retry::new(&config).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "unknown placeholder `{attempts}` in retry message template `retry {attempts} of {total}`, \
+         expected one of attempt, total, remaining, reason, host, operation, error"
+    );
+}
+
+#[cargo_test]
+fn malformed_retry_settings_name_the_value() {
+    let config = ConfigBuilder::new().config_arg("net.retry='two'").build();
+    assert_error(
+        Retry::new(&config).err().unwrap(),
+        "\
+error in --config cli option: could not load config key `net.retry`
+
+Caused by:
+  invalid type: string \"two\", expected a non-negative integer",
+    );
+
+    let config = ConfigBuilder::new().env("CARGO_NET_RETRY", "-1").build();
+    assert_error(
+        Retry::new(&config).err().unwrap(),
+        "\
+error in environment variable `CARGO_NET_RETRY`: could not load config key `net.retry`
+
+Caused by:
+  invalid value: integer `-1`, expected a non-negative integer",
+    );
+
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-502-backoff='1s'")
+        .build();
+    assert_error(
+        Retry::new(&config).err().unwrap(),
+        "\
+error in --config cli option: could not load config key `net.retry-502-backoff`
+
+Caused by:
+  invalid type: string \"1s\", expected a number of milliseconds",
+    );
+
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-max-backoff-for.timeout='30s'")
+        .build();
+    assert_error(
+        Retry::new(&config).err().unwrap(),
+        "\
+error in --config cli option: could not load config key `net.retry-max-backoff-for.timeout`
+
+Caused by:
+  invalid type: string \"30s\", expected a number of milliseconds",
+    );
+}
+
+#[cargo_test]
+fn retry_setup_errors_are_told_apart() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-max-backoff='10s'")
+        .build();
+    let err = Retry::new(&config).err().unwrap();
+    assert!(err.is::<RetrySetupError>());
+    assert_eq!(
+        err.to_string(),
+        "error in --config cli option: could not load config key `net.retry-max-backoff`"
+    );
+
+    // Errors of the operation itself aren't.
+    let config = ConfigBuilder::new().build();
+    let err = retry::with_retry(&config, || -> cargo::CargoResult<()> {
+        anyhow::bail!("not found")
+    })
+    .unwrap_err();
+    assert!(!err.is::<RetrySetupError>());
+}
+
+/// Runs a single attempt that fails with HTTP 503.
+fn try_http_503(retry: &mut Retry<'_>) -> RetryResult<()> {
+    retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(http_error_at(503, "https://index.example.com/config.json", &[]).into())
+    })
+}
+
+#[cargo_test]
+fn retry_on_4xx() {
+    assert!(matches!(try_status("[]", 403), RetryResult::Err(_)));
+    assert!(matches!(
+        try_status(r#"["403"]"#, 403),
+        RetryResult::Retry(_)
+    ));
+    assert!(matches!(try_status(r#"["403"]"#, 404), RetryResult::Err(_)));
+}
+
+#[cargo_test]
+fn retry_on_4xx_rejects_other_codes() {
+    let config = ConfigBuilder::new()
+        .config_arg(r#"net.retry-on-4xx=["403", "503"]"#)
+        .build();
+    assert_eq!(
+        Retry::new(&config).err().unwrap().to_string(),
+        "`net.retry-on-4xx` must only contain 4xx HTTP status codes, found `503`"
+    );
+}
+
+/// A project whose git dependency always fails with a spurious error, retried
+/// once and reported at the given `net.retry-warn-level`.
+fn retry_warn_level_project(level: &str) -> cargo_test_support::Project {
+    project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies.bar]
+                git = "http://127.0.0.1:11/foo/bar"
+            "#,
+        )
+        .file("src/main.rs", "")
+        .file(
+            ".cargo/config",
+            &format!(
+                r#"
+                    [net]
+                    retry = 1
+                    retry-warn-level = "{level}"
+                    [http]
+                    timeout = 1
+                "#
+            ),
+        )
+        .build()
+}
+
+#[cargo_test]
+fn retry_warn_level_warn() {
+    let p = retry_warn_level_project("warn");
+
+    p.cargo("check")
+        .with_status(101)
+        .with_stderr_contains("[WARNING] spurious network error (1 tries remaining): [..]")
+        .run();
+}
+
+#[cargo_test]
+fn retry_warn_level_info() {
+    let p = retry_warn_level_project("info");
+
+    p.cargo("check")
+        .with_status(101)
+        .with_stderr_does_not_contain("[..]spurious network error[..]")
+        .run();
+    p.cargo("check -v")
+        .with_status(101)
+        .with_stderr_contains("[NOTE] spurious network error (1 tries remaining): [..]")
+        .run();
+}
+
+#[cargo_test]
+fn retry_warn_level_debug() {
+    let p = retry_warn_level_project("debug");
+
+    p.cargo("check -v")
+        .with_status(101)
+        .with_stderr_does_not_contain("[..]spurious network error[..]")
+        .run();
+    p.cargo("check -vv")
+        .with_status(101)
+        .with_stderr_contains("[NOTE] spurious network error (1 tries remaining): [..]")
+        .run();
+}
+
+/// Number of attempts of an operation that keeps failing with a 503, and
+/// whether `net.retry` was set.
+fn attempts_in(cwd: &str) -> (u32, bool) {
+    let config = ConfigBuilder::new()
+        .cwd(cwd)
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let user_specified = Retry::new(&config).unwrap().retries_user_specified();
+    let (result, stats) = with_retry_stats(&config, || -> cargo::CargoResult<()> {
+        Err(http_error_at(503, "https://index.example.com/config.json", &[]).into())
+    });
+    assert!(result.is_err());
+    (stats.attempts, user_specified)
+}
+
+#[cargo_test]
+fn net_retry_default_from_parent_config() {
+    assert_eq!(attempts_in("."), (3, false));
+
+    write_config_at(
+        ".cargo/config.toml",
+        "
+            [net]
+            retry-default = 4
+        ",
+    );
+    std::fs::create_dir_all(paths::root().join("foo/bar")).unwrap();
+    assert_eq!(attempts_in("foo/bar"), (5, false));
+
+    // An explicit `net.retry` further down wins, even when it is 0.
+    write_config_at(
+        "foo/.cargo/config.toml",
+        "
+            [net]
+            retry = 0
+        ",
+    );
+    assert_eq!(attempts_in("foo/bar"), (1, true));
+}
+
+#[cargo_test]
+fn net_no_retry_overrides_net_retry() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=5")
+        .env("CARGO_NET_NO_RETRY", "1")
+        .build();
+    let (result, stats) = with_retry_stats(&config, || -> cargo::CargoResult<()> {
+        Err(http_error_at(503, "https://index.example.com/config.json", &[]).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(stats.attempts, 1);
+
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=5")
+        .env("CARGO_NET_NO_RETRY", "2")
+        .build();
+    assert_eq!(
+        format!("{:#}", Retry::new(&config).err().unwrap()),
+        "error in environment variable `CARGO_NET_NO_RETRY`: \
+         could not load config key `net.no-retry`: \
+         invalid value: integer `2`, expected true/false or 0/1"
+    );
+}
+
+#[cargo_test(public_network_test)]
+fn net_retry_reports_dns_lookup_time() {
+    // Names under `.invalid` never resolve, so this needs a DNS server to
+    // say so, but doesn't depend on any host being up.
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+
+                [dependencies]
+                bar = { version = "1.0", registry = "unresolvable" }
+            "#,
+        )
+        .file("src/lib.rs", "")
+        .file(
+            ".cargo/config",
+            r#"
+                [registries.unresolvable]
+                index = "sparse+https://index.invalid/"
+            "#,
+        )
+        .build();
+
+    p.cargo("fetch")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .with_status(101)
+        .with_stderr_contains(
+            "[WARNING] spurious network error (2 tries remaining): [6] [..] \
+             (DNS lookup took [..]s)",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn net_retry_profile_overrides_net_retry() {
+    let attempts = |profile: Option<&str>| {
+        let mut config = ConfigBuilder::new()
+            .config_arg("net.retry=1")
+            .config_arg("net.retry-profile.install.retry=4")
+            .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+            .build();
+        if let Some(profile) = profile {
+            config.set_retry_profile(profile);
+        }
+        let (result, stats) = with_retry_stats(&config, || -> cargo::CargoResult<()> {
+            Err(http_error_at(503, "https://index.example.com/config.json", &[]).into())
+        });
+        assert!(result.is_err());
+        stats.attempts
+    };
+    assert_eq!(attempts(None), 2);
+    assert_eq!(attempts(Some("install")), 5);
+    assert_eq!(attempts(Some("publish")), 2);
+}
+
+#[cargo_test]
+fn cargo_install_uses_install_retry_profile() {
+    cargo_process("install --git http://127.0.0.1:11/foo/bar")
+        .arg("--config=net.retry=0")
+        .arg("--config=net.retry-profile.install.retry=1")
+        .arg("--config=http.timeout=1")
+        .with_status(101)
+        .with_stderr_contains("[WARNING] spurious network error (1 tries remaining): [..]")
         .run();
 }
+
+#[cargo_test]
+fn http_multiplexing_can_be_turned_off() {
+    let config = ConfigBuilder::new().build();
+    assert_eq!(config.http_config().unwrap().multiplexing, None);
+
+    let config = ConfigBuilder::new()
+        .config_arg("http.multiplexing=false")
+        .build();
+    assert_eq!(config.http_config().unwrap().multiplexing, Some(false));
+
+    let config = ConfigBuilder::new()
+        .env("CARGO_HTTP_MULTIPLEXING", "false")
+        .build();
+    assert_eq!(config.http_config().unwrap().multiplexing, Some(false));
+}
+
+#[cargo_test]
+fn http2_stream_errors_are_retried_on_a_new_connection() {
+    let config = ConfigBuilder::new().build();
+    let mut retry = Retry::new(&config).unwrap();
+    let result = retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(curl::Error::new(curl_sys::CURLE_HTTP2_STREAM).into())
+    });
+    assert!(matches!(result, RetryResult::Retry(_)));
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=true"]);
+}
+
+#[cargo_test]
+fn failed_connections_are_retried_over_ipv4() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-ipv4-fallback=true")
+        .build();
+    let mut retry = Retry::new(&config).unwrap();
+    let result = retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(curl::Error::new(curl_sys::CURLE_COULDNT_CONNECT).into())
+    });
+    assert!(matches!(result, RetryResult::Retry(_)));
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=false", "ip_resolve=V4"]);
+}
+
+#[cargo_test]
+fn repeated_http2_stream_errors_are_retried_over_http1() {
+    let config = ConfigBuilder::new().build();
+    let mut retry = Retry::new(&config).unwrap();
+    for _ in 0..2 {
+        let result = retry.r#try(|| -> cargo::CargoResult<()> {
+            Err(curl::Error::new(curl_sys::CURLE_HTTP2_STREAM).into())
+        });
+        assert!(matches!(result, RetryResult::Retry(_)));
+    }
+    let mut handle = RecordingHandle::default();
+    retry.configure_handle(&mut handle, 0).unwrap();
+    assert_eq!(handle.0, ["fresh_connect=true", "http_version=V11"]);
+}
+
+#[cargo_test]
+fn network_deadline_stops_retries() {
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies.bar]
+                git = "http://127.0.0.1:11/foo/bar"
+            "#,
+        )
+        .file("src/main.rs", "")
+        .build();
+
+    p.cargo("check --network-deadline 0s")
+        .with_status(101)
+        .with_stderr_does_not_contain("[WARNING] spurious network error[..]")
+        .with_stderr_contains(
+            "[..]not retrying, the deadline set with `--network-deadline` has passed",
+        )
+        .run();
+
+    p.cargo("check --network-deadline soon")
+        .with_status(101)
+        .with_stderr(
+            "[ERROR] invalid value `soon` for `--network-deadline`, \
+             expected a number of seconds such as `90s`, or minutes such as `2m`",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn retry_verbose_logs_classification() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-verbose=true")
+        .build();
+    let mut retry = Retry::new(&config).unwrap();
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    let result = retry.r#try(|| -> cargo::CargoResult<()> {
+        Err(http_error_at(404, "https://index.example.com/config.json", &[]).into())
+    });
+    assert!(matches!(result, RetryResult::Err(_)));
+    drop(retry);
+    assert_eq!(
+        read_output(config),
+        "note: classified as spurious: 503 (built-in rules)\n\
+         warning: spurious network error (2 tries remaining): \
+         failed to get successful HTTP response: 503 on GET \
+         `https://index.example.com/config.json`\n\
+         body:\n\n\
+         note: classified as not spurious: 404 (built-in rules)\n"
+    );
+}
+
+#[cargo_test]
+fn retry_flag_overrides_config() {
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies.bar]
+                git = "http://127.0.0.1:11/foo/bar"
+            "#,
+        )
+        .file("src/main.rs", "")
+        .file(
+            ".cargo/config",
+            r#"
+           [net]
+           retry=1
+           [http]
+           timeout=1
+            "#,
+        )
+        .build();
+
+    p.cargo("fetch --retry 3")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .with_status(101)
+        .with_stderr_contains("[WARNING] spurious network error (3 tries remaining): [..]")
+        .with_stderr_contains("[WARNING] spurious network error (1 tries remaining): [..]")
+        .run();
+
+    p.cargo("fetch --retry=-1")
+        .with_status(1)
+        .with_stderr_contains("error: invalid value '-1' for '--retry <N>'[..]")
+        .run();
+}
+
+/// A [`Clock`] that only moves when told to.
+struct ManualClock(Cell<Instant>);
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+}
+
+#[cargo_test]
+fn retry_warnings_escalate_after_a_while() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=3")
+        .config_arg("net.retry-warn-level='info'")
+        .build();
+    let clock = Rc::new(ManualClock(Cell::new(Instant::now())));
+    let mut retry = RetryBuilder::new(&config)
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    clock.0.set(clock.0.get() + Duration::from_secs(10));
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    clock.0.set(clock.0.get() + Duration::from_secs(21));
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    drop(retry);
+    assert_eq!(
+        read_output(config),
+        "warning: still failing after 31s: spurious network error (1 tries remaining): \
+         failed to get successful HTTP response: 503 on GET \
+         `https://index.example.com/config.json`\n\
+         body:\n\n"
+    );
+}
+
+#[cargo_test]
+fn identical_retry_warnings_are_suppressed() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-dedup-window=60000")
+        .build();
+    let clock = Rc::new(ManualClock(Cell::new(Instant::now())));
+    let retry_once = || {
+        let mut retry = RetryBuilder::new(&config)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    };
+    for _ in 0..4 {
+        retry_once();
+    }
+    clock.0.set(clock.0.get() + Duration::from_secs(61));
+    for _ in 0..3 {
+        retry_once();
+    }
+    retry::print_session_summary(&config).unwrap();
+    let warning = "warning: spurious network error (2 tries remaining): \
+                   failed to get successful HTTP response: 503 on GET \
+                   `https://index.example.com/config.json`\n\
+                   body:\n\n";
+    assert_eq!(
+        read_output(config),
+        format!(
+            "{warning}\
+             warning: 3 more retry warnings for `index.example.com` (503) suppressed\n\
+             {warning}\
+             warning: 2 more retry warnings for `index.example.com` (503) suppressed\n"
+        )
+    );
+}
+
+#[cargo_test]
+fn escalated_retry_warnings_are_not_suppressed() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=3")
+        .config_arg("net.retry-dedup-window=60000")
+        .build();
+    let clock = Rc::new(ManualClock(Cell::new(Instant::now())));
+    let mut retry = RetryBuilder::new(&config)
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    clock.0.set(clock.0.get() + Duration::from_secs(31));
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    clock.0.set(clock.0.get() + Duration::from_secs(5));
+    assert!(matches!(try_http_503(&mut retry), RetryResult::Retry(_)));
+    drop(retry);
+    let error = "failed to get successful HTTP response: 503 on GET \
+                 `https://index.example.com/config.json`\n\
+                 body:\n\n";
+    assert_eq!(
+        read_output(config),
+        format!(
+            "warning: spurious network error (3 tries remaining): {error}\
+             warning: still failing after 31s: spurious network error (2 tries remaining): {error}\
+             warning: still failing after 36s: spurious network error (1 tries remaining): {error}"
+        )
+    );
+}
+
+#[cargo_test]
+fn download_with_retry_from_flaky_server() {
+    let failures = Mutex::new(0);
+    let registry = RegistryBuilder::new()
+        .http_api()
+        .add_responder("/small.txt", move |_, _| {
+            let mut failures = failures.lock().unwrap();
+            if *failures < 2 {
+                *failures += 1;
+                Response {
+                    code: 503,
+                    headers: vec![],
+                    body: b"try again".to_vec(),
+                }
+            } else {
+                Response {
+                    code: 200,
+                    headers: vec![],
+                    body: b"hello".to_vec(),
+                }
+            }
+        })
+        .add_responder("/down.txt", |_, _| Response {
+            code: 503,
+            headers: vec![],
+            body: b"try again".to_vec(),
+        })
+        .add_responder("/missing.txt", |req, server| server.not_found(req))
+        .build();
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-max-backoff=10")
+        .build();
+    let url = |path| registry.api_url().join(path).unwrap().to_string();
+    let dest = paths::root().join("small.txt");
+
+    // Two 503s are within the default two retries.
+    download::download_with_retry(&config, &url("small.txt"), &dest).unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+
+    // A failed check keeps the file from being written.
+    let checked = paths::root().join("checked.txt");
+    let err = download::download_with_retry_checked(&config, &url("small.txt"), &checked, |data| {
+        anyhow::ensure!(data == b"bye", "checksum mismatch");
+        Ok(())
+    })
+    .unwrap_err();
+    assert_eq!(err.root_cause().to_string(), "checksum mismatch");
+    assert!(!checked.exists());
+
+    let err = download::download_with_retry(&config, &url("down.txt"), &dest).unwrap_err();
+    assert_eq!(err.downcast_ref::<RetriesExhausted>().unwrap().attempts, 3);
+    assert_eq!(
+        err.root_cause()
+            .downcast_ref::<HttpNotSuccessful>()
+            .unwrap()
+            .code,
+        503
+    );
+
+    let err = download::download_with_retry(&config, &url("missing.txt"), &dest).unwrap_err();
+    assert!(err.downcast_ref::<RetriesExhausted>().is_none());
+    assert_eq!(
+        err.root_cause()
+            .downcast_ref::<HttpNotSuccessful>()
+            .unwrap()
+            .code,
+        404
+    );
+}
+
+#[cargo_test]
+fn download_with_retry_follows_the_url_rewrite() {
+    let registry = RegistryBuilder::new()
+        .http_api()
+        .add_responder("/blue/file.txt", |_, _| Response {
+            code: 503,
+            headers: vec![],
+            body: b"being replaced".to_vec(),
+        })
+        .add_responder("/green/file.txt", |_, _| Response {
+            code: 200,
+            headers: vec![],
+            body: b"hello from green".to_vec(),
+        })
+        .build();
+    let mut config = ConfigBuilder::new()
+        .config_arg("net.retry-max-backoff=10")
+        .build();
+    let attempts = Rc::new(RefCell::new(Vec::new()));
+    let seen = Rc::clone(&attempts);
+    config.set_retry_url_rewrite(Rc::new(move |url: &str, attempt| {
+        seen.borrow_mut().push((url.to_string(), attempt));
+        url.replace("/blue/", "/green/")
+    }));
+    let url = registry
+        .api_url()
+        .join("blue/file.txt")
+        .unwrap()
+        .to_string();
+    let dest = paths::root().join("file.txt");
+
+    download::download_with_retry(&config, &url, &dest).unwrap();
+    assert_eq!(std::fs::read(&dest).unwrap(), b"hello from green");
+    // The first attempt went to `/blue/`, the retry to `/green/`.
+    assert_eq!(*attempts.borrow(), [(url, 2)]);
+}
+
+#[cargo_test]
+fn with_retry_trace_records_each_attempt() {
+    let config = ConfigBuilder::new()
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let mut attempt = 0;
+    let (result, trace) = with_retry_trace(&config, || {
+        attempt += 1;
+        if attempt == 3 {
+            return Ok(attempt);
+        }
+        Err(curl::Error::new(match attempt {
+            1 => curl_sys::CURLE_COULDNT_CONNECT,
+            _ => curl_sys::CURLE_OPERATION_TIMEDOUT,
+        })
+        .into())
+    });
+    assert_eq!(result.unwrap(), 3);
+    assert_eq!(
+        trace.attempts,
+        [
+            AttemptOutcome::Err(Some(SpuriousReason::ConnectFailed)),
+            AttemptOutcome::Err(Some(SpuriousReason::Timeout)),
+            AttemptOutcome::Ok,
+        ]
+    );
+    assert_eq!(trace.stats().attempts, 3);
+    assert_eq!(
+        trace.to_string(),
+        "succeeded on attempt 3 after connect, timeout"
+    );
+}
+
+#[cargo_test]
+fn with_retry_resumable_retries_from_the_failed_step() {
+    let config = ConfigBuilder::new()
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let mut calls = Vec::new();
+    let mut failed = false;
+    let pages = with_retry_resumable(&config, (1, Vec::new()), |(page, pages)| {
+        calls.push(*page);
+        if *page == 2 && !failed {
+            failed = true;
+            return Err(curl::Error::new(curl_sys::CURLE_RECV_ERROR).into());
+        }
+        let mut pages = pages.clone();
+        pages.push(format!("page {page}"));
+        Ok(match page {
+            3 => Step::Done(pages),
+            _ => Step::Continue((page + 1, pages)),
+        })
+    })
+    .unwrap();
+    assert_eq!(pages, ["page 1", "page 2", "page 3"]);
+    // Only the step that failed was made again.
+    assert_eq!(calls, [1, 2, 2, 3]);
+}
+
+#[cargo_test]
+fn with_retry_fallback_is_used_once_retries_run_out() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=1")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let status = |code| {
+        move || -> cargo::CargoResult<&str> {
+            Err(http_error_at(code, "https://index.example.com/3/b/bar", &[]).into())
+        }
+    };
+
+    let entry = with_retry_fallback(&config, status(503), |e| {
+        assert_eq!(e.downcast_ref::<HttpNotSuccessful>().unwrap().code, 503);
+        Some("cached")
+    })
+    .unwrap();
+    assert_eq!(entry, "cached");
+
+    // Errors that aren't retried don't fall back.
+    let err = with_retry_fallback(&config, status(404), |_| -> Option<&str> {
+        panic!("not spurious")
+    })
+    .unwrap_err();
+    assert_eq!(err.downcast_ref::<HttpNotSuccessful>().unwrap().code, 404);
+
+    assert_eq!(
+        read_output(config),
+        "\
+warning: spurious network error (1 tries remaining): failed to get successful HTTP response: \
+503 on GET `https://index.example.com/3/b/bar`
+body:
+
+warning: gave up after 2 attempts, continuing with a result that may be out of date: \
+failed to get successful HTTP response: 503 on GET `https://index.example.com/3/b/bar`
+body:
+
+"
+    );
+}
+
+#[cargo_test]
+fn try_with_context_names_what_was_retried() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=1")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let mut retry = Retry::new(&config).unwrap();
+    let result = loop {
+        let fetch = || -> cargo::CargoResult<()> {
+            Err(http_error_at(503, "https://index.example.com/config.json", &[]).into())
+        };
+        match retry.try_with_context(fetch, "fetching index for crates-io") {
+            RetryResult::Success(()) => unreachable!(),
+            RetryResult::Err(e) => break e,
+            RetryResult::Retry(delay) => retry.sleep(delay),
+        }
+    };
+    assert_eq!(
+        result.downcast_ref::<HttpNotSuccessful>().unwrap().code,
+        503
+    );
+    assert_eq!(
+        read_output(config),
+        "\
+warning: spurious network error (1 tries remaining) while fetching index for crates-io: \
+failed to get successful HTTP response: 503 on GET \
+`https://index.example.com/config.json`
+body:
+
+"
+    );
+}
+
+#[cargo_test]
+fn with_retry_all_errors_lists_every_attempt() {
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry=4")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let mut attempt = 0;
+    let err = with_retry_all_errors(&config, || -> cargo::CargoResult<()> {
+        attempt += 1;
+        Err(curl::Error::new(match attempt {
+            1 => curl_sys::CURLE_COULDNT_CONNECT,
+            2..=4 => curl_sys::CURLE_RECV_ERROR,
+            _ => curl_sys::CURLE_OPERATION_TIMEDOUT,
+        })
+        .into())
+    })
+    .unwrap_err();
+    // The messages come from the curl in use.
+    let message = |code| curl::Error::new(code).to_string();
+    assert_eq!(
+        err.downcast_ref::<RetryErrors>().unwrap().to_string(),
+        format!(
+            "all 5 attempts failed:\n  attempt 1: {}\n  attempt 2–4: {}\n  attempt 5: {}",
+            message(curl_sys::CURLE_COULDNT_CONNECT),
+            message(curl_sys::CURLE_RECV_ERROR),
+            message(curl_sys::CURLE_OPERATION_TIMEDOUT),
+        )
+    );
+    assert!(err
+        .downcast_ref::<curl::Error>()
+        .unwrap()
+        .is_operation_timedout());
+}
+
+#[cargo_test]
+fn with_retry_provenance_tells_cache_hits_apart() {
+    let config = ConfigBuilder::new()
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .build();
+    let (result, provenance) = with_retry_provenance(
+        &config,
+        || Ok(Some("cached")),
+        || -> cargo::CargoResult<&str> { panic!("the cache had it") },
+    )
+    .unwrap();
+    assert_eq!(result.unwrap(), "cached");
+    assert_eq!(
+        provenance,
+        Provenance {
+            from_cache: true,
+            attempts: 0
+        }
+    );
+
+    let mut attempt = 0;
+    let (result, provenance) = with_retry_provenance(
+        &config,
+        || Ok(None),
+        || {
+            attempt += 1;
+            if attempt == 1 {
+                return Err(curl::Error::new(curl_sys::CURLE_RECV_ERROR).into());
+            }
+            Ok("fetched")
+        },
+    )
+    .unwrap();
+    assert_eq!(result.unwrap(), "fetched");
+    assert_eq!(
+        provenance,
+        Provenance {
+            from_cache: false,
+            attempts: 2
+        }
+    );
+
+    // A broken cache is an error of its own, with nothing fetched.
+    let err = with_retry_provenance(
+        &config,
+        || -> cargo::CargoResult<Option<&str>> { anyhow::bail!("cache is corrupt") },
+        || -> cargo::CargoResult<&str> { panic!("the cache failed") },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "cache is corrupt");
+}
+
+#[cargo_test]
+fn download_with_retry_cached_checks_the_cached_copy() {
+    let requests = Arc::new(Mutex::new(0));
+    let seen = Arc::clone(&requests);
+    let registry = RegistryBuilder::new()
+        .http_api()
+        .add_responder("/file.txt", move |_, _| {
+            let mut requests = seen.lock().unwrap();
+            *requests += 1;
+            if *requests % 2 == 1 {
+                Response {
+                    code: 503,
+                    headers: vec![],
+                    body: b"try again".to_vec(),
+                }
+            } else {
+                Response {
+                    code: 200,
+                    headers: vec![],
+                    body: b"hello".to_vec(),
+                }
+            }
+        })
+        .build();
+    let config = ConfigBuilder::new()
+        .config_arg("net.retry-max-backoff=10")
+        .build();
+    let url = registry.api_url().join("file.txt").unwrap().to_string();
+    let dest = paths::root().join("file.txt");
+    let verify = |data: &[u8]| {
+        anyhow::ensure!(data == b"hello", "checksum mismatch");
+        Ok(())
+    };
+
+    // Nothing is cached yet, so it is downloaded, after one retry.
+    let provenance = download::download_with_retry_cached(&config, &url, &dest, verify).unwrap();
+    assert_eq!(
+        provenance,
+        Provenance {
+            from_cache: false,
+            attempts: 2
+        }
+    );
+    assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+
+    // The copy that was just downloaded is used as is.
+    let provenance = download::download_with_retry_cached(&config, &url, &dest, verify).unwrap();
+    assert_eq!(
+        provenance,
+        Provenance {
+            from_cache: true,
+            attempts: 0
+        }
+    );
+    assert_eq!(*requests.lock().unwrap(), 2);
+
+    // A truncated copy is downloaded again.
+    std::fs::write(&dest, b"hel").unwrap();
+    let provenance = download::download_with_retry_cached(&config, &url, &dest, verify).unwrap();
+    assert_eq!(
+        provenance,
+        Provenance {
+            from_cache: false,
+            attempts: 2
+        }
+    );
+    assert_eq!(std::fs::read(&dest).unwrap(), b"hello");
+    assert_eq!(*requests.lock().unwrap(), 4);
+}
+
+#[cargo_test]
+fn net_retry_is_capped_at_the_ceiling() {
+    let retries = |args: &[&str]| {
+        let mut builder = ConfigBuilder::new();
+        for arg in args {
+            builder.config_arg(*arg);
+        }
+        let config = builder.build();
+        let retries = Retry::new(&config).unwrap().remaining();
+        // The warning is only shown once.
+        Retry::new(&config).unwrap();
+        (retries, read_output(config))
+    };
+
+    assert_eq!(retries(&["net.retry=9"]), (9, String::new()));
+    assert_eq!(retries(&["net.retry=10"]), (10, String::new()));
+    assert_eq!(
+        retries(&["net.retry=9999"]),
+        (
+            10,
+            "\
+warning: 9999 network retries were asked for, more than the `net.retry-ceiling` of 10, \
+so only 10 are made
+to keep retrying through a long outage, limit how long with `--network-deadline` instead, \
+or raise `net.retry-ceiling`
+"
+            .to_string()
+        )
+    );
+
+    // The ceiling can be raised.
+    assert_eq!(
+        retries(&["net.retry=20", "net.retry-ceiling=50"]),
+        (20, String::new())
+    );
+    assert_eq!(retries(&["net.retry=20", "net.retry-ceiling=15"]).0, 15);
+}
+
+#[cargo_test]
+fn lowering_net_retry_when_locked_is_warned_about() {
+    let retries = |args: &[&str]| {
+        let mut builder = ConfigBuilder::new();
+        builder.locked();
+        for arg in args {
+            builder.config_arg(*arg);
+        }
+        let config = builder.build();
+        let retries = Retry::new(&config).unwrap().remaining();
+        // The warning is only shown once.
+        Retry::new(&config).unwrap();
+        (retries, read_output(config))
+    };
+
+    // The default isn't something the user asked for.
+    assert_eq!(retries(&[]), (1, String::new()));
+    assert_eq!(retries(&["net.retry=1"]), (1, String::new()));
+    assert_eq!(
+        retries(&["net.retry=5"]),
+        (
+            1,
+            "\
+warning: 5 network retries were asked for, but only 1 are made with `--locked` or `--frozen`
+to retry more, pass `--retry` or raise `net.retry-locked`
+"
+            .to_string()
+        )
+    );
+    assert_eq!(
+        retries(&["net.retry=5", "net.retry-locked=5"]),
+        (5, String::new())
+    );
+}
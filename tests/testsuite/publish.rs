@@ -1856,7 +1856,7 @@ fn api_error_code() {
 [ERROR] failed to publish to registry at http://127.0.0.1:[..]/
 
 Caused by:
-  failed to get a 200 OK response, got 400
+  failed to get successful HTTP response: 400 on PUT `http://127.0.0.1:[..]/api/v1/crates/new`
   headers:
   <tab>HTTP/1.1 400
   <tab>Content-Length: 7
@@ -4,7 +4,7 @@ use cargo::core::SourceId;
 use cargo_test_support::cargo_process;
 use cargo_test_support::paths::{self, CargoPathExt};
 use cargo_test_support::registry::{
-    self, registry_path, Dependency, Package, RegistryBuilder, TestRegistry,
+    self, registry_path, Dependency, Package, RegistryBuilder, Response, TestRegistry,
 };
 use cargo_test_support::{basic_manifest, project};
 use cargo_test_support::{git, install::cargo_home, t};
@@ -2741,10 +2741,14 @@ fn sparse_retry() {
         .with_stderr(
             "\
 [UPDATING] `dummy-registry` index
-warning: spurious network error (2 tries remaining): failed to get successful HTTP response from `[..]`, got 500
+warning: spurious network error (2 tries remaining): failed to get successful HTTP response: 500 on GET `[..]`
+headers:
+<tab>Content-Length: 21
 body:
 internal server error
-warning: spurious network error (1 tries remaining): failed to get successful HTTP response from `[..]`, got 500
+warning: spurious network error (1 tries remaining): failed to get successful HTTP response: 500 on GET `[..]`
+headers:
+<tab>Content-Length: 21
 body:
 internal server error
 [DOWNLOADING] crates ...
@@ -2757,6 +2761,291 @@ internal server error
         .run();
 }
 
+#[cargo_test]
+fn sparse_retry_strict_network() {
+    let failed = Mutex::new(false);
+    let _registry = RegistryBuilder::new()
+        .http_index()
+        .add_responder("/index/3/b/bar", move |req, server| {
+            let mut failed = failed.lock().unwrap();
+            if !*failed {
+                *failed = true;
+                server.internal_server_error(req)
+            } else {
+                server.index(req)
+            }
+        })
+        .build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies]
+                bar = ">= 0.0.0"
+            "#,
+        )
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    Package::new("bar", "0.0.1").publish();
+
+    // The retry still goes ahead, but the command fails once it's done.
+    p.cargo("check --strict-network")
+        .with_status(101)
+        .with_stderr(
+            "\
+[UPDATING] `dummy-registry` index
+warning: spurious network error (2 tries remaining): failed to get successful HTTP response: 500 on GET `[..]`
+headers:
+<tab>Content-Length: 21
+body:
+internal server error
+[DOWNLOADING] crates ...
+[DOWNLOADED] bar v0.0.1 (registry `dummy-registry`)
+[CHECKING] bar v0.0.1
+[CHECKING] foo v0.0.1 ([CWD])
+[FINISHED] dev [unoptimized + debuginfo] target(s) in [..]s
+[ERROR] network operations were retried, which isn't allowed with `--strict-network` or `net.retry-strict`
+
+Caused by:
+  retries: 1 (500×1)
+",
+        )
+        .run();
+
+    // Nothing is retried the second time around.
+    p.cargo("check --config net.retry-strict=true")
+        .with_stderr("[FINISHED] dev [..]")
+        .run();
+}
+
+#[cargo_test]
+fn batched_retry_mode() {
+    let failed = Mutex::new(false);
+    let _registry = RegistryBuilder::new()
+        .http_index()
+        .add_responder("/dl/bar/0.0.1/download", move |req, server| {
+            let mut failed = failed.lock().unwrap();
+            if !*failed {
+                *failed = true;
+                server.internal_server_error(req)
+            } else {
+                server.dl(req)
+            }
+        })
+        .build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies]
+                bar = "0.0.1"
+                baz = "0.0.1"
+            "#,
+        )
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    Package::new("bar", "0.0.1").publish();
+    Package::new("baz", "0.0.1").publish();
+
+    p.cargo("fetch")
+        .env("CARGO_NET_RETRY_MODE", "batched")
+        .env("__CARGO_TEST_FIXED_RETRY_SLEEP_MS", "1")
+        .with_stderr_unordered(
+            "\
+[UPDATING] `dummy-registry` index
+[DOWNLOADING] crates ...
+warning: spurious network error (2 tries remaining): failed to get successful HTTP response: \
+500 on GET `[..]/dl/bar/0.0.1/download`
+headers:
+<tab>HTTP/1.1 500
+<tab>Content-Length: 21
+body:
+internal server error
+[DOWNLOADED] bar v0.0.1 (registry `dummy-registry`)
+[DOWNLOADED] baz v0.0.1 (registry `dummy-registry`)
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn sparse_retry_bad_not_modified() {
+    let requests = Mutex::new(0);
+    let _registry = RegistryBuilder::new()
+        .http_index()
+        .add_responder("/index/3/b/bar", move |req, server| {
+            let mut requests = requests.lock().unwrap();
+            *requests += 1;
+            if *requests == 1 {
+                // A cache answering for a file it doesn't have.
+                assert_eq!(req.if_none_match, None);
+                assert_eq!(req.cache_control, None);
+                Response {
+                    code: 304,
+                    headers: vec![],
+                    body: vec![],
+                }
+            } else {
+                assert_eq!(req.cache_control.as_deref(), Some("no-cache"));
+                server.index(req)
+            }
+        })
+        .build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies]
+                bar = ">= 0.0.0"
+            "#,
+        )
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    Package::new("bar", "0.0.1").publish();
+
+    p.cargo("check")
+        .with_stderr(
+            "\
+[UPDATING] `dummy-registry` index
+warning: spurious network error (2 tries remaining): failed to get successful HTTP response: 304 on GET `[..]`
+headers:
+<tab>Content-Length: 0
+body:
+
+[DOWNLOADING] crates ...
+[DOWNLOADED] bar v0.0.1 (registry `dummy-registry`)
+[CHECKING] bar v0.0.1
+[CHECKING] foo v0.0.1 ([CWD])
+[FINISHED] dev [unoptimized + debuginfo] target(s) in [..]s
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn sparse_error_after_redirect() {
+    let _registry = RegistryBuilder::new()
+        .http_index()
+        .add_responder("/index/3/b/bar", |_, _| Response {
+            code: 302,
+            headers: vec!["Location: /index/moved/bar?signature=secret".to_string()],
+            body: vec![],
+        })
+        .add_responder("/index/moved/bar", |_, _| Response {
+            code: 403,
+            headers: vec![],
+            body: b"denied".to_vec(),
+        })
+        .build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies]
+                bar = ">= 0.0.0"
+            "#,
+        )
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    p.cargo("check")
+        .with_status(101)
+        .with_stderr(
+            "\
+[UPDATING] `dummy-registry` index
+[ERROR] failed to get `bar` as a dependency of package `foo v0.0.1 ([ROOT]/foo)`
+
+Caused by:
+  failed to query replaced source registry `crates-io`
+
+Caused by:
+  download of 3/b/bar failed
+
+Caused by:
+  failed to get successful HTTP response: 403 on GET `http://127.0.0.1:[..]/index/moved/bar?[REDACTED]`, \
+redirected from `http://127.0.0.1:[..]/index/3/b/bar`
+  headers:
+  <tab>Content-Length: 6
+  body:
+  denied
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn sparse_warning_header() {
+    let _registry = RegistryBuilder::new()
+        .http_index()
+        .add_responder("/index/3/b/bar", move |req, server| {
+            let mut response = server.index(req);
+            response
+                .headers
+                .push(r#"Warning: 299 - "this index endpoint is deprecated""#.to_string());
+            response
+        })
+        .build();
+
+    let p = project()
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "foo"
+                version = "0.0.1"
+                authors = []
+
+                [dependencies]
+                bar = ">= 0.0.0"
+            "#,
+        )
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    Package::new("bar", "0.0.1").publish();
+
+    p.cargo("check")
+        .with_stderr(
+            "\
+[UPDATING] `dummy-registry` index
+warning: `http://127.0.0.1:[..]/index/3/b/bar` responded with a warning: this index endpoint is deprecated
+[DOWNLOADING] crates ...
+[DOWNLOADED] bar v0.0.1 (registry `dummy-registry`)
+[CHECKING] bar v0.0.1
+[CHECKING] foo v0.0.1 ([CWD])
+[FINISHED] dev [unoptimized + debuginfo] target(s) in [..]s
+",
+        )
+        .run();
+}
+
 #[cargo_test]
 fn deleted_entry() {
     // Checks the behavior when a package is removed from the index.
@@ -57,7 +57,11 @@ fn requires_nightly() {
 error: failed to download from `[..]/dl/bar/0.0.1/download`
 
 Caused by:
-  failed to get successful HTTP response from `[..]`, got 401
+  failed to get successful HTTP response: 401 on GET `[..]`
+  headers:
+  <tab>HTTP/1.1 401
+  <tab>Content-Length: 33
+  <tab>WWW-Authenticate: Cargo login_url="https://test-registry-login/me"
   body:
   Unauthorized message from server.
 "#,
@@ -388,7 +392,10 @@ Caused by:
   or use environment variable CARGO_REGISTRIES_ALTERNATIVE_TOKEN
 
 Caused by:
-  failed to get successful HTTP response from `http://[..]/index/config.json`, got 401
+  failed to get successful HTTP response: 401 on GET `http://[..]/index/config.json`
+  headers:
+  <tab>Content-Length: 33
+  <tab>WWW-Authenticate: Cargo login_url=\"https://test-registry-login/me\"
   body:
   Unauthorized message from server.",
         )
@@ -415,7 +422,11 @@ fn incorrect_token_git() {
 [ERROR] failed to download from `http://[..]/dl/bar/0.0.1/download`
 
 Caused by:
-  failed to get successful HTTP response from `http://[..]/dl/bar/0.0.1/download`, got 401
+  failed to get successful HTTP response: 401 on GET `http://[..]/dl/bar/0.0.1/download`
+  headers:
+  <tab>HTTP/1.1 401
+  <tab>Content-Length: 33
+  <tab>WWW-Authenticate: Cargo login_url=\"https://test-registry-login/me\"
   body:
   Unauthorized message from server.",
         )
@@ -0,0 +1,69 @@
+//! Tests for the `cargo simulate-retry` command.
+
+use cargo_test_support::cargo_process;
+
+#[cargo_test]
+fn gated() {
+    cargo_process("simulate-retry --errors ok")
+        .masquerade_as_nightly_cargo(&["simulate-retry"])
+        .with_status(101)
+        .with_stderr(
+            "\
+error: the `cargo simulate-retry` command is unstable, pass `-Z unstable-options` to enable it
+See https://github.com/rust-lang/cargo/issues/177 for more information about the `cargo simulate-retry` command.",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn scripted_errors_then_success() {
+    cargo_process("-Zunstable-options simulate-retry --errors 503,timeout,ok --seed 1")
+        .masquerade_as_nightly_cargo(&["simulate-retry"])
+        // The seeded delays, rather than the ones fixed for tests.
+        .env_remove("__CARGO_TEST_FIXED_RETRY_SLEEP_MS")
+        .with_stdout(
+            "\
+attempt 1: 503, retrying in 456ms (503)
+attempt 2: timeout, retrying in 987ms (timeout)
+attempt 3: ok
+succeeded after 3 attempt(s)
+",
+        )
+        .with_stderr(
+            "\
+warning: spurious network error (2 tries remaining), retrying simulated operation: \
+failed to get successful HTTP response: 503 on GET `https://example.com/`
+body:
+
+warning: spurious network error (1 tries remaining), retrying simulated operation: \
+[28] Timeout was reached
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn errors_that_are_not_retried() {
+    cargo_process("-Zunstable-options simulate-retry --errors 503,404,ok --seed 1")
+        .masquerade_as_nightly_cargo(&["simulate-retry"])
+        // The seeded delays, rather than the ones fixed for tests.
+        .env_remove("__CARGO_TEST_FIXED_RETRY_SLEEP_MS")
+        .with_status(101)
+        .with_stdout(
+            "\
+attempt 1: 503, retrying in 456ms (503)
+attempt 2: 404, not retried
+",
+        )
+        .with_stderr_contains("error: failed after 2 attempt(s)")
+        .run();
+
+    cargo_process("-Zunstable-options simulate-retry --errors 503,nope")
+        .masquerade_as_nightly_cargo(&["simulate-retry"])
+        .with_status(101)
+        .with_stderr(
+            "error: unknown outcome `nope`, expected `ok`, an HTTP status code, or one of \
+             timeout, connect, dns, send, recv, http2, ssl, partial",
+        )
+        .run();
+}
@@ -1,10 +1,11 @@
 //! Tests for the `cargo yank` command.
 
 use std::fs;
+use std::sync::Mutex;
 
 use cargo_test_support::paths::CargoPathExt;
-use cargo_test_support::project;
-use cargo_test_support::registry;
+use cargo_test_support::registry::{self, Response};
+use cargo_test_support::{basic_manifest, project};
 
 fn setup(name: &str, version: &str) {
     let dir = registry::api_path().join(format!("api/v1/crates/{}/{}", name, version));
@@ -200,3 +201,82 @@ fn inline_and_explicit_version() {
         .with_stderr("error: cannot specify both `@0.0.1` and `--version`")
         .run();
 }
+
+/// A registry whose API fails the first request to `path` with a 503.
+fn flaky_api(path: &'static str) -> registry::TestRegistry {
+    let failed = Mutex::new(false);
+    registry::RegistryBuilder::new()
+        .http_api()
+        .add_responder(path, move |req, server| {
+            let mut failed = failed.lock().unwrap();
+            if *failed {
+                server.ok(req)
+            } else {
+                *failed = true;
+                Response {
+                    code: 503,
+                    headers: vec![],
+                    body: b"service unavailable".to_vec(),
+                }
+            }
+        })
+        .build()
+}
+
+#[cargo_test]
+fn yank_retries_spurious_errors() {
+    let registry = flaky_api("/api/v1/crates/foo/0.0.1/yank");
+
+    let p = project()
+        .file("Cargo.toml", &basic_manifest("foo", "0.0.1"))
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    p.cargo("yank --version 0.0.1")
+        .replace_crates_io(registry.index_url())
+        .with_stderr(
+            "\
+[UPDATING] crates.io index
+        Yank foo@0.0.1
+warning: spurious network error (2 tries remaining), retrying yank of foo v0.0.1: \
+failed to get successful HTTP response: \
+503 on DELETE `http://127.0.0.1:[..]/api/v1/crates/foo/0.0.1/yank`
+headers:
+<tab>HTTP/1.1 503
+<tab>Content-Length: 19
+<tab>
+body:
+service unavailable
+",
+        )
+        .run();
+}
+
+#[cargo_test]
+fn unyank_retries_spurious_errors() {
+    let registry = flaky_api("/api/v1/crates/foo/0.0.1/unyank");
+
+    let p = project()
+        .file("Cargo.toml", &basic_manifest("foo", "0.0.1"))
+        .file("src/main.rs", "fn main() {}")
+        .build();
+
+    p.cargo("yank --undo --version 0.0.1")
+        .replace_crates_io(registry.index_url())
+        .with_stderr(
+            "\
+[UPDATING] crates.io index
+      Unyank foo@0.0.1
+warning: spurious network error (2 tries remaining), retrying unyank of foo v0.0.1: \
+failed to get successful HTTP response: \
+503 on PUT `http://127.0.0.1:[..]/api/v1/crates/foo/0.0.1/unyank`
+headers:
+<tab>HTTP/1.1 503
+<tab>Content-Length: 19
+<tab>
+body:
+service unavailable
+",
+        )
+        .run();
+}